@@ -2,7 +2,7 @@ use std::env;
 
 use google_generative_ai_rs::v1::{
     api::Client,
-    gemini::{request::Request, Content, Part, ResponseType, Role},
+    gemini::{request::Request, Content, Part, Role},
 };
 use log::info;
 
@@ -17,19 +17,14 @@ use log::info;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let client = Client::new_from_response_type(
-        ResponseType::CountTokens,
-        env::var("API_KEY").unwrap().to_string(),
-    );
+    let client = Client::new(env::var("API_KEY").unwrap().to_string());
 
     let txt_request = Request {
         contents: vec![Content {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Write a story about a magic backpack.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -40,7 +35,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let response = client.count_tokens(30, &txt_request).await?;
 
     info!("{:#?}", response);
 
@@ -1,9 +1,9 @@
 use std::env;
 
-use google_generative_ai_rs::v1::{api::Client, gemini::ResponseType};
+use google_generative_ai_rs::v1::api::Client;
 use log::info;
 
-/// Simple text request using the public API and an API key for authn
+/// Gets the default model using the public API and an API key for authn
 /// To run:
 /// ```
 /// API_KEY=[YOUR_API_KEY] RUST_LOG=info cargo run --package google-generative-ai-rs  --example get_model
@@ -12,10 +12,7 @@ use log::info;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let client = Client::new_from_response_type(
-        ResponseType::GetModel,
-        env::var("API_KEY").unwrap().to_string(),
-    );
+    let client = Client::new(env::var("API_KEY").unwrap().to_string());
 
     let response = client.get_model(30).await?;
 
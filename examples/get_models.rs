@@ -1,9 +1,10 @@
 use std::env;
 
+use futures::TryStreamExt;
 use google_generative_ai_rs::v1::{api::Client, gemini::ResponseType};
 use log::info;
 
-/// Simple text request using the public API and an API key for authn
+/// Lists all the models that support `generateContent`, using the public API and an API key for authn
 /// To run:
 /// ```
 /// API_KEY=[YOUR_API_KEY] RUST_LOG=info cargo run --package google-generative-ai-rs  --example get_models
@@ -12,14 +13,17 @@ use log::info;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let client = Client::new_from_response_type(
-        ResponseType::GetModelList,
-        env::var("API_KEY").unwrap().to_string(),
-    );
+    let client = Client::new(env::var("API_KEY").unwrap().to_string());
 
-    let response = client.get_model_list(30).await?;
+    // Follows the `nextPageToken` of each page until all models are listed
+    let models: Vec<_> = client
+        .list_models_supporting(30, ResponseType::GenerateContent)
+        .try_collect()
+        .await?;
 
-    info!("{:#?}", response);
+    for model in models {
+        info!("{} - {}", model.name, model.display_name);
+    }
 
     Ok(())
 }
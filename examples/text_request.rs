@@ -11,21 +11,27 @@ use google_generative_ai_rs::v1::{
 /// ```
 /// API_KEY=[YOUR_API_KEY] RUST_LOG=info cargo run --package google-generative-ai-rs  --example text_request
 /// ``
+///
+/// To record the interaction once, and then replay it offline without an API key, e.g. in CI:
+/// ```
+/// API_KEY=[YOUR_API_KEY] GEMINI_CASSETTE=fixtures/text_request.json GEMINI_CASSETTE_MODE=record cargo run --example text_request
+/// GEMINI_CASSETTE=fixtures/text_request.json cargo run --example text_request
+/// ```
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
     // Either run as a standard text request or a stream generate content request
-    let client = Client::new(env::var("API_KEY").unwrap().to_string());
+    // No API key is needed when replaying a cassette
+    let client = Client::new(env::var("API_KEY").unwrap_or_default().to_string())
+        .with_cassette_from_env()?;
 
     let txt_request = Request {
         contents: vec![Content {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Give me a recipe for banana bread.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -36,7 +42,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let response = client.generate_content(30, &txt_request).await?;
 
     info!("{:#?}", response);
 
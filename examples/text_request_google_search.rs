@@ -0,0 +1,63 @@
+#[cfg(feature = "beta")]
+use std::env;
+
+#[cfg(feature = "beta")]
+use google_generative_ai_rs::v1::{
+    api::Client,
+    gemini::{
+        request::{Request, Tools},
+        Content, Model, Part, Role,
+    },
+};
+
+/// Text request grounded with Google Search using the public API and an API key for authn.
+/// The response text is printed with inline citation markers, followed by the list of sources.
+///
+/// NOTE: Currently, only available on the v1beta API.
+///
+/// To run:
+/// ```
+/// API_KEY=[YOUR_API_KEY] RUST_LOG=info cargo run --features "beta" --package google-generative-ai-rs  --example text_request_google_search
+/// ``
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    #[cfg(not(feature = "beta"))]
+    {
+        log::error!("Grounding with Google Search currently only works on 'beta'");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "beta")]
+    {
+        let client = Client::new_from_model(
+            Model::Gemini2_0Flash,
+            env::var("API_KEY").unwrap().to_string(),
+        );
+
+        let txt_request = Request {
+            contents: vec![Content {
+                role: Role::User,
+                parts: vec![Part {
+                    text: Some("Who won the most recent Formula 1 world championship?".to_string()),
+                    ..Default::default()
+                }],
+            }],
+            tools: vec![Tools::google_search()],
+            safety_settings: vec![],
+            generation_config: None,
+
+            system_instruction: None,
+        };
+
+        let response = client.generate_content(30, &txt_request).await?;
+
+        for candidate in &response.candidates {
+            println!("{}", candidate.get_text_with_citations());
+        }
+
+        Ok(())
+    }
+}
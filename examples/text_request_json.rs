@@ -48,28 +48,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 role: Role::User,
                 parts: vec![Part {
                     text: Some(prompt),
-                    inline_data: None,
-                    file_data: None,
-                    video_metadata: None,
+                    ..Default::default()
                 }],
             }],
             tools: vec![],
             safety_settings: vec![],
-            generation_config: Some(GenerationConfig {
-                temperature: None,
-                top_p: None,
-                top_k: None,
-                candidate_count: None,
-                max_output_tokens: None,
-                stop_sequences: None,
-                response_mime_type: Some("application/json".to_string()),
-                response_schema: None,
-            }),
+            generation_config: Some(
+                GenerationConfig::builder()
+                    .response_mime_type("application/json".to_string())
+                    .build()?,
+            ),
 
             system_instruction: None,
         };
 
-        let response = client.post(30, &txt_request).await?;
+        let response = client.generate_content(30, &txt_request).await?;
 
         log::info!("{:#?}", response);
 
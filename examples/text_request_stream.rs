@@ -3,7 +3,7 @@ use std::io::{stdout, Write};
 
 use google_generative_ai_rs::v1::{
     api::Client,
-    gemini::{request::Request, response::GeminiResponse, Content, Part, ResponseType, Role},
+    gemini::{request::Request, response::GeminiResponse, Content, Part, Role},
 };
 
 /// Simple text request using the public API and an API key for authn
@@ -24,22 +24,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Either run as a standard text request or a stream generate content request
-    let client = Client::new_from_model_response_type(
+    let client = Client::new_from_model(
         google_generative_ai_rs::v1::gemini::Model::Gemini1_0Pro,
-        token.clone(),
-        ResponseType::StreamGenerateContent,
+        token,
     );
 
-    println!("token {:#?}", token);
-
     let txt_request = Request {
         contents: vec![Content {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Give me a recipe for banana bread.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -50,27 +45,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let response = client.stream_generate_content(30, &txt_request).await?;
 
     println!("output streaming content");
 
-    if let Some(stream_response) = response.streamed() {
-        if let Some(json_stream) = stream_response.response_stream {
-            Client::for_each_async(json_stream, move |response: GeminiResponse| async move {
-                let mut lock = stdout().lock();
-                write!(
-                    lock,
-                    "{}",
-                    response.candidates[0].content.parts[0]
-                        .text
-                        .clone()
-                        .unwrap()
-                        .as_str()
-                )
-                .unwrap();
-            })
-            .await
-        }
+    if let Some(json_stream) = response.response_stream {
+        Client::for_each_async(json_stream, move |response: GeminiResponse| async move {
+            let mut lock = stdout().lock();
+            write!(lock, "{}", response.get_text()).unwrap();
+        })
+        .await
     }
 
     Ok(())
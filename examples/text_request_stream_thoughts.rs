@@ -0,0 +1,74 @@
+#[cfg(feature = "beta")]
+use std::env;
+#[cfg(feature = "beta")]
+use std::io::{stdout, Write};
+
+#[cfg(feature = "beta")]
+use google_generative_ai_rs::v1::{
+    api::Client,
+    gemini::{
+        request::{GenerationConfig, Request},
+        response::GeminiResponse,
+        Content, Model, Part, Role,
+    },
+};
+
+/// Streamed text request to a thinking model, showing the thought summaries separately from the answer.
+///
+/// NOTE: Currently, only available on the v1beta API.
+///
+/// To run:
+/// ```
+/// API_KEY=[YOUR_API_KEY] RUST_LOG=info cargo run --features "beta" --package google-generative-ai-rs  --example text_request_stream_thoughts
+/// ``
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    #[cfg(not(feature = "beta"))]
+    {
+        log::error!("Thinking models currently only work on 'beta'");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "beta")]
+    {
+        let client = Client::new_from_model(
+            Model::Custom("gemini-2.5-flash".to_string()),
+            env::var("API_KEY").unwrap().to_string(),
+        );
+
+        let txt_request = Request {
+            contents: vec![Content {
+                role: Role::User,
+                parts: vec![Part {
+                    text: Some("How many Rs are in the word strawberry?".to_string()),
+                    ..Default::default()
+                }],
+            }],
+            tools: vec![],
+            safety_settings: vec![],
+            generation_config: Some(GenerationConfig::builder().include_thoughts(true).build()?),
+
+            system_instruction: None,
+        };
+
+        let response = client.stream_generate_content(30, &txt_request).await?;
+
+        if let Some(json_stream) = response.response_stream {
+            Client::for_each_async(json_stream, move |response: GeminiResponse| async move {
+                let mut lock = stdout().lock();
+                let thoughts = response.get_thought_text();
+                if !thoughts.is_empty() {
+                    // Thoughts are dimmed to set them apart from the answer
+                    write!(lock, "\x1b[2m{}\x1b[0m", thoughts).unwrap();
+                }
+                write!(lock, "{}", response.get_text()).unwrap();
+            })
+            .await
+        }
+
+        Ok(())
+    }
+}
@@ -2,12 +2,11 @@ use std::env;
 
 use google_generative_ai_rs::v1::{
     api::Client,
-    gemini::{request::Request, Content, Part, ResponseType, Role},
+    gemini::{request::Request, Content, Part, Role},
 };
 use log::info;
 
-/// Counts the tokens used in a prompt using the public API and an API key for authn
-/// See: `https://ai.google.dev/tutorials/rest_quickstart#count_tokens`
+/// Counts and computes the tokens used in a prompt using Vertex AI
 /// You'll need to install the GCP cli tools and set up your GCP project and region.
 ///
 /// The ensure you locally authenticated with GCP using the following commands:
@@ -26,20 +25,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let region = env::var("GCP_REGION_NAME").unwrap().to_string();
     let project_id = env::var("GCP_PROJECT_ID").unwrap().to_string();
 
-    let client = Client::new_from_region_project_id_response_type(
-        region.to_string(),
-        project_id.to_string(),
-        ResponseType::CountTokens,
-    );
+    let client = Client::new_from_region_project_id(region.to_string(), project_id.to_string());
 
     let txt_request = Request {
         contents: vec![Content {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Write a story about a magic backpack.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -50,9 +43,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let token_count = client.count_tokens(30, &txt_request).await?;
+    info!("{:#?}", token_count);
 
-    info!("{:#?}", response);
+    let computed_tokens = client.compute_tokens(30, &txt_request).await?;
+    info!("{:#?}", computed_tokens);
 
     Ok(())
 }
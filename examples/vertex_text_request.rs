@@ -3,7 +3,7 @@ use std::env;
 
 use google_generative_ai_rs::v1::{
     api::Client,
-    gemini::{request::Request, Content, Part, ResponseType, Role},
+    gemini::{request::Request, Content, Part, Role},
 };
 
 /// Simple text request using the public API and an API key for authn
@@ -26,20 +26,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let region = env::var("GCP_REGION_NAME").unwrap().to_string();
     let project_id = env::var("GCP_PROJECT_ID").unwrap().to_string();
 
-    let client = Client::new_from_region_project_id_response_type(
-        region.to_string(),
-        project_id.to_string(),
-        ResponseType::GenerateContent,
-    );
+    let client = Client::new_from_region_project_id(region.to_string(), project_id.to_string());
 
     let txt_request = Request {
         contents: vec![Content {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Give me a recipe for banana bread.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -50,7 +44,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let response = client.generate_content(30, &txt_request).await?;
 
     info!("{:#?}", response);
 
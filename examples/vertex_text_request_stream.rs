@@ -32,9 +32,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             role: Role::User,
             parts: vec![Part {
                 text: Some("Give me a recipe for banana bread.".to_string()),
-                inline_data: None,
-                file_data: None,
-                video_metadata: None,
+                ..Default::default()
             }],
         }],
         tools: vec![],
@@ -45,27 +43,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         system_instruction: None,
     };
 
-    let response = client.post(30, &txt_request).await?;
+    let response = client.stream_generate_content(30, &txt_request).await?;
 
     println!("output streaming content");
 
-    if let Some(stream_response) = response.streamed() {
-        if let Some(json_stream) = stream_response.response_stream {
-            Client::for_each_async(json_stream, move |response: GeminiResponse| async move {
-                let mut lock = stdout().lock();
-                write!(
-                    lock,
-                    "{}",
-                    response.candidates[0].content.parts[0]
-                        .text
-                        .clone()
-                        .unwrap()
-                        .as_str()
-                )
-                .unwrap();
-            })
-            .await
-        }
+    if let Some(json_stream) = response.response_stream {
+        Client::for_each_async(json_stream, move |response: GeminiResponse| async move {
+            let mut lock = stdout().lock();
+            write!(lock, "{}", response.get_text()).unwrap();
+        })
+        .await
     }
 
     Ok(())
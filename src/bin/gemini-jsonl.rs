@@ -0,0 +1,150 @@
+//! Runs a JSON Lines file of requests, and writes their responses or errors to a JSON Lines file, see the
+//! `v1::jsonl` module. Running it again with the same files resumes an interrupted run.
+//!
+//! To run with the public API:
+//! ```
+//! API_KEY=[YOUR_API_KEY] cargo run --features cli --bin gemini-jsonl -- prompts.jsonl responses.jsonl --concurrency 16
+//! ```
+//!
+//! or with Vertex AI, using the application default credentials:
+//! ```
+//! GOOGLE_CLOUD_PROJECT=[PROJECT] GOOGLE_CLOUD_REGION=us-central1 cargo run --features cli --bin gemini-jsonl -- prompts.jsonl responses.jsonl
+//! ```
+use std::env;
+use std::process::ExitCode;
+
+use google_generative_ai_rs::v1::{
+    api::Client,
+    bulk::{BulkGenerator, RetryPolicy},
+    gemini::Model,
+    jsonl::JsonlRunner,
+};
+
+const USAGE: &str = "Usage: gemini-jsonl <INPUT> <OUTPUT> [OPTIONS]
+
+Options:
+  --model <MODEL>          the model, e.g. gemini-1.5-flash
+  --concurrency <N>        the maximum number of requests in flight [default: 8]
+  --timeout <SECONDS>      the timeout of each attempt [default: 60]
+  --max-attempts <N>       the attempts per request, including retries [default: 3]
+  --retry-failed           also send again the requests that failed in a previous run
+
+Environment:
+  API_KEY                                    the API key of the public API, or
+  GOOGLE_CLOUD_PROJECT, GOOGLE_CLOUD_REGION  the project and region of Vertex AI";
+
+struct Args {
+    input: String,
+    output: String,
+    model: Option<Model>,
+    concurrency: usize,
+    timeout: u64,
+    max_attempts: u32,
+    retry_failed: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut paths = Vec::new();
+    let mut parsed = Args {
+        input: String::new(),
+        output: String::new(),
+        model: None,
+        concurrency: 8,
+        timeout: 60,
+        max_attempts: RetryPolicy::default().max_attempts,
+        retry_failed: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--model" => parsed.model = Some(Model::from(value(&arg)?.as_str())),
+            "--concurrency" => parsed.concurrency = parse_number(&arg, value(&arg)?)?,
+            "--timeout" => parsed.timeout = parse_number(&arg, value(&arg)?)?,
+            "--max-attempts" => parsed.max_attempts = parse_number(&arg, value(&arg)?)?,
+            "--retry-failed" => parsed.retry_failed = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    match <[String; 2]>::try_from(paths) {
+        Ok([input, output]) => {
+            parsed.input = input;
+            parsed.output = output;
+            Ok(parsed)
+        }
+        Err(_) => Err("expected an input and an output file".to_string()),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", name, value))
+}
+
+fn client(model: Option<Model>) -> Result<Client, String> {
+    let model = model.unwrap_or_default();
+    match (
+        env::var("API_KEY"),
+        env::var("GOOGLE_CLOUD_PROJECT"),
+        env::var("GOOGLE_CLOUD_REGION"),
+    ) {
+        (Ok(api_key), _, _) => Ok(Client::new_from_model(model, api_key)),
+        (Err(_), Ok(project_id), Ok(region)) => Ok(Client::new_from_model_region_project_id(
+            model, region, project_id,
+        )),
+        _ => Err("set API_KEY, or GOOGLE_CLOUD_PROJECT and GOOGLE_CLOUD_REGION".to_string()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("error: {}\n", error);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let client = match client(args.model) {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::from(2);
+        }
+    };
+
+    let generator = BulkGenerator::new(client)
+        .with_concurrency(args.concurrency)
+        .with_timeout(args.timeout)
+        .with_retry_policy(RetryPolicy {
+            max_attempts: args.max_attempts.max(1),
+            ..Default::default()
+        });
+    let runner = JsonlRunner::new(generator).with_retry_failed(args.retry_failed);
+    match runner.run(&args.input, &args.output).await {
+        Ok(summary) => {
+            eprintln!(
+                "{} requests: {} skipped, {} succeeded, {} failed, {} tokens",
+                summary.total,
+                summary.skipped,
+                summary.succeeded,
+                summary.failed,
+                summary.total_token_count
+            );
+            if summary.failed > 0 {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Err(error) => {
+            eprintln!("error: {}", error.message);
+            ExitCode::FAILURE
+        }
+    }
+}
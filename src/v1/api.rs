@@ -4,25 +4,37 @@ use futures::stream::StreamExt;
 use reqwest::StatusCode;
 use reqwest_streams::error::StreamBodyError;
 use reqwest_streams::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
+use crate::v1::auth::{ApiKeyProvider, CredentialProvider};
+use crate::v1::cassette::{Cassette, CassetteMode};
 use crate::v1::errors::GoogleAPIError;
-use crate::v1::gemini::request::Request;
-use crate::v1::gemini::response::GeminiResponse;
-use crate::v1::gemini::Model;
+#[cfg(feature = "beta")]
+use crate::v1::gemini::request::SystemInstructionContent;
+use crate::v1::gemini::request::{EmbedContentRequest, GenerationConfig, Request, Tools};
+use crate::v1::gemini::response::{
+    BatchEmbedContentsResponse, ContentEmbedding, EmbedContentResponse, GeminiResponse,
+};
+use crate::v1::gemini::{Content, Model};
+use crate::v1::middleware::{self, Middleware};
+use crate::v1::rate_limit::{RateLimitPermit, RateLimiter};
+use crate::v1::telemetry::{self, ContentCapture};
+use crate::v1::vertexai::VertexOptions;
 
 use super::gemini::response::{GeminiErrorResponse, StreamedGeminiResponse, TokenCount};
 use super::gemini::{ModelInformation, ModelInformationList, ResponseType};
 
 #[cfg(feature = "beta")]
-const PUBLIC_API_URL_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+pub(crate) const PUBLIC_API_URL_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
 
 #[cfg(not(feature = "beta"))]
-const PUBLIC_API_URL_BASE: &str = "https://generativelanguage.googleapis.com/v1";
+pub(crate) const PUBLIC_API_URL_BASE: &str = "https://generativelanguage.googleapis.com/v1";
 
 /// Enables a streamed or non-streamed response to be returned from the API.
 #[derive(Debug)]
@@ -52,13 +64,29 @@ impl PostResult {
     }
 }
 
-/// Manages the specific API connection
+/// Manages the specific API connection.
+///
+/// The URL of each operation is built per call, so a single client can generate, stream, count tokens, embed and
+/// get models. `response_type` is only used by [`Client::post`].
 pub struct Client {
-    pub url: String,
     pub model: Model,
     pub region: Option<String>,
     pub project_id: Option<String>,
     pub response_type: ResponseType,
+    /// Provides the API key or bearer token for each request.
+    pub(crate) credentials: Arc<dyn CredentialProvider>,
+    /// Overrides the base URL of the API, see [`Client::with_base_url`].
+    pub(crate) base_url: Option<String>,
+    /// The API version and publisher used for Vertex AI.
+    pub(crate) vertex_options: VertexOptions,
+    /// Records or replays the HTTP interactions, see [`Client::with_cassette`].
+    pub(crate) cassette: Option<Arc<Cassette>>,
+    /// The middleware chain around each request, see [`Client::with_middleware`].
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
+    /// Whether prompts and completions are recorded in the traces, see [`Client::with_content_capture`].
+    pub(crate) content_capture: ContentCapture,
+    /// Enforces the RPM and TPM quotas of the model, see [`Client::with_rate_limiter`].
+    pub(crate) rate_limiter: Option<RateLimiter>,
 }
 
 /// Implements the functions for the API client.
@@ -67,38 +95,21 @@ pub struct Client {
 impl Client {
     /// Creates a default new public API client.
     pub fn new(api_key: String) -> Self {
-        let url = Url::new(&Model::default(), api_key, &ResponseType::GenerateContent);
-        Self {
-            url: url.url,
-            model: Model::default(),
-            region: None,
-            project_id: None,
-            response_type: ResponseType::GenerateContent,
-        }
+        Client::new_from_model_response_type(
+            Model::default(),
+            api_key,
+            ResponseType::GenerateContent,
+        )
     }
 
     /// Creates a default new public API client for a specified response type.
     pub fn new_from_response_type(response_type: ResponseType, api_key: String) -> Self {
-        let url = Url::new(&Model::default(), api_key, &response_type);
-        Self {
-            url: url.url,
-            model: Model::default(),
-            region: None,
-            project_id: None,
-            response_type,
-        }
+        Client::new_from_model_response_type(Model::default(), api_key, response_type)
     }
 
     /// Create a new public API client for a specified model.
     pub fn new_from_model(model: Model, api_key: String) -> Self {
-        let url = Url::new(&model, api_key, &ResponseType::GenerateContent);
-        Self {
-            url: url.url,
-            model,
-            region: None,
-            project_id: None,
-            response_type: ResponseType::GenerateContent,
-        }
+        Client::new_from_model_response_type(model, api_key, ResponseType::GenerateContent)
     }
 
     /// Create a new public API client for a specified model.
@@ -107,107 +118,265 @@ impl Client {
         api_key: String,
         response_type: ResponseType,
     ) -> Self {
-        let url = Url::new(&model, api_key, &response_type);
         Self {
-            url: url.url,
             model,
             region: None,
             project_id: None,
             response_type,
+            credentials: Arc::new(ApiKeyProvider::new(api_key)),
+            base_url: None,
+            vertex_options: VertexOptions::default(),
+            cassette: None,
+            middleware: Vec::new(),
+            content_capture: ContentCapture::default(),
+            rate_limiter: None,
+        }
+    }
+
+    /// Overrides the base URL of the API, e.g. to go through a proxy or to test against a local server.
+    /// For Vertex AI, it replaces `https://{region}-aiplatform.googleapis.com/v1`, including the API version: the
+    /// override is used as is, whatever the version set by [`Client::with_vertex_api_version`]. The publisher models,
+    /// only available on `v1beta1`, are fetched with the version of an override ending with `/v1` replaced by `/v1beta1`.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Records the HTTP interactions to, or replays them from, a fixture file - see [`crate::v1::cassette`].
+    /// When replaying, no request is sent and no credentials are requested.
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(Arc::new(cassette));
+        self
+    }
+
+    /// Uses the cassette set by the `GEMINI_CASSETTE` and `GEMINI_CASSETTE_MODE` environment variables, if any.
+    /// See [`Cassette::from_env`].
+    pub fn with_cassette_from_env(self) -> Result<Self, GoogleAPIError> {
+        Ok(match Cassette::from_env()? {
+            Some(cassette) => self.with_cassette(cassette),
+            None => self,
+        })
+    }
+
+    /// Adds a middleware to the end of the chain around each request - see [`crate::v1::middleware`].
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Records the prompts and completions as events of the `tracing` spans, optionally redacted - see
+    /// [`crate::v1::telemetry`]. They are not recorded by default.
+    pub fn with_content_capture(mut self, content_capture: ContentCapture) -> Self {
+        self.content_capture = content_capture;
+        self
+    }
+
+    /// Waits for the quotas of the model before generating content - see [`crate::v1::rate_limit`].
+    /// Clone the limiter to share the quotas with other clients.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Replaces the credential provider, e.g. to send a bearer token from your own secret broker.
+    /// Parameters:
+    /// * provider - provides the credential for each request, see [`crate::v1::auth`]
+    pub fn with_credential_provider(mut self, provider: impl CredentialProvider + 'static) -> Self {
+        self.credentials = Arc::new(provider);
+        self
+    }
+
+    /// Forces the credential provider to fetch a fresh credential for the next request, e.g. a new access token.
+    /// Requests rejected with HTTP 401 do this, and are retried once, automatically, if the provider can refresh its
+    /// credential - see [`CredentialProvider::can_refresh`].
+    pub fn invalidate_credentials(&self) {
+        self.credentials.invalidate();
+    }
+
+    /// Waits for the rate limiter, if any, to allow the request - see [`Client::with_rate_limiter`].
+    async fn acquire_rate_limit(
+        &self,
+        api_request: &Request,
+    ) -> Result<Option<RateLimitPermit>, GoogleAPIError> {
+        match &self.rate_limiter {
+            Some(rate_limiter) => {
+                let estimated_tokens = rate_limiter.estimate(api_request);
+                Ok(Some(
+                    rate_limiter.acquire(&self.model, estimated_tokens).await?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Sends the request through the middleware chain, if any, see [`Client::with_middleware`].
+    pub(crate) async fn send(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
+        if self.middleware.is_empty() {
+            return self.transmit(request_builder).await;
+        }
+        middleware::dispatch(self, request_builder).await
+    }
+
+    /// Sends the request with the current credential.
+    /// If the credential is rejected with HTTP 401, it is invalidated and, if the provider can refresh it, the request is
+    /// retried once with a fresh one.
+    /// With a cassette, the interaction is recorded, or replayed without sending the request.
+    pub(crate) async fn transmit(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
+        let cassette = self.cassette.as_deref();
+        if let Some(cassette) = cassette.filter(|c| c.mode() == CassetteMode::Replay) {
+            return cassette.replay_next(request_builder).map(Ok);
+        }
+
+        // Recorded before the credential is added
+        let recorded_request = cassette.and_then(|_| request_builder.try_clone());
+        let retry = request_builder
+            .try_clone()
+            .filter(|_| self.credentials.can_refresh());
+        let mut result = self.authorize(request_builder).await?.send().await;
+
+        if let (Ok(response), Some(retry)) = (&result, retry) {
+            if response.status() == StatusCode::UNAUTHORIZED {
+                self.invalidate_credentials();
+                result = self.authorize(retry).await?.send().await;
+            }
+        }
+
+        match (cassette, recorded_request, result) {
+            (Some(cassette), Some(request), Ok(response)) => {
+                cassette.record_interaction(request, response).await.map(Ok)
+            }
+            (_, _, result) => Ok(result),
         }
     }
 
-    // post
+    /// Adds the credential from the client's provider to a request.
+    pub(crate) async fn authorize(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, GoogleAPIError> {
+        let credential = self.credentials.credential().await?;
+        Ok(credential.apply(request_builder))
+    }
+
+    /// Posts the request using the operation of the client's `response_type`.
+    #[deprecated(
+        note = "use `generate_content`, `stream_generate_content` or `count_tokens` instead"
+    )]
     pub async fn post(
         &self,
         timeout: u64,
         api_request: &Request,
     ) -> Result<PostResult, GoogleAPIError> {
-        let client: reqwest::Client = self.get_reqwest_client(timeout)?;
         match self.response_type {
             ResponseType::GenerateContent => {
-                let result = self.get_post_result(client, api_request).await?;
+                let result = self.generate_content(timeout, api_request).await?;
                 Ok(PostResult::Rest(result))
             }
             ResponseType::StreamGenerateContent => {
-                let result = self.get_streamed_post_result(client, api_request).await?;
+                let result = self.stream_generate_content(timeout, api_request).await?;
                 Ok(PostResult::Streamed(result))
             }
             ResponseType::CountTokens => {
-                let result = self.get_token_count(client, api_request).await?;
+                let result = self.count_tokens(timeout, api_request).await?;
                 Ok(PostResult::Count(result))
             }
             _ => Err(GoogleAPIError {
                 message: format!("Unsupported response type: {:?}", self.response_type),
                 code: None,
+                rate_limit: None,
             }),
         }
     }
 
-    /// A standard post request, i.e., not streamed
-    async fn get_post_result(
+    /// Generates a response, i.e., not streamed - see: "https://ai.google.dev/api/generate-content#method:-models.generatecontent"
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * api_request - the request to send
+    pub async fn generate_content(
         &self,
-        client: reqwest::Client,
+        timeout: u64,
         api_request: &Request,
     ) -> Result<GeminiResponse, GoogleAPIError> {
-        let token_option = self.get_auth_token_option().await?;
-
-        let result = self
-            .get_post_response(client, api_request, token_option)
+        let span = telemetry::operation_span(self, "generate_content");
+        telemetry::record_request(&span, api_request, &self.content_capture);
+        let response: GeminiResponse = telemetry::traced(&span, async {
+            let permit = self.acquire_rate_limit(api_request).await?;
+            let url = self.operation_url(&ResponseType::GenerateContent);
+            let response: Result<GeminiResponse, GoogleAPIError> = async {
+                let result = self.post_json(timeout, &url, api_request).await?;
+                self.deserialize_response(result).await
+            }
             .await;
-
-        if let Ok(result) = result {
-            match result.status() {
-                reqwest::StatusCode::OK => {
-                    Ok(result.json::<GeminiResponse>().await.map_err(|e|GoogleAPIError {
-                    message: format!(
-                            "Failed to deserialize API response into v1::gemini::response::GeminiResponse: {}",
-                            e
-                        ),
-                    code: None,
-                    })?)
-                },
-                _ => {
-                    let status = result.status();
-
-                    match result.json::<GeminiErrorResponse>().await {
-                        Ok(GeminiErrorResponse::Error { message, .. }) => Err(self.new_error_from_api_message(status, message)),
-                        Err(_) => Err(self.new_error_from_status_code(status)),
+            match (permit, &response) {
+                (Some(permit), Ok(response)) => {
+                    if let Some(usage) = &response.usage_metadata {
+                        permit.reconcile_usage(usage);
                     }
-                },
+                }
+                (Some(permit), Err(_)) => permit.reconcile(0),
+                (None, _) => {}
             }
-        } else {
-            Err(self.new_error_from_reqwest_error(result.unwrap_err()))
-        }
+            response
+        })
+        .await?;
+        telemetry::record_response(&span, &response, &self.content_capture);
+        Ok(response)
     }
 
-    // Define the function that accepts the stream and the consumer
-    /// A streamed post request
-    async fn get_streamed_post_result(
+    /// Generates a streamed response - see: "https://ai.google.dev/api/generate-content#method:-models.streamgeneratecontent"
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * api_request - the request to send
+    pub async fn stream_generate_content(
         &self,
-        client: reqwest::Client,
+        timeout: u64,
         api_request: &Request,
     ) -> Result<StreamedGeminiResponse, GoogleAPIError> {
-        let token_option = self.get_auth_token_option().await?;
-
-        let result = self
-            .get_post_response(client, api_request, token_option)
-            .await;
-
-        match result {
-            Ok(response) => match response.status() {
-                reqwest::StatusCode::OK => {
-                    // Wire to enable introspection on the response stream
-                    let json_stream = response.json_array_stream::<serde_json::Value>(2048); //TODO what is a good length?;
-
-                    Ok(StreamedGeminiResponse {
-                        response_stream: Some(json_stream),
-                    })
+        let span = telemetry::operation_span(self, "generate_content");
+        telemetry::record_request(&span, api_request, &self.content_capture);
+        let started = Instant::now();
+        let response = telemetry::traced(&span, async {
+            let permit = self.acquire_rate_limit(api_request).await?;
+            let url = self.operation_url(&ResponseType::StreamGenerateContent);
+            let result = match self.post_json(timeout, &url, api_request).await {
+                Ok(Ok(response)) if response.status() == reqwest::StatusCode::OK => Ok(response),
+                Ok(Ok(response)) => Err(self.new_error_from_response(response).await),
+                Ok(Err(e)) => Err(self.new_error_from_reqwest_error(e)),
+                Err(e) => Err(e),
+            };
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    if let Some(permit) = &permit {
+                        permit.reconcile(0);
+                    }
+                    return Err(e);
                 }
-                _ => Err(self.new_error_from_status_code(response.status())),
-            },
-            Err(e) => Err(self.new_error_from_reqwest_error(e)),
-        }
+            };
+
+            // Wire to enable introspection on the response stream
+            let json_stream = response.json_array_stream::<serde_json::Value>(2048); //TODO what is a good length?;
+            let response = StreamedGeminiResponse {
+                response_stream: Some(json_stream),
+            };
+            Ok(match permit {
+                Some(permit) => permit.reconcile_stream(response),
+                None => response,
+            })
+        })
+        .await?;
+        Ok(telemetry::instrument_stream(
+            span,
+            response,
+            self.content_capture.clone(),
+            started,
+        ))
     }
 
     /// Applies an asynchronous operation to each item in a stream, potentially concurrently.
@@ -255,12 +424,14 @@ impl Client {
                                         e
                                     ),
                                     code: None,
+                                    rate_limit: None,
                                 }
                             })
                         }
                         Err(e) => Err(GoogleAPIError {
                             message: format!("Failed to get JSON stream from request: {}", e),
                             code: None,
+                            rate_limit: None,
                         }),
                     };
 
@@ -273,140 +444,342 @@ impl Client {
             .await;
     }
 
-    /// Gets a ['reqwest::GeminiResponse'] from a post request.
+    // Count Tokens - see: "https://ai.google.dev/tutorials/rest_quickstart#count_tokens"
+    //
     /// Parameters:
     /// * client - the ['reqwest::Client'] to use
-    /// * api_request - the ['Request'] to send
-    /// * authn_token - an optional authn token to use
-    async fn get_post_response(
+    /// * api_request - the request to send to check token count
+    #[deprecated(note = "use `count_tokens` instead")]
+    pub async fn get_token_count(
         &self,
         client: reqwest::Client,
         api_request: &Request,
-        authn_token: Option<String>,
-    ) -> Result<reqwest::Response, reqwest::Error> {
-        let mut request_builder = client
-            .post(&self.url)
+    ) -> Result<TokenCount, GoogleAPIError> {
+        let request_builder = client
+            .post(self.operation_url(&ResponseType::CountTokens))
             .header(reqwest::header::USER_AGENT, env!("CARGO_CRATE_NAME"))
             .header(reqwest::header::CONTENT_TYPE, "application/json");
 
-        // If a GCP authn token is provided, use it
-        if let Some(token) = authn_token {
-            request_builder = request_builder.bearer_auth(token);
-        }
-
-        request_builder.json(&api_request).send().await
+        let result = self.send(request_builder.json(api_request)).await?;
+        self.deserialize_response(result).await
     }
-    // Count Tokens - see: "https://ai.google.dev/tutorials/rest_quickstart#count_tokens"
-    //
+
+    /// Counts the tokens of the whole request, including the tools, system instruction and generation config.
+    ///
+    /// Unlike [`Client::get_token_count`], this works whatever the response type of the client is.
     /// Parameters:
     /// * timeout - the timeout in seconds
-    /// * api_request - the request to send to check token count
-    pub async fn get_token_count(
+    /// * api_request - the request to count the tokens of
+    pub async fn count_tokens(
         &self,
-        client: reqwest::Client,
+        timeout: u64,
         api_request: &Request,
     ) -> Result<TokenCount, GoogleAPIError> {
-        let token_option = self.get_auth_token_option().await?;
-
-        let result = self
-            .get_post_response(client, api_request, token_option)
-            .await;
+        let span = telemetry::operation_span(self, "count_tokens");
+        let token_count: TokenCount = telemetry::traced(&span, async {
+            let url = self.operation_url(&ResponseType::CountTokens);
+            let result = if self.is_vertex() {
+                self.post_json(timeout, &url, &VertexCountTokensRequest::from(api_request))
+                    .await?
+            } else {
+                let body = CountTokensRequest {
+                    generate_content_request: GenerateContentRequest {
+                        model: self.model.resource_name(),
+                        request: api_request,
+                    },
+                };
+                self.post_json(timeout, &url, &body).await?
+            };
+            self.deserialize_response(result).await
+        })
+        .await?;
+        span.record("gen_ai.usage.input_tokens", token_count.total_tokens);
+        Ok(token_count)
+    }
 
-        match result {
-            Ok(response) => match response.status() {
-                reqwest::StatusCode::OK => Ok(response.json::<TokenCount>().await.map_err(|e|GoogleAPIError {
-                message: format!(
-                        "Failed to deserialize API response into v1::gemini::response::TokenCount: {}",
-                        e
-                    ),
-                code: None,
-            })?),
-                _ => Err(self.new_error_from_status_code(response.status())),
+    /// Gets the URL of an operation on the client's model, whatever the response type of the client is.
+    pub(crate) fn operation_url(&self, response_type: &ResponseType) -> String {
+        match (&self.region, &self.project_id) {
+            (Some(region), Some(project_id)) => Url::new_from_vertex_options(
+                self.base_url.as_deref(),
+                &self.vertex_options,
+                &self.model,
+                region,
+                project_id,
+                response_type,
+            ),
+            _ => match &self.base_url {
+                Some(base_url) => Url::new_from_base_url(base_url, &self.model, response_type),
+                None => Url::new(&self.model, response_type),
             },
-            Err(e) => Err(self.new_error_from_reqwest_error(e)),
         }
+        .url
     }
 
-    /// Get for the url specified in 'self'
-    async fn get(
+    /// Posts the body as JSON to the specified url.
+    pub(crate) async fn post_json<T: Serialize + ?Sized>(
         &self,
         timeout: u64,
+        url: &str,
+        body: &T,
     ) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
         let client: reqwest::Client = self.get_reqwest_client(timeout)?;
-        let result = client
-            .get(&self.url)
+
+        let request_builder = client
+            .post(url)
             .header(reqwest::header::USER_AGENT, env!("CARGO_CRATE_NAME"))
-            .send()
-            .await;
-        Ok(result)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+        self.send(request_builder.json(body)).await
+    }
+
+    /// Get for the specified url, with the given query parameters.
+    pub(crate) async fn get(
+        &self,
+        timeout: u64,
+        url: &str,
+        query: &[(&str, String)],
+    ) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
+        let client: reqwest::Client = self.get_reqwest_client(timeout)?;
+
+        let request_builder = client
+            .get(url)
+            .query(query)
+            .header(reqwest::header::USER_AGENT, env!("CARGO_CRATE_NAME"));
+
+        self.send(request_builder).await
     }
     /// Gets a model - see: "https://ai.google.dev/tutorials/rest_quickstart#get_model"
+    /// For Vertex AI, the publisher model is returned.
     /// Parameters:
     /// * timeout - the timeout in seconds
     pub async fn get_model(&self, timeout: u64) -> Result<ModelInformation, GoogleAPIError> {
-        let result = self.get(timeout).await?;
-
-        match result {
-            Ok(response) => {
-                match response.status() {
-                    reqwest::StatusCode::OK => Ok(response
-                        .json::<ModelInformation>()
-                        .await
-                        .map_err(|e| GoogleAPIError {
-                            message: format!(
-                        "Failed to deserialize API response into v1::gemini::ModelInformation: {}",
-                        e
-                    ),
-                            code: None,
-                        })?),
-                    _ => Err(self.new_error_from_status_code(response.status())),
-                }
+        let span = telemetry::operation_span(self, "get_model");
+        telemetry::traced(&span, async {
+            if self.is_vertex() {
+                return self.get_publisher_model(timeout).await;
             }
-            Err(e) => Err(self.new_error_from_reqwest_error(e)),
-        }
+
+            let url = self.operation_url(&ResponseType::GetModel);
+            let result = self.get(timeout, &url, &[]).await?;
+            self.deserialize_response(result).await
+        })
+        .await
     }
-    /// Gets a list of models - see: "https://ai.google.dev/tutorials/rest_quickstart#list_models"
+    /// Gets the first page of the list of models - see: "https://ai.google.dev/tutorials/rest_quickstart#list_models"
+    /// Use [`Client::list_models`] to get all the models.
     /// Parameters:
     /// * timeout - the timeout in seconds
     pub async fn get_model_list(
         &self,
         timeout: u64,
     ) -> Result<ModelInformationList, GoogleAPIError> {
-        let result = self.get(timeout).await?;
+        self.get_model_list_page(timeout, None, None).await
+    }
+    /// Gets a single page of the list of models.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * page_size - the maximum number of models to return, if not set the API default is used
+    /// * page_token - the `next_page_token` of the previous page, if any
+    pub async fn get_model_list_page(
+        &self,
+        timeout: u64,
+        page_size: Option<u32>,
+        page_token: Option<String>,
+    ) -> Result<ModelInformationList, GoogleAPIError> {
+        let span = telemetry::operation_span(self, "list_models");
+        telemetry::traced(&span, async {
+            if self.is_vertex() {
+                return self
+                    .get_publisher_model_list_page(timeout, page_size, page_token)
+                    .await;
+            }
+
+            let mut query = Vec::new();
+            if let Some(page_size) = page_size {
+                query.push(("pageSize", page_size.to_string()));
+            }
+            if let Some(page_token) = page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let url = self.operation_url(&ResponseType::GetModelList);
+            let result = self.get(timeout, &url, &query).await?;
+            self.deserialize_response(result).await
+        })
+        .await
+    }
+    /// Lists all the models, following the `next_page_token` of each page.
+    ///
+    /// For Vertex AI, the Google publisher models are listed.
+    /// Parameters:
+    /// * timeout - the timeout in seconds, per page
+    /// * page_size - the maximum number of models to return per page, if not set the API default is used
+    pub fn list_models(
+        &self,
+        timeout: u64,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<ModelInformation, GoogleAPIError>> + '_ {
+        // The state is `None` once the last page has been returned
+        stream::try_unfold(
+            Some(None),
+            move |page_token: Option<Option<String>>| async move {
+                let page_token = match page_token {
+                    Some(page_token) => page_token,
+                    None => return Ok::<_, GoogleAPIError>(None),
+                };
+                let page: ModelInformationList = self
+                    .get_model_list_page(timeout, page_size, page_token)
+                    .await?;
+                let next_page_token = page
+                    .next_page_token
+                    .filter(|token| !token.is_empty())
+                    .map(Some);
+
+                Ok(Some((
+                    stream::iter(page.models.into_iter().map(Ok)),
+                    next_page_token,
+                )))
+            },
+        )
+        .try_flatten()
+    }
+    /// Lists all the models that support the API method used for the response type, e.g. only the models that
+    /// support [`ResponseType::EmbedContent`].
+    /// Parameters:
+    /// * timeout - the timeout in seconds, per page
+    /// * response_type - the response type the models need to support
+    pub fn list_models_supporting(
+        &self,
+        timeout: u64,
+        response_type: ResponseType,
+    ) -> impl Stream<Item = Result<ModelInformation, GoogleAPIError>> + '_ {
+        self.list_models(timeout, None)
+            .try_filter(move |model| future::ready(model.supports(&response_type)))
+    }
 
+    /// Deserializes a successful response, else creates an error from the API error message or status code.
+    pub(crate) async fn deserialize_response<T: DeserializeOwned>(
+        &self,
+        result: Result<reqwest::Response, reqwest::Error>,
+    ) -> Result<T, GoogleAPIError> {
         match result {
-            Ok(response) => {
-                match response.status() {
-                    reqwest::StatusCode::OK => Ok(response
-                        .json::<ModelInformationList>()
-                        .await
-                        .map_err(|e| GoogleAPIError {
-                            message: format!(
-                        "Failed to deserialize API response into Vec<v1::gemini::ModelInformationList>: {}",
+            Ok(response) => match response.status() {
+                reqwest::StatusCode::OK => response.json::<T>().await.map_err(|e| GoogleAPIError {
+                    message: format!(
+                        "Failed to deserialize API response into {}: {}",
+                        std::any::type_name::<T>(),
                         e
                     ),
-                        code: None,
-                    })?),
-                    _ => Err(self.new_error_from_status_code(response.status())),
-                }
-            }
+                    code: None,
+                    rate_limit: None,
+                }),
+                _ => Err(self.new_error_from_response(response).await),
+            },
             Err(e) => Err(self.new_error_from_reqwest_error(e)),
         }
     }
 
-    // TODO function - see "https://cloud.google.com/vertex-ai/docs/generative-ai/multimodal/function-calling"
+    /// Creates an error from the API error message of an unsuccessful response, else from its status code.
+    pub(crate) async fn new_error_from_response(
+        &self,
+        response: reqwest::Response,
+    ) -> GoogleAPIError {
+        let status = response.status();
+        match response.json::<GeminiErrorResponse>().await {
+            Ok(GeminiErrorResponse::Error { message, .. }) => {
+                self.new_error_from_api_message(status, message)
+            }
+            Err(_) => self.new_error_from_status_code(status),
+        }
+    }
+
+    /// Embeds a content - see: "https://ai.google.dev/api/embeddings#method:-models.embedcontent"
+    /// On Vertex AI, the `predict` method of the embedding model is used.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * embed_request - the content to embed
+    pub async fn embed(
+        &self,
+        timeout: u64,
+        embed_request: &EmbedContentRequest,
+    ) -> Result<ContentEmbedding, GoogleAPIError> {
+        let span = telemetry::operation_span(self, "embeddings");
+        telemetry::traced(&span, async {
+            if self.is_vertex() {
+                let mut embeddings = self
+                    .predict_embeddings(timeout, std::slice::from_ref(embed_request))
+                    .await?;
+                return embeddings.pop().ok_or_else(|| GoogleAPIError {
+                    message: "Vertex AI returned no embedding".to_string(),
+                    code: None,
+                    rate_limit: None,
+                });
+            }
+
+            let url = self.operation_url(&ResponseType::EmbedContent);
+            let result = self.post_json(timeout, &url, embed_request).await?;
+            let response: EmbedContentResponse = self.deserialize_response(result).await?;
+            Ok(response.embedding)
+        })
+        .await
+    }
+
+    /// Embeds several contents in a single request, returning the embeddings in the same order.
+    /// See: "https://ai.google.dev/api/embeddings#method:-models.batchembedcontents"
+    /// On Vertex AI, the `predict` method of the embedding model is used.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * embed_requests - the contents to embed
+    pub async fn batch_embed(
+        &self,
+        timeout: u64,
+        embed_requests: &[EmbedContentRequest],
+    ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+        let span = telemetry::operation_span(self, "embeddings");
+        telemetry::traced(&span, async {
+            if self.is_vertex() {
+                return self.predict_embeddings(timeout, embed_requests).await;
+            }
 
-    // TODO embedContent - see: "https://ai.google.dev/tutorials/rest_quickstart#embedding"
+            // Each request of a batch needs the model
+            let requests: Vec<EmbedContentRequest> = embed_requests
+                .iter()
+                .map(|request| EmbedContentRequest {
+                    model: Some(self.model.resource_name()),
+                    ..request.clone()
+                })
+                .collect();
+            let url = self.operation_url(&ResponseType::BatchEmbedContents);
+            let result = self
+                .post_json(
+                    timeout,
+                    &url,
+                    &BatchEmbedContentsRequest {
+                        requests: &requests,
+                    },
+                )
+                .await?;
+            let response: BatchEmbedContentsResponse = self.deserialize_response(result).await?;
+            Ok(response.embeddings)
+        })
+        .await
+    }
 
-    /// The current version of the Vertex API only supports streamed responses, so
-    /// in order to handle any issues we use a serde_json::Value and then convert to a Gemini [`Candidate`].
+    // TODO function - see "https://cloud.google.com/vertex-ai/docs/generative-ai/multimodal/function-calling"
+
+    /// Each item of a streamed response is read as a serde_json::Value, so that it can be introspected, and then
+    /// converted to a [`GeminiResponse`].
     fn convert_json_value_to_response(
         json_value: &serde_json::Value,
     ) -> Result<GeminiResponse, serde_json::error::Error> {
         serde_json::from_value(json_value.clone())
     }
 
-    fn get_reqwest_client(&self, timeout: u64) -> Result<reqwest::Client, GoogleAPIError> {
+    pub(crate) fn get_reqwest_client(
+        &self,
+        timeout: u64,
+    ) -> Result<reqwest::Client, GoogleAPIError> {
         let client: reqwest::Client = reqwest::Client::builder()
             .timeout(Duration::from_secs(timeout))
             .build()
@@ -421,6 +794,7 @@ impl Client {
         GoogleAPIError {
             message,
             code: Some(code),
+            rate_limit: None,
         }
     }
 
@@ -431,11 +805,12 @@ impl Client {
         GoogleAPIError {
             message,
             code: Some(code),
+            rate_limit: None,
         }
     }
 
     /// Creates a new error from a reqwest error.
-    fn new_error_from_reqwest_error(&self, mut e: reqwest::Error) -> GoogleAPIError {
+    pub(crate) fn new_error_from_reqwest_error(&self, mut e: reqwest::Error) -> GoogleAPIError {
         if let Some(url) = e.url_mut() {
             // Remove the API key from the URL, if any
             url.query_pairs_mut().clear();
@@ -444,6 +819,50 @@ impl Client {
         GoogleAPIError {
             message: format!("{}", e),
             code: e.status(),
+            rate_limit: None,
+        }
+    }
+}
+
+/// The public API `countTokens` body, which wraps the full request so that tools and the system instruction are counted.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CountTokensRequest<'a> {
+    generate_content_request: GenerateContentRequest<'a>,
+}
+#[derive(Serialize)]
+struct GenerateContentRequest<'a> {
+    model: String,
+    #[serde(flatten)]
+    request: &'a Request,
+}
+
+#[derive(Serialize)]
+struct BatchEmbedContentsRequest<'a> {
+    requests: &'a [EmbedContentRequest],
+}
+
+/// The Vertex AI `countTokens` body, which takes the request fields directly.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct VertexCountTokensRequest<'a> {
+    contents: &'a [Content],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: &'a [Tools],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<&'a GenerationConfig>,
+    #[cfg(feature = "beta")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<&'a SystemInstructionContent>,
+}
+impl<'a> From<&'a Request> for VertexCountTokensRequest<'a> {
+    fn from(request: &'a Request) -> Self {
+        VertexCountTokensRequest {
+            contents: &request.contents,
+            tools: &request.tools,
+            generation_config: request.generation_config.as_ref(),
+            #[cfg(feature = "beta")]
+            system_instruction: request.system_instruction.as_ref(),
         }
     }
 }
@@ -452,39 +871,31 @@ impl Client {
 /// Authn for public models is via an API key, while authn for private models is via application default credentials (ADC).
 /// The public API URL is in the form of: https://generativelanguage.googleapis.com/v1/models/{model}:{generateContent|streamGenerateContent}
 /// The Vertex AI API URL is in the form of: https://{region}-aiplatform.googleapis.com/v1/projects/{project_id}/locations/{region}/publishers/google/models/{model}:{streamGenerateContent}
+/// or, for a deployed tuned model: https://{region}-aiplatform.googleapis.com/v1/projects/{project_id}/locations/{region}/endpoints/{endpoint}:{generateContent|streamGenerateContent}
 #[derive(Debug)]
 pub(crate) struct Url {
     pub url: String,
 }
 impl Url {
-    pub(crate) fn new(model: &Model, api_key: String, response_type: &ResponseType) -> Self {
-        let base_url = PUBLIC_API_URL_BASE.to_owned();
+    pub(crate) fn new(model: &Model, response_type: &ResponseType) -> Self {
+        Url::new_from_base_url(PUBLIC_API_URL_BASE, model, response_type)
+    }
+
+    pub(crate) fn new_from_base_url(
+        base_url: &str,
+        model: &Model,
+        response_type: &ResponseType,
+    ) -> Self {
         match response_type {
-            ResponseType::GenerateContent => Self {
-                url: format!(
-                    "{}/models/{}:{}?key={}",
-                    base_url, model, response_type, api_key
-                ),
-            },
-            ResponseType::StreamGenerateContent => Self {
-                url: format!(
-                    "{}/models/{}:{}?key={}",
-                    base_url, model, response_type, api_key
-                ),
-            },
             ResponseType::GetModel => Self {
-                url: format!("{}/models/{}?key={}", base_url, model, api_key),
+                url: format!("{}/{}", base_url, model.resource_name()),
             },
             ResponseType::GetModelList => Self {
-                url: format!("{}/models?key={}", base_url, api_key),
+                url: format!("{}/models", base_url),
             },
-            ResponseType::CountTokens => Self {
-                url: format!(
-                    "{}/models/{}:{}?key={}",
-                    base_url, model, response_type, api_key
-                ),
+            _ => Self {
+                url: format!("{}/{}:{}", base_url, model.resource_name(), response_type),
             },
-            _ => panic!("Unsupported response type: {:?}", response_type),
         }
     }
 }
@@ -505,18 +916,290 @@ mod tests {
         assert_eq!(error.code, Some(status_code));
     }
 
+    const SECRET_API_KEY: &str = "AIzaSy-secret-api-key-that-must-not-leak";
+
+    /// Captures every tracing span, span field and event, as lines, so tests can check that secrets are never logged.
+    #[derive(Clone, Default)]
+    struct CapturedLines(Arc<std::sync::Mutex<Vec<String>>>);
+    struct Fields(String);
+    impl tracing::field::Visit for Fields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedLines {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields(format!(
+                "{} {}",
+                attrs.metadata().target(),
+                attrs.metadata().name()
+            ));
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields("record".to_string());
+            values.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields(event.metadata().target().to_string());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    /// Serves a single request with the response, returning the raw request.
+    async fn serve_once(response: String) -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 16 * 1024];
+            let read = socket.read(&mut request).await.unwrap();
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+        (base_url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_api_key_is_sent_in_a_header_and_not_in_the_url() {
+        let body =
+            r#"{"error":{"code":403,"message":"API key not valid.","status":"PERMISSION_DENIED"}}"#;
+        let response = format!(
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let (base_url, server) = serve_once(response).await;
+        let client = Client::new(SECRET_API_KEY.to_string()).with_base_url(base_url);
+
+        let error = client.get_model(5).await.unwrap_err();
+        let request = server.await.unwrap().to_lowercase();
+
+        let request_line = request.lines().next().unwrap();
+        assert!(!request_line.contains(&SECRET_API_KEY.to_lowercase()));
+        assert!(request.contains(&format!(
+            "x-goog-api-key: {}",
+            SECRET_API_KEY.to_lowercase()
+        )));
+        assert_eq!(error.code, Some(StatusCode::FORBIDDEN));
+        assert!(!error.message.contains(SECRET_API_KEY));
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_requests_are_only_retried_with_a_refreshable_credential() {
+        use crate::v1::auth::{CallbackProvider, Credential};
+        use crate::v1::mock_server::{MockResponse, MockServer};
+
+        let server = MockServer::start().await;
+        server
+            .enqueue(
+                ResponseType::GetModel,
+                MockResponse::error(401, "API key not valid."),
+            )
+            .enqueue(
+                ResponseType::GetModel,
+                MockResponse::error(401, "Token expired."),
+            );
+        let client = Client::new(SECRET_API_KEY.to_string()).with_base_url(server.base_url());
+
+        let error = client.get_model(5).await.unwrap_err();
+
+        assert_eq!(error.code, Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(server.requests().len(), 1);
+
+        let client = client.with_credential_provider(CallbackProvider::new(|| async {
+            Ok(Credential::Bearer("token".into()))
+        }));
+        client.get_model(5).await.unwrap();
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_is_not_in_errors_logs_or_display() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let captured = CapturedLines::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+        // Nothing listens on the discard port, so the connection is refused
+        let client = Client::new(SECRET_API_KEY.to_string())
+            .with_base_url("http://127.0.0.1:9")
+            .with_content_capture(ContentCapture::Full);
+
+        let error = client.get_model(5).await.unwrap_err();
+        let request = Request::new(vec![], vec![], vec![], None);
+        let generate_error = client.generate_content(5, &request).await.unwrap_err();
+
+        assert!(!error.message.contains(SECRET_API_KEY));
+        assert!(!format!("{:?}", error).contains(SECRET_API_KEY));
+        assert!(!generate_error.message.contains(SECRET_API_KEY));
+        assert!(!format!("{}", client).contains(SECRET_API_KEY));
+        assert!(
+            !format!("{:?}", crate::v1::auth::ApiKeyProvider::new(SECRET_API_KEY))
+                .contains(SECRET_API_KEY)
+        );
+        let lines = captured.0.lock().unwrap();
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("gen_ai.operation.name=\"generate_content\"")));
+        assert!(lines.iter().any(|line| line.contains("error.type")));
+        for line in lines.iter() {
+            assert!(!line.contains(SECRET_API_KEY), "{}", line);
+        }
+    }
+
     #[test]
-    fn test_url_new() {
-        let model = Model::default();
-        let api_key = String::from("my-api-key");
-        let url = Url::new(&model, api_key.clone(), &ResponseType::GenerateContent);
+    fn test_url_new_for_tuned_model() {
+        let model = Model::from("tunedModels/my-tuned-model");
+        let url = Url::new(&model, &ResponseType::GenerateContent);
 
         assert_eq!(
             url.url,
             format!(
-                "{}/models/{}:generateContent?key={}",
-                PUBLIC_API_URL_BASE, model, api_key
+                "{}/tunedModels/my-tuned-model:generateContent",
+                PUBLIC_API_URL_BASE
+            )
+        );
+    }
+
+    #[test]
+    fn test_operation_url_ignores_the_client_response_type() {
+        let client = Client::new_from_model_response_type(
+            Model::Gemini1_5Flash,
+            "my-api-key".to_string(),
+            ResponseType::StreamGenerateContent,
+        );
+
+        assert_eq!(
+            client.operation_url(&ResponseType::CountTokens),
+            format!(
+                "{}/models/gemini-1.5-flash:countTokens",
+                PUBLIC_API_URL_BASE
             )
         );
     }
+
+    #[test]
+    fn test_operation_urls_for_a_single_client() {
+        let client = Client::new_from_model(Model::TextEmbedding004, "my-api-key".to_string());
+        let base = format!("{}/models/text-embedding-004", PUBLIC_API_URL_BASE);
+
+        assert_eq!(
+            client.operation_url(&ResponseType::GenerateContent),
+            format!("{}:generateContent", base)
+        );
+        assert_eq!(
+            client.operation_url(&ResponseType::StreamGenerateContent),
+            format!("{}:streamGenerateContent", base)
+        );
+        assert_eq!(
+            client.operation_url(&ResponseType::EmbedContent),
+            format!("{}:embedContent", base)
+        );
+        assert_eq!(
+            client.operation_url(&ResponseType::BatchEmbedContents),
+            format!("{}:batchEmbedContents", base)
+        );
+        assert_eq!(client.operation_url(&ResponseType::GetModel), base.clone());
+        assert_eq!(
+            client.operation_url(&ResponseType::GetModelList),
+            format!("{}/models", PUBLIC_API_URL_BASE)
+        );
+    }
+
+    #[test]
+    fn test_embed_request_body() {
+        let mut request = EmbedContentRequest::new("Hello");
+        request.task_type = Some(crate::v1::gemini::request::TaskType::RetrievalQuery);
+        request.model = Some(Model::TextEmbedding004.resource_name());
+
+        let body = serde_json::to_value(BatchEmbedContentsRequest {
+            requests: &[request],
+        })
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "requests": [{
+                    "model": "models/text-embedding-004",
+                    "content": {"role": "user", "parts": [{"text": "Hello"}]},
+                    "taskType": "RETRIEVAL_QUERY"
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_count_tokens_request_bodies() {
+        let request = Request::new(
+            vec![Content {
+                role: crate::v1::gemini::Role::User,
+                parts: vec![crate::v1::gemini::Part {
+                    text: Some("Hello".to_string()),
+                    ..Default::default()
+                }],
+            }],
+            vec![Tools::google_search()],
+            vec![],
+            None,
+        );
+
+        let public = serde_json::to_value(CountTokensRequest {
+            generate_content_request: GenerateContentRequest {
+                model: Model::Gemini1_5Flash.resource_name(),
+                request: &request,
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            public,
+            serde_json::json!({
+                "generateContentRequest": {
+                    "model": "models/gemini-1.5-flash",
+                    "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+                    "tools": [{"googleSearch": {}}]
+                }
+            })
+        );
+
+        let vertex = serde_json::to_value(VertexCountTokensRequest::from(&request)).unwrap();
+        assert_eq!(
+            vertex,
+            serde_json::json!({
+                "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+                "tools": [{"googleSearch": {}}]
+            })
+        );
+    }
+
+    #[test]
+    fn test_url_new() {
+        let model = Model::default();
+        let url = Url::new(&model, &ResponseType::GenerateContent);
+
+        // The API key is sent in a header, never in the URL
+        assert_eq!(
+            url.url,
+            format!("{}/models/{}:generateContent", PUBLIC_API_URL_BASE, model)
+        );
+    }
 }
@@ -0,0 +1,640 @@
+//! Pluggable credentials for the API client.
+//!
+//! A [`CredentialProvider`] is asked for a [`Credential`] before every request, so providers can rotate keys and
+//! tokens. The built-in providers cover API keys, static bearer tokens, GCP application default credentials (ADC),
+//! service account JSON files and user callbacks, e.g. to fetch tokens from a secret broker.
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use reqwest::header::HeaderValue;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+use zeroize::Zeroize;
+
+use crate::v1::errors::GoogleAPIError;
+
+const GCP_API_AUTH_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// The header used to send an API key, so that it is not part of the URL.
+pub(crate) const API_KEY_HEADER: &str = "x-goog-api-key";
+
+/// A secret, e.g. an API key or access token.
+///
+/// The secret is zeroed when dropped and is masked by `Debug` and `Display`, so it cannot leak into logs or error
+/// messages by accident. Use [`SecretString::expose_secret`] to read it.
+#[derive(Clone)]
+pub struct SecretString(String);
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        SecretString(secret)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString(secret)
+    }
+}
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString(secret.to_string())
+    }
+}
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+/// Ensuring there is no leakage of secrets
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("*************")
+    }
+}
+/// Ensuring there is no leakage of secrets
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("*************")
+    }
+}
+
+/// A credential to authenticate a request with.
+#[derive(Debug, Clone)]
+pub enum Credential {
+    /// An API key, sent in the `x-goog-api-key` header.
+    ApiKey(SecretString),
+    /// An OAuth2 access token, sent in the `Authorization: Bearer` header.
+    Bearer(SecretString),
+}
+impl Credential {
+    /// Adds the credential to a request, as a sensitive header so it is never logged.
+    pub(crate) fn apply(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        match self {
+            Credential::ApiKey(key) => match HeaderValue::from_str(key.expose_secret()) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    request_builder.header(API_KEY_HEADER, value)
+                }
+                // An invalid key fails when the request is built; the error does not contain the key
+                Err(_) => request_builder.header(API_KEY_HEADER, key.expose_secret()),
+            },
+            Credential::Bearer(token) => request_builder.bearer_auth(token.expose_secret()),
+        }
+    }
+}
+
+/// Provides the credential for each request.
+///
+/// Implement this to plug in your own source of credentials:
+/// ```
+/// use async_trait::async_trait;
+/// use google_generative_ai_rs::v1::auth::{Credential, CredentialProvider};
+/// use google_generative_ai_rs::v1::errors::GoogleAPIError;
+///
+/// struct SecretBroker;
+///
+/// #[async_trait]
+/// impl CredentialProvider for SecretBroker {
+///     async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+///         Ok(Credential::Bearer("token-from-the-broker".into()))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError>;
+
+    /// Discards any cached credential, so the next call to [`CredentialProvider::credential`] fetches a fresh one.
+    /// Called by the client when a request is rejected with HTTP 401.
+    fn invalidate(&self) {}
+
+    /// Returns true if the provider may return a different credential after [`CredentialProvider::invalidate`].
+    /// Only then does the client retry a request rejected with HTTP 401, as a fixed credential would be rejected again.
+    fn can_refresh(&self) -> bool {
+        false
+    }
+}
+
+/// Sends a fixed API key in the `x-goog-api-key` header.
+#[derive(Debug)]
+pub struct ApiKeyProvider {
+    api_key: SecretString,
+}
+impl ApiKeyProvider {
+    pub fn new(api_key: impl Into<SecretString>) -> Self {
+        ApiKeyProvider {
+            api_key: api_key.into(),
+        }
+    }
+}
+#[async_trait]
+impl CredentialProvider for ApiKeyProvider {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        Ok(Credential::ApiKey(self.api_key.clone()))
+    }
+}
+
+/// Sends a fixed bearer token, e.g. one from `gcloud auth print-access-token`.
+#[derive(Debug)]
+pub struct StaticTokenProvider {
+    token: SecretString,
+}
+impl StaticTokenProvider {
+    pub fn new(token: impl Into<SecretString>) -> Self {
+        StaticTokenProvider {
+            token: token.into(),
+        }
+    }
+}
+#[async_trait]
+impl CredentialProvider for StaticTokenProvider {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        Ok(Credential::Bearer(self.token.clone()))
+    }
+}
+
+/// Gets bearer tokens from the GCP application default credentials (ADC), see [`gcp_auth::provider`].
+///
+/// Tokens are cached until shortly before they expire. Each refresh, including the one after
+/// [`CredentialProvider::invalidate`], creates a new ADC provider, as its own cache would return the same token until
+/// it is about to expire.
+///
+/// To use ADC locally, authenticate with:
+/// ```text
+/// gcloud auth application-default login
+/// ```
+pub struct GcpAuthProvider {
+    factory: TokenProviderFactory,
+    cache: TokenCache,
+}
+impl Default for GcpAuthProvider {
+    fn default() -> Self {
+        GcpAuthProvider {
+            factory: Box::new(|| {
+                Box::pin(async {
+                    gcp_auth::provider().await.map_err(|e| GoogleAPIError {
+                        message: format!("Failed to create AuthenticationManager: {}", e),
+                        code: None,
+                        rate_limit: None,
+                    })
+                })
+            }),
+            cache: TokenCache::default(),
+        }
+    }
+}
+impl GcpAuthProvider {
+    pub fn new() -> Self {
+        GcpAuthProvider::default()
+    }
+
+    /// Sets how long before expiry a cached token is refreshed, [`DEFAULT_REFRESH_MARGIN`] by default.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.cache.refresh_margin = refresh_margin;
+        self
+    }
+}
+#[async_trait]
+impl CredentialProvider for GcpAuthProvider {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        self.cache
+            .get_or_refresh(|| fetch_new_token(&self.factory))
+            .await
+    }
+
+    fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    fn can_refresh(&self) -> bool {
+        true
+    }
+}
+
+/// Gets bearer tokens for a service account, from its JSON key file.
+///
+/// Tokens are cached until shortly before they expire. Each refresh reads the key file again, so a rotated key is
+/// picked up, and gets a new token rather than the one cached by [`gcp_auth::CustomServiceAccount`].
+pub struct ServiceAccountFileProvider {
+    factory: TokenProviderFactory,
+    cache: TokenCache,
+}
+impl ServiceAccountFileProvider {
+    /// Errors if the key file cannot be read.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, GoogleAPIError> {
+        let path = path.as_ref().to_path_buf();
+        read_service_account(&path)?;
+        Ok(ServiceAccountFileProvider {
+            factory: Box::new(move || {
+                let path = path.clone();
+                Box::pin(async move {
+                    let provider: Arc<dyn gcp_auth::TokenProvider> =
+                        Arc::new(read_service_account(&path)?);
+                    Ok(provider)
+                })
+            }),
+            cache: TokenCache::default(),
+        })
+    }
+
+    /// Sets how long before expiry a cached token is refreshed, [`DEFAULT_REFRESH_MARGIN`] by default.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.cache.refresh_margin = refresh_margin;
+        self
+    }
+}
+#[async_trait]
+impl CredentialProvider for ServiceAccountFileProvider {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        self.cache
+            .get_or_refresh(|| fetch_new_token(&self.factory))
+            .await
+    }
+
+    fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    fn can_refresh(&self) -> bool {
+        true
+    }
+}
+
+fn read_service_account(path: &Path) -> Result<gcp_auth::CustomServiceAccount, GoogleAPIError> {
+    gcp_auth::CustomServiceAccount::from_file(path).map_err(|e| GoogleAPIError {
+        message: format!(
+            "Failed to read service account from {}: {}",
+            path.display(),
+            e
+        ),
+        code: None,
+        rate_limit: None,
+    })
+}
+
+/// Gets the credential from a user callback, called before every request.
+pub struct CallbackProvider {
+    callback: Box<dyn Fn() -> BoxFuture<'static, Result<Credential, GoogleAPIError>> + Send + Sync>,
+}
+impl CallbackProvider {
+    /// Parameters:
+    /// * callback - returns a future resolving to the credential to use
+    pub fn new<F, Fut>(callback: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Credential, GoogleAPIError>> + Send + 'static,
+    {
+        CallbackProvider {
+            callback: Box::new(move || Box::pin(callback())),
+        }
+    }
+}
+#[async_trait]
+impl CredentialProvider for CallbackProvider {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        (self.callback)().await
+    }
+
+    /// The callback may return a new credential on every call.
+    fn can_refresh(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl<T: CredentialProvider + ?Sized> CredentialProvider for Arc<T> {
+    async fn credential(&self) -> Result<Credential, GoogleAPIError> {
+        self.as_ref().credential().await
+    }
+
+    fn invalidate(&self) {
+        self.as_ref().invalidate();
+    }
+
+    fn can_refresh(&self) -> bool {
+        self.as_ref().can_refresh()
+    }
+}
+
+/// How long before expiry a cached access token is refreshed by the GCP providers, unless set with
+/// [`GcpAuthProvider::with_refresh_margin`] or [`ServiceAccountFileProvider::with_refresh_margin`].
+///
+/// A token issued with less time left, e.g. one cached by the GCE metadata server, is used until
+/// [`MIN_REFRESH_MARGIN`] before it expires instead, so that requests do not refresh it over and over.
+pub const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// How long before expiry a cached access token is always refreshed, as `gcp_auth` does.
+pub const MIN_REFRESH_MARGIN: Duration = Duration::from_secs(20);
+
+/// Creates a `gcp_auth` token provider.
+type TokenProviderFactory = Box<
+    dyn Fn() -> BoxFuture<'static, Result<Arc<dyn gcp_auth::TokenProvider>, GoogleAPIError>>
+        + Send
+        + Sync,
+>;
+
+/// A bearer token and when it expires.
+struct CachedToken {
+    token: SecretString,
+    expires_at: Instant,
+}
+
+/// A cached token and when to refresh it.
+struct StoredToken {
+    token: SecretString,
+    refresh_at: Instant,
+}
+
+/// Caches a bearer token until shortly before it expires.
+/// Concurrent requests that find the token stale wait for a single refresh, rather than each fetching a token.
+struct TokenCache {
+    refresh_margin: Duration,
+    token: std::sync::Mutex<Option<StoredToken>>,
+    refresh: tokio::sync::Mutex<()>,
+}
+impl Default for TokenCache {
+    fn default() -> Self {
+        TokenCache {
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            token: std::sync::Mutex::new(None),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+impl TokenCache {
+    /// Returns the cached token if it is not about to expire, else refreshes it.
+    async fn get_or_refresh<F, Fut>(&self, refresh: F) -> Result<Credential, GoogleAPIError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CachedToken, GoogleAPIError>>,
+    {
+        if let Some(token) = self.fresh_token() {
+            return Ok(Credential::Bearer(token));
+        }
+
+        let _refreshing = self.refresh.lock().await;
+        // A concurrent request may have refreshed the token while this one waited
+        if let Some(token) = self.fresh_token() {
+            return Ok(Credential::Bearer(token));
+        }
+
+        let cached = refresh().await?;
+        let token = cached.token.clone();
+        let refresh_at = self.refresh_at(cached.expires_at);
+        *self.lock_token() = Some(StoredToken {
+            token: cached.token,
+            refresh_at,
+        });
+        Ok(Credential::Bearer(token))
+    }
+
+    /// Returns when to refresh a token fetched now: `refresh_margin` before it expires, or [`MIN_REFRESH_MARGIN`]
+    /// before if it expires sooner than that.
+    fn refresh_at(&self, expires_at: Instant) -> Instant {
+        let now = Instant::now();
+        expires_at
+            .checked_sub(self.refresh_margin)
+            .filter(|refresh_at| *refresh_at > now)
+            .or_else(|| expires_at.checked_sub(MIN_REFRESH_MARGIN))
+            .unwrap_or(now)
+    }
+
+    fn fresh_token(&self) -> Option<SecretString> {
+        self.lock_token()
+            .as_ref()
+            .filter(|stored| Instant::now() < stored.refresh_at)
+            .map(|stored| stored.token.clone())
+    }
+
+    fn invalidate(&self) {
+        *self.lock_token() = None;
+    }
+
+    fn lock_token(&self) -> std::sync::MutexGuard<'_, Option<StoredToken>> {
+        // The cached token is always left valid, so it can be used even if another thread panicked
+        self.token.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Gets a token from a new `gcp_auth` token provider, so that it is not a token cached by a previous one.
+async fn fetch_new_token(factory: &TokenProviderFactory) -> Result<CachedToken, GoogleAPIError> {
+    let provider = factory().await?;
+    fetch_token(provider.as_ref()).await
+}
+
+/// Gets a cloud-platform scoped token from a `gcp_auth` token provider.
+async fn fetch_token(
+    provider: &dyn gcp_auth::TokenProvider,
+) -> Result<CachedToken, GoogleAPIError> {
+    let token = provider
+        .token(&[GCP_API_AUTH_SCOPE])
+        .await
+        .map_err(|e| GoogleAPIError {
+            message: format!("Failed to generate authentication token: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let expires_in = token.expires_at().timestamp().saturating_sub(now).max(0) as u64;
+
+    Ok(CachedToken {
+        token: token.as_str().into(),
+        expires_at: Instant::now()
+            .checked_add(Duration::from_secs(expires_in))
+            .unwrap_or_else(Instant::now),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(credential: &Credential) -> reqwest::header::HeaderMap {
+        let request = credential
+            .apply(reqwest::Client::new().get("https://example.com"))
+            .build()
+            .unwrap();
+        request.headers().clone()
+    }
+
+    #[tokio::test]
+    async fn test_api_key_is_sent_in_a_header() {
+        let credential = ApiKeyProvider::new("my-api-key".to_string())
+            .credential()
+            .await
+            .unwrap();
+
+        let headers = headers(&credential);
+        assert_eq!(headers[API_KEY_HEADER], "my-api-key");
+        assert!(headers.get(reqwest::header::AUTHORIZATION).is_none());
+        assert_eq!(format!("{:?}", credential), "ApiKey(*************)");
+    }
+
+    #[tokio::test]
+    async fn test_callback_provider_is_called_for_each_credential() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let provider = CallbackProvider::new(move || {
+            let call = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move { Ok(Credential::Bearer(format!("token-{}", call).into())) }
+        });
+
+        let first = provider.credential().await.unwrap();
+        let second = provider.credential().await.unwrap();
+
+        assert_eq!(
+            headers(&first)[reqwest::header::AUTHORIZATION],
+            "Bearer token-0"
+        );
+        assert_eq!(
+            headers(&second)[reqwest::header::AUTHORIZATION],
+            "Bearer token-1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_cache_refreshes_once_for_concurrent_requests() {
+        let cache = TokenCache::default();
+        let fetches = std::sync::atomic::AtomicUsize::new(0);
+        let refresh = || async {
+            let fetch = fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(CachedToken {
+                token: format!("token-{}", fetch).into(),
+                expires_at: Instant::now() + Duration::from_secs(3600),
+            })
+        };
+
+        let credentials =
+            futures::future::join_all((0..10).map(|_| cache.get_or_refresh(refresh))).await;
+
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 1);
+        for credential in credentials {
+            assert_eq!(
+                headers(&credential.unwrap())[reqwest::header::AUTHORIZATION],
+                "Bearer token-0"
+            );
+        }
+
+        // A forced refresh, e.g. after a 401, fetches a new token
+        cache.invalidate();
+        let credential = cache.get_or_refresh(refresh).await.unwrap();
+        assert_eq!(
+            headers(&credential)[reqwest::header::AUTHORIZATION],
+            "Bearer token-1"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_cache_refreshes_tokens_about_to_expire() {
+        let mut cache = TokenCache::default();
+        let fetches = std::sync::atomic::AtomicUsize::new(0);
+        let refresh = |lifetime: u64| {
+            fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                Ok(CachedToken {
+                    token: "token".into(),
+                    expires_at: Instant::now() + Duration::from_secs(lifetime),
+                })
+            }
+        };
+        let fetched = || fetches.load(std::sync::atomic::Ordering::SeqCst);
+
+        // Refreshed 5 minutes before it expires
+        cache.get_or_refresh(|| refresh(3600)).await.unwrap();
+        tokio::time::advance(Duration::from_secs(3600 - 301)).await;
+        cache.get_or_refresh(|| refresh(60)).await.unwrap();
+        assert_eq!(fetched(), 1);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        cache.get_or_refresh(|| refresh(60)).await.unwrap();
+        assert_eq!(fetched(), 2);
+
+        // Issued within the refresh margin, so used until 20 seconds before it expires
+        cache.get_or_refresh(|| refresh(60)).await.unwrap();
+        tokio::time::advance(Duration::from_secs(39)).await;
+        cache.get_or_refresh(|| refresh(60)).await.unwrap();
+        assert_eq!(fetched(), 2);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        cache.get_or_refresh(|| refresh(60)).await.unwrap();
+        assert_eq!(fetched(), 3);
+
+        // A margin longer than any token does not overflow
+        cache.refresh_margin = Duration::MAX;
+        cache.invalidate();
+        cache.get_or_refresh(|| refresh(3600)).await.unwrap();
+        cache.get_or_refresh(|| refresh(3600)).await.unwrap();
+        assert_eq!(fetched(), 4);
+    }
+
+    /// Returns the same token for its lifetime, like the `gcp_auth` providers, which cache their token.
+    struct CachingTokenProvider(Arc<gcp_auth::Token>);
+    #[async_trait]
+    impl gcp_auth::TokenProvider for CachingTokenProvider {
+        async fn token(&self, _scopes: &[&str]) -> Result<Arc<gcp_auth::Token>, gcp_auth::Error> {
+            Ok(self.0.clone())
+        }
+        async fn project_id(&self) -> Result<Arc<str>, gcp_auth::Error> {
+            Ok("my-project".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gcp_provider_gets_a_new_token_after_invalidate() {
+        let created = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = created.clone();
+        let provider = GcpAuthProvider {
+            factory: Box::new(move || {
+                let index = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Box::pin(async move {
+                    let token: gcp_auth::Token = serde_json::from_value(serde_json::json!({
+                        "access_token": format!("token-{}", index),
+                        "expires_in": 3600
+                    }))
+                    .unwrap();
+                    let provider: Arc<dyn gcp_auth::TokenProvider> =
+                        Arc::new(CachingTokenProvider(Arc::new(token)));
+                    Ok(provider)
+                })
+            }),
+            cache: TokenCache::default(),
+        };
+        let bearer = |credential: Credential| {
+            headers(&credential)[reqwest::header::AUTHORIZATION]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let first = bearer(provider.credential().await.unwrap());
+        let cached = bearer(provider.credential().await.unwrap());
+        provider.invalidate();
+        let refreshed = bearer(provider.credential().await.unwrap());
+
+        assert_eq!(first, "Bearer token-0");
+        assert_eq!(cached, first);
+        assert_eq!(refreshed, "Bearer token-1");
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_service_account_file_provider_reports_missing_file() {
+        let error = ServiceAccountFileProvider::new("/does/not/exist.json")
+            .err()
+            .unwrap();
+        assert!(error.message.contains("/does/not/exist.json"));
+    }
+}
@@ -0,0 +1,670 @@
+//! A backend-agnostic seam over the Gemini operations.
+//!
+//! Code that depends on [`GenerativeBackend`] rather than on [`Client`] can run against the public API
+//! ([`PublicApi`]), Vertex AI ([`VertexAi`]) or, in unit tests, the in-memory [`FakeBackend`].
+//!
+//! ```
+//! use google_generative_ai_rs::v1::backend::{FakeBackend, GenerativeBackend};
+//! use google_generative_ai_rs::v1::gemini::{request::Request, Content, Part, Role};
+//!
+//! async fn summarise(backend: &dyn GenerativeBackend, text: &str) -> String {
+//!     let request = Request {
+//!         contents: vec![Content {
+//!             role: Role::User,
+//!             parts: vec![Part {
+//!                 text: Some(format!("Summarise: {}", text)),
+//!                 ..Default::default()
+//!             }],
+//!         }],
+//!         tools: vec![],
+//!         safety_settings: vec![],
+//!         generation_config: None,
+//!         #[cfg(feature = "beta")]
+//!         system_instruction: None,
+//!     };
+//!     match backend.generate_content(30, &request).await {
+//!         Ok(response) => response.get_text(),
+//!         Err(_) => String::new(),
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let fake = FakeBackend::new();
+//! fake.push_text("A short summary.");
+//! assert_eq!(summarise(&fake, "A long text.").await, "A short summary.");
+//! assert_eq!(fake.requests().len(), 1);
+//! # });
+//! ```
+use async_trait::async_trait;
+use futures::stream;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::v1::api::Client;
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::{EmbedContentRequest, Request};
+use crate::v1::gemini::response::{
+    Candidate, ContentEmbedding, GeminiResponse, StreamedGeminiResponse, TokenCount,
+};
+use crate::v1::gemini::{Content, Model, ModelInformation, Part, ResponseType, Role};
+
+/// The Gemini operations, independent of where they are served from.
+///
+/// Parameters:
+/// * timeout - the timeout in seconds
+#[async_trait]
+pub trait GenerativeBackend: Send + Sync {
+    /// Generates a response, i.e., not streamed.
+    async fn generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<GeminiResponse, GoogleAPIError>;
+
+    /// Generates a streamed response.
+    async fn stream_generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<StreamedGeminiResponse, GoogleAPIError>;
+
+    /// Counts the tokens of a request.
+    async fn count_tokens(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<TokenCount, GoogleAPIError>;
+
+    /// Embeds a content.
+    async fn embed(
+        &self,
+        timeout: u64,
+        embed_request: &EmbedContentRequest,
+    ) -> Result<ContentEmbedding, GoogleAPIError>;
+
+    /// Embeds several contents. By default, each content is embedded in turn.
+    async fn batch_embed(
+        &self,
+        timeout: u64,
+        embed_requests: &[EmbedContentRequest],
+    ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+        let mut embeddings = Vec::with_capacity(embed_requests.len());
+        for embed_request in embed_requests {
+            embeddings.push(self.embed(timeout, embed_request).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Gets the information about the model.
+    async fn get_model(&self, timeout: u64) -> Result<ModelInformation, GoogleAPIError>;
+}
+
+#[async_trait]
+impl GenerativeBackend for Client {
+    async fn generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<GeminiResponse, GoogleAPIError> {
+        Client::generate_content(self, timeout, api_request).await
+    }
+
+    async fn stream_generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<StreamedGeminiResponse, GoogleAPIError> {
+        Client::stream_generate_content(self, timeout, api_request).await
+    }
+
+    async fn count_tokens(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<TokenCount, GoogleAPIError> {
+        Client::count_tokens(self, timeout, api_request).await
+    }
+
+    async fn embed(
+        &self,
+        timeout: u64,
+        embed_request: &EmbedContentRequest,
+    ) -> Result<ContentEmbedding, GoogleAPIError> {
+        Client::embed(self, timeout, embed_request).await
+    }
+
+    async fn batch_embed(
+        &self,
+        timeout: u64,
+        embed_requests: &[EmbedContentRequest],
+    ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+        Client::batch_embed(self, timeout, embed_requests).await
+    }
+
+    async fn get_model(&self, timeout: u64) -> Result<ModelInformation, GoogleAPIError> {
+        Client::get_model(self, timeout).await
+    }
+}
+
+#[async_trait]
+impl<T: GenerativeBackend + ?Sized> GenerativeBackend for Arc<T> {
+    async fn generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<GeminiResponse, GoogleAPIError> {
+        self.as_ref().generate_content(timeout, api_request).await
+    }
+
+    async fn stream_generate_content(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<StreamedGeminiResponse, GoogleAPIError> {
+        self.as_ref()
+            .stream_generate_content(timeout, api_request)
+            .await
+    }
+
+    async fn count_tokens(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<TokenCount, GoogleAPIError> {
+        self.as_ref().count_tokens(timeout, api_request).await
+    }
+
+    async fn embed(
+        &self,
+        timeout: u64,
+        embed_request: &EmbedContentRequest,
+    ) -> Result<ContentEmbedding, GoogleAPIError> {
+        self.as_ref().embed(timeout, embed_request).await
+    }
+
+    async fn batch_embed(
+        &self,
+        timeout: u64,
+        embed_requests: &[EmbedContentRequest],
+    ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+        self.as_ref().batch_embed(timeout, embed_requests).await
+    }
+
+    async fn get_model(&self, timeout: u64) -> Result<ModelInformation, GoogleAPIError> {
+        self.as_ref().get_model(timeout).await
+    }
+}
+
+/// Implements [`GenerativeBackend`] for a wrapper by delegating to its [`Client`].
+macro_rules! delegate_to_client {
+    ($backend:ty) => {
+        #[async_trait]
+        impl GenerativeBackend for $backend {
+            async fn generate_content(
+                &self,
+                timeout: u64,
+                api_request: &Request,
+            ) -> Result<GeminiResponse, GoogleAPIError> {
+                self.0.generate_content(timeout, api_request).await
+            }
+
+            async fn stream_generate_content(
+                &self,
+                timeout: u64,
+                api_request: &Request,
+            ) -> Result<StreamedGeminiResponse, GoogleAPIError> {
+                self.0.stream_generate_content(timeout, api_request).await
+            }
+
+            async fn count_tokens(
+                &self,
+                timeout: u64,
+                api_request: &Request,
+            ) -> Result<TokenCount, GoogleAPIError> {
+                self.0.count_tokens(timeout, api_request).await
+            }
+
+            async fn embed(
+                &self,
+                timeout: u64,
+                embed_request: &EmbedContentRequest,
+            ) -> Result<ContentEmbedding, GoogleAPIError> {
+                self.0.embed(timeout, embed_request).await
+            }
+
+            async fn batch_embed(
+                &self,
+                timeout: u64,
+                embed_requests: &[EmbedContentRequest],
+            ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+                self.0.batch_embed(timeout, embed_requests).await
+            }
+
+            async fn get_model(&self, timeout: u64) -> Result<ModelInformation, GoogleAPIError> {
+                self.0.get_model(timeout).await
+            }
+        }
+    };
+}
+
+/// The public Gemini API, authenticated with an API key.
+pub struct PublicApi(Client);
+impl PublicApi {
+    pub fn new(model: Model, api_key: String) -> Self {
+        PublicApi(Client::new_from_model(model, api_key))
+    }
+    /// Returns the underlying client, e.g. to configure it with [`Client::with_base_url`].
+    pub fn into_client(self) -> Client {
+        self.0
+    }
+    pub fn client(&self) -> &Client {
+        &self.0
+    }
+}
+impl TryFrom<Client> for PublicApi {
+    type Error = GoogleAPIError;
+
+    fn try_from(client: Client) -> Result<Self, Self::Error> {
+        if client.is_vertex() {
+            return Err(GoogleAPIError {
+                message: "The client is configured for Vertex AI".to_string(),
+                code: None,
+                rate_limit: None,
+            });
+        }
+        Ok(PublicApi(client))
+    }
+}
+delegate_to_client!(PublicApi);
+
+/// Vertex AI, authenticated with GCP credentials.
+pub struct VertexAi(Client);
+impl VertexAi {
+    pub fn new(model: Model, region: String, project_id: String) -> Self {
+        VertexAi(Client::new_from_model_region_project_id(
+            model, region, project_id,
+        ))
+    }
+    /// Returns the underlying client, e.g. to configure it with [`Client::with_publisher`].
+    pub fn into_client(self) -> Client {
+        self.0
+    }
+    pub fn client(&self) -> &Client {
+        &self.0
+    }
+}
+impl TryFrom<Client> for VertexAi {
+    type Error = GoogleAPIError;
+
+    fn try_from(client: Client) -> Result<Self, Self::Error> {
+        if !client.is_vertex() {
+            return Err(GoogleAPIError {
+                message: "The client is not configured for Vertex AI".to_string(),
+                code: None,
+                rate_limit: None,
+            });
+        }
+        Ok(VertexAi(client))
+    }
+}
+delegate_to_client!(VertexAi);
+
+/// A scripted reply of the [`FakeBackend`].
+#[derive(Debug)]
+pub enum FakeReply {
+    Generate(GeminiResponse),
+    /// The chunks of a streamed response.
+    Stream(Vec<GeminiResponse>),
+    Count(TokenCount),
+    Embedding(ContentEmbedding),
+    Model(ModelInformation),
+    Error(GoogleAPIError),
+    /// Returns the reply after a delay, e.g. to simulate latency.
+    Delayed(Duration, Box<FakeReply>),
+}
+impl FakeReply {
+    fn name(&self) -> &'static str {
+        match self {
+            FakeReply::Delayed(_, reply) => reply.name(),
+            FakeReply::Generate(_) => "Generate",
+            FakeReply::Stream(_) => "Stream",
+            FakeReply::Count(_) => "Count",
+            FakeReply::Embedding(_) => "Embedding",
+            FakeReply::Model(_) => "Model",
+            FakeReply::Error(_) => "Error",
+        }
+    }
+}
+
+/// A call recorded by the [`FakeBackend`].
+#[derive(Debug, Clone)]
+pub enum FakeCall {
+    GenerateContent(Request),
+    StreamGenerateContent(Request),
+    CountTokens(Request),
+    EmbedContent(EmbedContentRequest),
+    GetModel,
+}
+
+/// An in-memory [`GenerativeBackend`] that replies from a script, for unit testing prompt logic without HTTP.
+///
+/// Replies are returned in the order they are pushed, whatever the operation. A call fails if the next reply does not
+/// match the operation, or if the script is exhausted. [`FakeReply::Error`] is returned to any operation.
+/// A call takes its reply as soon as it is made, so concurrent calls get the replies in the order they were made,
+/// even if the replies are [delayed](FakeReply::Delayed).
+/// Every call is recorded, see [`FakeBackend::calls`].
+#[derive(Default)]
+pub struct FakeBackend {
+    replies: Mutex<VecDeque<FakeReply>>,
+    calls: Mutex<Vec<FakeCall>>,
+}
+impl fmt::Debug for FakeBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FakeBackend")
+            .field("replies", &self.replies.lock().unwrap().len())
+            .field("calls", &self.calls.lock().unwrap().len())
+            .finish()
+    }
+}
+impl FakeBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a reply to the script.
+    pub fn push(&self, reply: FakeReply) -> &Self {
+        self.replies.lock().unwrap().push_back(reply);
+        self
+    }
+    pub fn push_response(&self, response: GeminiResponse) -> &Self {
+        self.push(FakeReply::Generate(response))
+    }
+    /// Appends a response with a single candidate answering `text`.
+    pub fn push_text(&self, text: &str) -> &Self {
+        self.push_response(Self::text_response(text))
+    }
+    /// Appends a streamed response with a chunk per text.
+    pub fn push_stream(&self, texts: &[&str]) -> &Self {
+        self.push(FakeReply::Stream(
+            texts.iter().map(|text| Self::text_response(text)).collect(),
+        ))
+    }
+    pub fn push_token_count(&self, total_tokens: u64) -> &Self {
+        self.push(FakeReply::Count(TokenCount {
+            total_tokens,
+            ..Default::default()
+        }))
+    }
+    pub fn push_embedding(&self, values: Vec<f32>) -> &Self {
+        self.push(FakeReply::Embedding(ContentEmbedding { values }))
+    }
+    pub fn push_model(&self, model: ModelInformation) -> &Self {
+        self.push(FakeReply::Model(model))
+    }
+    pub fn push_error(&self, error: GoogleAPIError) -> &Self {
+        self.push(FakeReply::Error(error))
+    }
+    /// Appends a reply returned after a delay.
+    pub fn push_delayed(&self, delay: Duration, reply: FakeReply) -> &Self {
+        self.push(FakeReply::Delayed(delay, Box::new(reply)))
+    }
+
+    /// Returns the calls made so far, in order.
+    pub fn calls(&self) -> Vec<FakeCall> {
+        self.calls.lock().unwrap().clone()
+    }
+    /// Returns the generate, stream and count requests made so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                FakeCall::GenerateContent(request)
+                | FakeCall::StreamGenerateContent(request)
+                | FakeCall::CountTokens(request) => Some(request),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Returns the number of scripted replies that have not been used.
+    pub fn remaining(&self) -> usize {
+        self.replies.lock().unwrap().len()
+    }
+
+    /// Builds a response with a single candidate answering `text`.
+    pub fn text_response(text: &str) -> GeminiResponse {
+        GeminiResponse {
+            candidates: vec![Candidate {
+                content: Content {
+                    role: Role::Model,
+                    parts: vec![Part {
+                        text: Some(text.to_string()),
+                        ..Default::default()
+                    }],
+                },
+                finish_reason: Some("STOP".to_string()),
+                index: Some(0),
+                safety_ratings: vec![],
+                citation_metadata: None,
+                grounding_metadata: None,
+                avg_logprobs: None,
+                logprobs_result: None,
+            }],
+            prompt_feedback: None,
+            usage_metadata: None,
+            model_version: None,
+            response_id: None,
+        }
+    }
+
+    /// Records the call and pops the next reply, which must be an error or match the operation.
+    async fn next_reply(
+        &self,
+        call: FakeCall,
+        operation: ResponseType,
+    ) -> Result<FakeReply, GoogleAPIError> {
+        self.calls.lock().unwrap().push(call);
+        let mut reply = self.replies.lock().unwrap().pop_front();
+        while let Some(FakeReply::Delayed(delay, delayed)) = reply {
+            tokio::time::sleep(delay).await;
+            reply = Some(*delayed);
+        }
+        let operation_name = match operation {
+            ResponseType::GetModel => "getModel".to_string(),
+            _ => operation.to_string(),
+        };
+        match (reply, &operation) {
+            (None, _) => Err(GoogleAPIError {
+                message: format!("FakeBackend has no scripted reply for {}", operation_name),
+                code: None,
+                rate_limit: None,
+            }),
+            (Some(FakeReply::Error(error)), _) => Err(error),
+            (Some(reply @ FakeReply::Generate(_)), ResponseType::GenerateContent)
+            | (Some(reply @ FakeReply::Stream(_)), ResponseType::StreamGenerateContent)
+            | (Some(reply @ FakeReply::Count(_)), ResponseType::CountTokens)
+            | (Some(reply @ FakeReply::Embedding(_)), ResponseType::EmbedContent)
+            | (Some(reply @ FakeReply::Model(_)), ResponseType::GetModel) => Ok(reply),
+            (Some(reply), _) => Err(GoogleAPIError {
+                message: format!(
+                    "FakeBackend expected a reply for {} but the next scripted reply is {}",
+                    operation_name,
+                    reply.name()
+                ),
+                code: None,
+                rate_limit: None,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl GenerativeBackend for FakeBackend {
+    async fn generate_content(
+        &self,
+        _timeout: u64,
+        api_request: &Request,
+    ) -> Result<GeminiResponse, GoogleAPIError> {
+        match self
+            .next_reply(
+                FakeCall::GenerateContent(api_request.clone()),
+                ResponseType::GenerateContent,
+            )
+            .await?
+        {
+            FakeReply::Generate(response) => Ok(response),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn stream_generate_content(
+        &self,
+        _timeout: u64,
+        api_request: &Request,
+    ) -> Result<StreamedGeminiResponse, GoogleAPIError> {
+        let chunks = match self
+            .next_reply(
+                FakeCall::StreamGenerateContent(api_request.clone()),
+                ResponseType::StreamGenerateContent,
+            )
+            .await?
+        {
+            FakeReply::Stream(chunks) => chunks,
+            _ => unreachable!(),
+        };
+        // The client streams the raw JSON chunks, so do the same
+        let values = chunks
+            .iter()
+            .map(|chunk| {
+                serde_json::to_value(chunk).map_err(|e| GoogleAPIError {
+                    message: format!("Failed to serialize a scripted chunk: {}", e),
+                    code: None,
+                    rate_limit: None,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(StreamedGeminiResponse {
+            response_stream: Some(Box::pin(stream::iter(values.into_iter().map(Ok)))),
+        })
+    }
+
+    async fn count_tokens(
+        &self,
+        _timeout: u64,
+        api_request: &Request,
+    ) -> Result<TokenCount, GoogleAPIError> {
+        match self
+            .next_reply(
+                FakeCall::CountTokens(api_request.clone()),
+                ResponseType::CountTokens,
+            )
+            .await?
+        {
+            FakeReply::Count(token_count) => Ok(token_count),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn embed(
+        &self,
+        _timeout: u64,
+        embed_request: &EmbedContentRequest,
+    ) -> Result<ContentEmbedding, GoogleAPIError> {
+        match self
+            .next_reply(
+                FakeCall::EmbedContent(embed_request.clone()),
+                ResponseType::EmbedContent,
+            )
+            .await?
+        {
+            FakeReply::Embedding(embedding) => Ok(embedding),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn get_model(&self, _timeout: u64) -> Result<ModelInformation, GoogleAPIError> {
+        match self
+            .next_reply(FakeCall::GetModel, ResponseType::GetModel)
+            .await?
+        {
+            FakeReply::Model(model) => Ok(model),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::v1::gemini::request::text_request;
+
+    #[tokio::test]
+    async fn test_fake_backend_replies_in_script_order() {
+        let fake = FakeBackend::new();
+        fake.push_token_count(7)
+            .push_stream(&["Once upon", " a time"])
+            .push_embedding(vec![0.5, 0.25])
+            .push_error(GoogleAPIError {
+                message: "quota exceeded".to_string(),
+                code: Some(reqwest::StatusCode::TOO_MANY_REQUESTS),
+                rate_limit: None,
+            });
+        let backend: Arc<dyn GenerativeBackend> = Arc::new(fake);
+
+        let request = text_request("Tell me a story");
+        let count = backend.count_tokens(30, &request).await.unwrap();
+        assert_eq!(count.total_tokens, 7);
+
+        let streamed = backend.stream_generate_content(30, &request).await.unwrap();
+        let chunks: Vec<GeminiResponse> = streamed
+            .response_stream
+            .unwrap()
+            .map(|value| serde_json::from_value(value.unwrap()).unwrap())
+            .collect()
+            .await;
+        let text: String = chunks.iter().map(|chunk| chunk.get_text()).collect();
+        assert_eq!(text, "Once upon a time");
+
+        let embeddings = backend
+            .batch_embed(30, &[EmbedContentRequest::new("story")])
+            .await
+            .unwrap();
+        assert_eq!(embeddings[0].values, vec![0.5, 0.25]);
+
+        let error = backend.generate_content(30, &request).await.unwrap_err();
+        assert_eq!(error.code, Some(reqwest::StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    #[tokio::test]
+    async fn test_fake_backend_records_calls_and_rejects_mismatched_replies() {
+        let fake = FakeBackend::new();
+        fake.push_text("Paris");
+
+        let error = fake
+            .count_tokens(30, &text_request("Capital of France?"))
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("countTokens"));
+        assert!(error.message.contains("Generate"));
+
+        let error = fake.get_model(30).await.unwrap_err();
+        assert!(error.message.contains("no scripted reply"));
+
+        assert_eq!(fake.remaining(), 0);
+        assert_eq!(fake.requests().len(), 1);
+        assert!(matches!(fake.calls()[1], FakeCall::GetModel));
+    }
+
+    #[test]
+    fn test_typed_backends_check_the_client_mode() {
+        let public = Client::new("my-api-key".to_string());
+        let vertex =
+            Client::new_from_region_project_id("us-central1".to_string(), "my-project".to_string());
+
+        assert!(VertexAi::try_from(public).is_err());
+        assert!(PublicApi::try_from(vertex).is_err());
+    }
+}
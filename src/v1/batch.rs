@@ -0,0 +1,816 @@
+//! The asynchronous Batch API, to generate content for large offline workloads at a reduced cost - see:
+//! "https://ai.google.dev/gemini-api/docs/batch-mode".
+//!
+//! A batch is created from requests sent inline, or from a JSON Lines file uploaded with
+//! [`Client::upload_batch_file`]. It runs as a long-running [`Operation`], which can be polled with
+//! [`Client::wait_for_batch`], listed and cancelled. Once done, [`Client::batch_results`] returns the response to each
+//! request by its key.
+//!
+//! The Batch API is only available on the public API, in `v1beta`, which is used whether or not the `beta` feature
+//! is enabled. For Vertex AI, see [`crate::v1::vertexai`].
+//!
+//! ```no_run
+//! use google_generative_ai_rs::v1::api::Client;
+//! use google_generative_ai_rs::v1::batch::{BatchInput, BatchRequest};
+//! use google_generative_ai_rs::v1::operation::OperationPoller;
+//! # use google_generative_ai_rs::v1::gemini::request::Request;
+//!
+//! # async fn run(requests: Vec<Request>) -> Result<(), google_generative_ai_rs::v1::errors::GoogleAPIError> {
+//! let client = Client::new("api-key".to_string());
+//! let requests: Vec<BatchRequest> = requests
+//!     .into_iter()
+//!     .enumerate()
+//!     .map(|(index, request)| BatchRequest::new(format!("request-{}", index), request))
+//!     .collect();
+//!
+//! let file = client.upload_batch_file(300, "evaluation", &requests).await?;
+//! let batch = client.create_batch(30, "evaluation", BatchInput::File(file.name)).await?;
+//! let batch = client.wait_for_batch(30, &batch.name, &OperationPoller::default()).await?;
+//! for result in client.batch_results(300, &batch).await? {
+//!     match result.result {
+//!         Ok(response) => println!("{}: {}", result.key, response.get_text()),
+//!         Err(error) => eprintln!("{} failed: {}", result.key, error),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use serde::{Deserialize, Serialize};
+
+use crate::v1::api::{Client, Url};
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::GeminiResponse;
+use crate::v1::gemini::ResponseType;
+use crate::v1::operation::{deserialize_int64, Operation, OperationPoller, Status};
+
+const BATCH_API_URL_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+const JSONL_MIME_TYPE: &str = "application/jsonl";
+
+/// A batch, as a long-running operation with the [`Batch`] as metadata and its output as response.
+pub type BatchOperation = Operation<Batch, BatchOutput>;
+
+/// A request of a batch, identified by a key unique within the batch.
+///
+/// This is also the format of each line of a batch input file: `{"key": "request-1", "request": {...}}`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchRequest {
+    pub key: String,
+    pub request: Request,
+}
+impl BatchRequest {
+    pub fn new(key: impl Into<String>, request: Request) -> Self {
+        BatchRequest {
+            key: key.into(),
+            request,
+        }
+    }
+
+    /// Returns the requests as the JSON Lines of a batch input file.
+    pub fn to_jsonl(requests: &[BatchRequest]) -> Result<String, GoogleAPIError> {
+        let mut jsonl = String::new();
+        for request in requests {
+            let line = serde_json::to_string(request).map_err(|e| GoogleAPIError {
+                message: format!("Failed to serialize batch request {}: {}", request.key, e),
+                code: None,
+                rate_limit: None,
+            })?;
+            jsonl.push_str(&line);
+            jsonl.push('\n');
+        }
+        Ok(jsonl)
+    }
+}
+
+/// The requests of a batch.
+#[derive(Debug, Clone)]
+pub enum BatchInput {
+    /// Requests sent inline, for batches of up to 20MB.
+    Requests(Vec<BatchRequest>),
+    /// The name of an uploaded batch input file, e.g. `files/123`, see [`Client::upload_batch_file`].
+    File(String),
+}
+
+/// The state of a batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BatchState {
+    #[default]
+    #[serde(rename = "BATCH_STATE_UNSPECIFIED")]
+    Unspecified,
+    #[serde(rename = "BATCH_STATE_PENDING")]
+    Pending,
+    #[serde(rename = "BATCH_STATE_RUNNING")]
+    Running,
+    #[serde(rename = "BATCH_STATE_SUCCEEDED")]
+    Succeeded,
+    #[serde(rename = "BATCH_STATE_FAILED")]
+    Failed,
+    #[serde(rename = "BATCH_STATE_CANCELLED")]
+    Cancelled,
+    #[serde(rename = "BATCH_STATE_EXPIRED")]
+    Expired,
+}
+impl BatchState {
+    /// Returns true if the batch will not change state anymore.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            BatchState::Succeeded
+                | BatchState::Failed
+                | BatchState::Cancelled
+                | BatchState::Expired
+        )
+    }
+}
+
+/// The metadata of a batch operation.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Batch {
+    /// The resource name, e.g. `batches/123`.
+    #[serde(default)]
+    pub name: String,
+    pub display_name: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub state: BatchState,
+    pub create_time: Option<String>,
+    pub update_time: Option<String>,
+    pub end_time: Option<String>,
+    pub batch_stats: Option<BatchStats>,
+    pub output: Option<BatchOutput>,
+}
+
+/// The progress of a batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStats {
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub request_count: i64,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub successful_request_count: i64,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub failed_request_count: i64,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub pending_request_count: i64,
+}
+
+/// Where the responses of a finished batch are: inline for inline requests, else in a file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchOutput {
+    /// The name of the JSON Lines file of the responses, e.g. `files/456`.
+    pub responses_file: Option<String>,
+    pub inlined_responses: Option<InlinedResponses>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlinedResponses {
+    #[serde(default)]
+    pub inlined_responses: Vec<InlinedResponse>,
+}
+
+/// The response to a request of a batch, or its error.
+///
+/// This is also the format of each line of a responses file, with the request key in `key` rather than in
+/// `metadata`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InlinedResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The metadata of the request, e.g. `{"key": "request-1"}`.
+    pub metadata: Option<serde_json::Value>,
+    pub response: Option<GeminiResponse>,
+    pub error: Option<Status>,
+}
+impl InlinedResponse {
+    /// Returns the key of the request, from `key` or from the metadata.
+    pub fn request_key(&self) -> Option<&str> {
+        self.key.as_deref().or_else(|| {
+            self.metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get("key"))
+                .and_then(|key| key.as_str())
+        })
+    }
+}
+
+/// The outcome of a request of a batch, by its key.
+#[derive(Debug)]
+pub struct BatchResult {
+    /// The key of the request, or its index in the batch if it has no key.
+    pub key: String,
+    pub result: Result<GeminiResponse, GoogleAPIError>,
+}
+impl BatchResult {
+    fn from_response(index: usize, response: InlinedResponse) -> Self {
+        let key = response
+            .request_key()
+            .map(str::to_string)
+            .unwrap_or_else(|| index.to_string());
+        let result = match (response.error, response.response) {
+            (Some(status), _) => Err(status.into()),
+            (None, Some(response)) => Ok(response),
+            (None, None) => Err(GoogleAPIError {
+                message: format!("Batch request {} has no response", key),
+                code: None,
+                rate_limit: None,
+            }),
+        };
+        BatchResult { key, result }
+    }
+
+    /// Parses the JSON Lines of a batch responses file.
+    pub fn parse_jsonl(jsonl: &str) -> Result<Vec<BatchResult>, GoogleAPIError> {
+        jsonl
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(index, line)| {
+                serde_json::from_str::<InlinedResponse>(line)
+                    .map(|response| BatchResult::from_response(index, response))
+                    .map_err(|e| GoogleAPIError {
+                        message: format!(
+                            "Failed to parse line {} of the batch responses: {}",
+                            index + 1,
+                            e
+                        ),
+                        code: None,
+                        rate_limit: None,
+                    })
+            })
+            .collect()
+    }
+}
+
+/// A page of batches.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchList {
+    #[serde(default)]
+    pub operations: Vec<BatchOperation>,
+    pub next_page_token: Option<String>,
+}
+
+/// A file uploaded with the Files API.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedFile {
+    /// The resource name, e.g. `files/123`.
+    pub name: String,
+    pub display_name: Option<String>,
+    pub mime_type: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub size_bytes: i64,
+    pub uri: Option<String>,
+    pub state: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UploadFileResponse {
+    file: UploadedFile,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateBatchRequest<'a> {
+    batch: CreateBatch<'a>,
+}
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateBatch<'a> {
+    display_name: &'a str,
+    input_config: InputConfig<'a>,
+}
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum InputConfig<'a> {
+    Requests { requests: Vec<InlinedRequest<'a>> },
+    FileName(&'a str),
+}
+#[derive(Serialize)]
+struct InlinedRequest<'a> {
+    request: &'a Request,
+    metadata: serde_json::Value,
+}
+
+impl Client {
+    /// Creates a batch, which runs asynchronously on the client's model.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * display_name - a name for the batch, e.g. to find it in the list of batches
+    /// * input - the requests, inline or in an uploaded file
+    pub async fn create_batch(
+        &self,
+        timeout: u64,
+        display_name: &str,
+        input: BatchInput,
+    ) -> Result<BatchOperation, GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let input_config = match &input {
+            BatchInput::Requests(requests) => InputConfig::Requests {
+                requests: requests
+                    .iter()
+                    .map(|request| InlinedRequest {
+                        request: &request.request,
+                        metadata: serde_json::json!({ "key": request.key }),
+                    })
+                    .collect(),
+            },
+            BatchInput::File(file_name) => InputConfig::FileName(file_name),
+        };
+        let body = CreateBatchRequest {
+            batch: CreateBatch {
+                display_name,
+                input_config,
+            },
+        };
+
+        let url = Url::new_from_base_url(
+            &self.batch_base_url(),
+            &self.model,
+            &ResponseType::BatchGenerateContent,
+        )
+        .url;
+        let result = self.post_json(timeout, &url, &body).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Uploads the requests as a JSON Lines file, to create a batch from with [`BatchInput::File`].
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * display_name - a name for the file
+    /// * requests - the requests, with their keys
+    pub async fn upload_batch_file(
+        &self,
+        timeout: u64,
+        display_name: &str,
+        requests: &[BatchRequest],
+    ) -> Result<UploadedFile, GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let jsonl = BatchRequest::to_jsonl(requests)?;
+        let http_client = self.get_reqwest_client(timeout)?;
+
+        // A resumable upload: the upload URL is requested with the metadata, then the content is sent to it
+        let start = http_client
+            .post(format!("{}/files", self.media_url("upload")))
+            .header(USER_AGENT, env!("CARGO_CRATE_NAME"))
+            .header("X-Goog-Upload-Protocol", "resumable")
+            .header("X-Goog-Upload-Command", "start")
+            .header("X-Goog-Upload-Header-Content-Length", jsonl.len())
+            .header("X-Goog-Upload-Header-Content-Type", JSONL_MIME_TYPE)
+            .json(&serde_json::json!({ "file": { "displayName": display_name } }));
+        // Media transfers are not JSON, so they bypass the middleware
+        let response = self
+            .successful_response(self.transmit(start).await?)
+            .await?;
+        let upload_url = response
+            .headers()
+            .get("x-goog-upload-url")
+            .and_then(|url| url.to_str().ok())
+            .ok_or_else(|| GoogleAPIError {
+                message: "The upload was not started: no upload URL in the response".to_string(),
+                code: None,
+                rate_limit: None,
+            })?
+            .to_string();
+
+        let upload = http_client
+            .post(upload_url)
+            .header(USER_AGENT, env!("CARGO_CRATE_NAME"))
+            .header(CONTENT_TYPE, JSONL_MIME_TYPE)
+            .header("X-Goog-Upload-Offset", 0)
+            .header("X-Goog-Upload-Command", "upload, finalize")
+            .body(jsonl);
+        let result = self.transmit(upload).await?;
+        let response: UploadFileResponse = self.deserialize_response(result).await?;
+        Ok(response.file)
+    }
+
+    /// Gets the latest state of a batch.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the batch, e.g. `batches/123`
+    pub async fn get_batch(
+        &self,
+        timeout: u64,
+        name: &str,
+    ) -> Result<BatchOperation, GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let url = format!("{}/{}", self.batch_base_url(), name);
+        let result = self.get(timeout, &url, &[]).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Polls a batch until it is done, i.e. succeeded, failed, cancelled or expired.
+    /// Parameters:
+    /// * timeout - the timeout in seconds of each poll
+    /// * name - the resource name of the batch
+    /// * poller - when to poll, and for how long
+    pub async fn wait_for_batch(
+        &self,
+        timeout: u64,
+        name: &str,
+        poller: &OperationPoller,
+    ) -> Result<BatchOperation, GoogleAPIError> {
+        poller
+            .poll_until(|| self.get_batch(timeout, name), |batch| batch.done)
+            .await
+    }
+
+    /// Lists a page of the batches, most recent first.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * page_size - the maximum number of batches to return
+    /// * page_token - the `next_page_token` of the previous page, if any
+    pub async fn list_batches(
+        &self,
+        timeout: u64,
+        page_size: Option<u32>,
+        page_token: Option<String>,
+    ) -> Result<BatchList, GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let mut query = Vec::new();
+        if let Some(page_size) = page_size {
+            query.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
+        let url = format!("{}/batches", self.batch_base_url());
+        let result = self.get(timeout, &url, &query).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Cancels a batch. Requests that already ran are still billed, and their responses are kept.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the batch
+    pub async fn cancel_batch(&self, timeout: u64, name: &str) -> Result<(), GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let url = format!("{}/{}:cancel", self.batch_base_url(), name);
+        let result = self
+            .post_json(timeout, &url, &serde_json::json!({}))
+            .await?;
+        let _: serde_json::Value = self.deserialize_response(result).await?;
+        Ok(())
+    }
+
+    /// Returns the result of each request of a finished batch, downloading the responses file if any.
+    /// Parameters:
+    /// * timeout - the timeout in seconds of the download
+    /// * batch - the finished batch, see [`Client::wait_for_batch`]
+    pub async fn batch_results(
+        &self,
+        timeout: u64,
+        batch: &BatchOperation,
+    ) -> Result<Vec<BatchResult>, GoogleAPIError> {
+        if let Some(status) = &batch.error {
+            return Err(status.clone().into());
+        }
+        let output = batch
+            .response
+            .as_ref()
+            .or_else(|| {
+                batch
+                    .metadata
+                    .as_ref()
+                    .and_then(|batch| batch.output.as_ref())
+            })
+            .ok_or_else(|| GoogleAPIError {
+                message: format!("Batch {} has no output yet", batch.name),
+                code: None,
+                rate_limit: None,
+            })?;
+
+        if let Some(responses_file) = &output.responses_file {
+            let jsonl = self.download_file(timeout, responses_file).await?;
+            return BatchResult::parse_jsonl(&jsonl);
+        }
+        Ok(output
+            .inlined_responses
+            .iter()
+            .flat_map(|responses| responses.inlined_responses.iter().cloned())
+            .enumerate()
+            .map(|(index, response)| BatchResult::from_response(index, response))
+            .collect())
+    }
+
+    /// Downloads the content of a file, e.g. the responses file of a batch.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the file, e.g. `files/123`
+    pub async fn download_file(&self, timeout: u64, name: &str) -> Result<String, GoogleAPIError> {
+        self.check_batch_api_support()?;
+        let request_builder = self
+            .get_reqwest_client(timeout)?
+            .get(format!("{}/{}:download", self.media_url("download"), name))
+            .query(&[("alt", "media")])
+            .header(USER_AGENT, env!("CARGO_CRATE_NAME"));
+        let response = self
+            .successful_response(self.transmit(request_builder).await?)
+            .await?;
+        response
+            .text()
+            .await
+            .map_err(|e| self.new_error_from_reqwest_error(e))
+    }
+
+    fn check_batch_api_support(&self) -> Result<(), GoogleAPIError> {
+        if self.is_vertex() {
+            return Err(GoogleAPIError {
+                message: "The Batch API is only available on the public API, use Vertex AI batch prediction jobs instead"
+                    .to_string(),
+                code: None,
+                rate_limit: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn batch_base_url(&self) -> String {
+        self.base_url
+            .clone()
+            .unwrap_or_else(|| BATCH_API_URL_BASE.to_string())
+    }
+
+    /// Returns the base URL of the media uploads or downloads, e.g.
+    /// `https://generativelanguage.googleapis.com/upload/v1beta`.
+    fn media_url(&self, media: &str) -> String {
+        let base_url = self.batch_base_url();
+        match base_url.rsplit_once('/') {
+            Some((host, version)) => format!("{}/{}/{}", host, media, version),
+            None => base_url,
+        }
+    }
+
+    async fn successful_response(
+        &self,
+        result: Result<reqwest::Response, reqwest::Error>,
+    ) -> Result<reqwest::Response, GoogleAPIError> {
+        match result {
+            Ok(response) if response.status().is_success() => Ok(response),
+            Ok(response) => Err(self.new_error_from_response(response).await),
+            Err(e) => Err(self.new_error_from_reqwest_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::v1::gemini::request::text_request;
+    use crate::v1::mock_server::{MockResponse, MockServer};
+
+    fn batch_json(state: &str, done: bool, response: serde_json::Value) -> serde_json::Value {
+        let mut operation = serde_json::json!({
+            "name": "batches/1",
+            "metadata": {
+                "@type": "type.googleapis.com/google.ai.generativelanguage.v1beta.GenerateContentBatch",
+                "name": "batches/1",
+                "displayName": "evaluation",
+                "model": "models/gemini-1.5-flash",
+                "state": state,
+                "batchStats": {"requestCount": "2", "pendingRequestCount": "2"}
+            },
+            "done": done
+        });
+        if !response.is_null() {
+            operation["response"] = response;
+        }
+        operation
+    }
+
+    fn response_json(text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "candidates": [{"content": {"role": "model", "parts": [{"text": text}]}, "finishReason": "STOP"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_inline_batch_is_created_polled_and_its_results_keyed() {
+        let server = MockServer::start().await;
+        server
+            .enqueue(
+                ResponseType::BatchGenerateContent,
+                MockResponse::json(200, batch_json("BATCH_STATE_PENDING", false, serde_json::Value::Null)),
+            )
+            .enqueue_route(
+                "GET",
+                "/batches/1",
+                MockResponse::json(200, batch_json("BATCH_STATE_RUNNING", false, serde_json::Value::Null)),
+            )
+            .enqueue_route(
+                "GET",
+                "/batches/1",
+                MockResponse::json(
+                    200,
+                    batch_json(
+                        "BATCH_STATE_SUCCEEDED",
+                        true,
+                        serde_json::json!({
+                            "@type": "type.googleapis.com/google.ai.generativelanguage.v1beta.GenerateContentBatchOutput",
+                            "inlinedResponses": {"inlinedResponses": [
+                                {"response": response_json("Paris"), "metadata": {"key": "capital"}},
+                                {"error": {"code": 3, "message": "Invalid request"}, "metadata": {"key": "broken"}}
+                            ]}
+                        }),
+                    ),
+                ),
+            );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+        let poller = OperationPoller {
+            initial_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let batch = client
+            .create_batch(
+                5,
+                "evaluation",
+                BatchInput::Requests(vec![
+                    BatchRequest::new("capital", text_request("Capital of France?")),
+                    BatchRequest::new("broken", text_request("")),
+                ]),
+            )
+            .await
+            .unwrap();
+        let metadata = batch.metadata.as_ref().unwrap();
+        assert_eq!(metadata.state, BatchState::Pending);
+        assert_eq!(metadata.batch_stats.as_ref().unwrap().request_count, 2);
+        let batch = client
+            .wait_for_batch(5, &batch.name, &poller)
+            .await
+            .unwrap();
+        assert_eq!(
+            batch.metadata.as_ref().unwrap().state,
+            BatchState::Succeeded
+        );
+        let results = client.batch_results(5, &batch).await.unwrap();
+
+        assert_eq!(results[0].key, "capital");
+        assert_eq!(results[0].result.as_ref().unwrap().get_text(), "Paris");
+        assert_eq!(results[1].key, "broken");
+        assert_eq!(
+            results[1].result.as_ref().unwrap_err().code,
+            Some(StatusCode::BAD_REQUEST)
+        );
+        let create = &server.requests()[0];
+        assert_eq!(
+            create.path,
+            format!("/v1/{}:batchGenerateContent", client.model.resource_name())
+        );
+        let body = create.json();
+        assert_eq!(body["batch"]["displayName"], "evaluation");
+        let inlined = &body["batch"]["inputConfig"]["requests"]["requests"][0];
+        assert_eq!(inlined["metadata"]["key"], "capital");
+        assert_eq!(
+            inlined["request"]["contents"][0]["parts"][0]["text"],
+            "Capital of France?"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_batch_is_uploaded_and_its_results_downloaded() {
+        let server = MockServer::start().await;
+        let upload_url = format!(
+            "{}/files?upload_id=abc",
+            server.base_url().replace("/v1", "/upload/v1")
+        );
+        server
+            .enqueue_route(
+                "POST",
+                "/upload/v1/files",
+                MockResponse::json(200, serde_json::json!({})).with_header("x-goog-upload-url", &upload_url),
+            )
+            .enqueue_route(
+                "POST",
+                "/upload/v1/files",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"file": {"name": "files/in", "mimeType": "application/jsonl", "sizeBytes": "120"}}),
+                ),
+            )
+            .enqueue(
+                ResponseType::BatchGenerateContent,
+                MockResponse::json(
+                    200,
+                    batch_json(
+                        "BATCH_STATE_SUCCEEDED",
+                        true,
+                        serde_json::json!({"responsesFile": "files/out"}),
+                    ),
+                ),
+            )
+            .enqueue_route(
+                "GET",
+                "/download/v1/files/out:download",
+                MockResponse::body(
+                    200,
+                    "application/jsonl",
+                    &format!(
+                        "{}\n{}\n",
+                        serde_json::json!({"key": "b", "response": response_json("Two")}),
+                        serde_json::json!({"key": "a", "response": response_json("One")})
+                    ),
+                ),
+            );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+
+        let file = client
+            .upload_batch_file(
+                5,
+                "evaluation",
+                &[
+                    BatchRequest::new("a", text_request("1")),
+                    BatchRequest::new("b", text_request("2")),
+                ],
+            )
+            .await
+            .unwrap();
+        let batch = client
+            .create_batch(5, "evaluation", BatchInput::File(file.name.clone()))
+            .await
+            .unwrap();
+        let results = client.batch_results(5, &batch).await.unwrap();
+
+        assert_eq!(file.size_bytes, 120);
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.key.as_str(),
+                    result.result.as_ref().unwrap().get_text(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            [("b", "Two".to_string()), ("a", "One".to_string())]
+        );
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["x-goog-upload-command"], "start");
+        assert_eq!(
+            requests[0].headers["x-goog-upload-header-content-type"],
+            "application/jsonl"
+        );
+        assert_eq!(requests[1].query["upload_id"], "abc");
+        assert_eq!(
+            requests[1].headers["x-goog-upload-command"],
+            "upload, finalize"
+        );
+        let lines: Vec<BatchRequest> = requests[1]
+            .body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[1].key, "b");
+        assert_eq!(
+            requests[2].json()["batch"]["inputConfig"]["fileName"],
+            "files/in"
+        );
+        assert_eq!(requests[3].query["alt"], "media");
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_listed_and_cancelled() {
+        let server = MockServer::start().await;
+        server
+            .enqueue_route(
+                "GET",
+                "/batches",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "operations": [batch_json("BATCH_STATE_RUNNING", false, serde_json::Value::Null)],
+                        "nextPageToken": "page-2"
+                    }),
+                ),
+            )
+            .enqueue_route("POST", "/batches/1:cancel", MockResponse::json(200, serde_json::json!({})));
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+
+        let list = client.list_batches(5, Some(10), None).await.unwrap();
+        client
+            .cancel_batch(5, &list.operations[0].name)
+            .await
+            .unwrap();
+
+        assert_eq!(list.operations.len(), 1);
+        assert_eq!(list.next_page_token.as_deref(), Some("page-2"));
+        let requests = server.requests();
+        assert_eq!(requests[0].query["pageSize"], "10");
+        assert_eq!(requests[1].path, "/v1/batches/1:cancel");
+
+        let vertex =
+            Client::new_from_region_project_id("us-central1".to_string(), "my-project".to_string());
+        assert!(vertex.list_batches(5, None, None).await.is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! Runs many generation requests, e.g. for offline evaluations, with bounded concurrency, per-request retries and
+//! optional rate limiting.
+//!
+//! Each request is tagged with its index in the input, and its outcome is reported as a [`BulkResult`], so one failed
+//! request does not fail the others. Results are returned in input order with [`BulkGenerator::run`], or as they
+//! complete with [`BulkGenerator::run_unordered`].
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use google_generative_ai_rs::v1::api::Client;
+//! use google_generative_ai_rs::v1::bulk::BulkGenerator;
+//! # use google_generative_ai_rs::v1::gemini::request::Request;
+//! # async fn run(requests: Vec<Request>) {
+//! let generator = BulkGenerator::new(Client::new("api-key".to_string())).with_concurrency(16);
+//! let mut results = generator.run_unordered(futures::stream::iter(requests));
+//! while let Some(result) = results.next().await {
+//!     match result.result {
+//!         Ok(response) => println!("{}: {}", result.index, response.get_text()),
+//!         Err(error) => eprintln!("{} failed after {} attempts: {}", result.index, result.attempts, error),
+//!     }
+//! }
+//! # }
+//! ```
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::StatusCode;
+use std::time::Duration;
+
+use crate::v1::backend::GenerativeBackend;
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::GeminiResponse;
+use crate::v1::gemini::Model;
+use crate::v1::rate_limit::{RateLimitError, RateLimitPermit, RateLimiter};
+
+/// When and how often a failed request is retried.
+///
+/// Requests are retried after HTTP 408, 429 and 5xx errors, and after errors without a status code, e.g. timeouts and
+/// connection errors. The backoff doubles after each attempt, up to `max_backoff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The attempts per request, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+impl RetryPolicy {
+    /// Never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if a request that failed with the error may succeed if retried.
+    /// A request that waited too long for the client-side rate limits is retryable, one that can never fit them is not.
+    pub fn is_retryable(&self, error: &GoogleAPIError) -> bool {
+        match &error.rate_limit {
+            Some(RateLimitError::MaxWaitExceeded { .. }) => return true,
+            Some(RateLimitError::ExceedsQuota { .. }) => return false,
+            None => {}
+        }
+        match error.code {
+            Some(code) => {
+                code == StatusCode::REQUEST_TIMEOUT
+                    || code == StatusCode::TOO_MANY_REQUESTS
+                    || code.is_server_error()
+            }
+            None => true,
+        }
+    }
+
+    /// Returns the backoff before the attempt after `attempt`, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// The outcome of one request of a bulk run.
+#[derive(Debug)]
+pub struct BulkResult {
+    /// The index of the request in the input.
+    pub index: usize,
+    /// The attempts made, including retries.
+    pub attempts: u32,
+    pub result: Result<GeminiResponse, GoogleAPIError>,
+}
+
+/// Runs generation requests against a backend, e.g. a [`crate::v1::api::Client`] - see the
+/// [module documentation](self).
+pub struct BulkGenerator<B> {
+    backend: B,
+    concurrency: usize,
+    timeout: u64,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<(RateLimiter, Model)>,
+}
+impl<B: GenerativeBackend> BulkGenerator<B> {
+    /// Creates a generator running 8 requests at a time, each with a timeout of 60 seconds and the default
+    /// [`RetryPolicy`].
+    pub fn new(backend: B) -> Self {
+        BulkGenerator {
+            backend,
+            concurrency: 8,
+            timeout: 60,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+        }
+    }
+
+    /// Sets the maximum number of requests in flight, at least 1.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets the timeout of each attempt, in seconds.
+    pub fn with_timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Waits for the quotas of the model before each attempt - see [`crate::v1::rate_limit`].
+    /// Only set a limiter here, or on the client, not both, or requests are counted twice.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter, model: Model) -> Self {
+        self.rate_limiter = Some((rate_limiter, model));
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Runs the requests, returning their results in input order.
+    /// Use [`futures::stream::iter`] to run the requests of an iterator.
+    pub fn run<'a>(
+        &'a self,
+        requests: impl Stream<Item = Request> + 'a,
+    ) -> impl Stream<Item = BulkResult> + 'a {
+        requests
+            .enumerate()
+            .map(move |(index, request)| self.generate(index, request))
+            .buffered(self.concurrency)
+    }
+
+    /// Runs the requests, returning their results as they complete.
+    pub fn run_unordered<'a>(
+        &'a self,
+        requests: impl Stream<Item = Request> + 'a,
+    ) -> impl Stream<Item = BulkResult> + 'a {
+        requests
+            .enumerate()
+            .map(move |(index, request)| self.generate(index, request))
+            .buffer_unordered(self.concurrency)
+    }
+
+    /// Runs all the requests, returning their results in input order.
+    pub async fn run_all(&self, requests: impl IntoIterator<Item = Request>) -> Vec<BulkResult> {
+        self.run(stream::iter(requests)).collect().await
+    }
+
+    /// Generates the response to a request, retrying it as allowed by the retry policy.
+    async fn generate(&self, index: usize, request: Request) -> BulkResult {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            // Not retried, as the limiter already waited as long as it was allowed to
+            let permit = match self.acquire_rate_limit(&request).await {
+                Ok(permit) => permit,
+                Err(error) => {
+                    return BulkResult {
+                        index,
+                        attempts,
+                        result: Err(error.into()),
+                    }
+                }
+            };
+            let result = self.backend.generate_content(self.timeout, &request).await;
+            match (&permit, &result) {
+                (Some(permit), Ok(response)) => {
+                    if let Some(usage) = &response.usage_metadata {
+                        permit.reconcile_usage(usage);
+                    }
+                }
+                // Failed attempts release their tokens, so that retries do not use up the quota
+                (Some(permit), Err(_)) => permit.reconcile(0),
+                (None, _) => {}
+            }
+
+            match result {
+                Err(error)
+                    if attempts < self.retry_policy.max_attempts
+                        && self.retry_policy.is_retryable(&error) =>
+                {
+                    tokio::time::sleep(self.retry_policy.backoff(attempts)).await;
+                }
+                result => {
+                    return BulkResult {
+                        index,
+                        attempts,
+                        result,
+                    }
+                }
+            }
+        }
+    }
+
+    async fn acquire_rate_limit(
+        &self,
+        request: &Request,
+    ) -> Result<Option<RateLimitPermit>, RateLimitError> {
+        match &self.rate_limiter {
+            Some((rate_limiter, model)) => {
+                let estimated_tokens = rate_limiter.estimate(request);
+                Ok(Some(rate_limiter.acquire(model, estimated_tokens).await?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::backend::{FakeBackend, FakeReply};
+    use crate::v1::gemini::request::text_request;
+    use crate::v1::rate_limit::RateLimits;
+
+    fn requests(texts: &[&str]) -> Vec<Request> {
+        texts.iter().map(|text| text_request(text)).collect()
+    }
+
+    fn error(code: StatusCode) -> FakeReply {
+        FakeReply::Error(GoogleAPIError {
+            message: "Failed".to_string(),
+            code: Some(code),
+            rate_limit: None,
+        })
+    }
+
+    /// Scripts a reply echoing each text after a delay of that many milliseconds.
+    fn push_delayed_echoes(backend: &FakeBackend, texts: &[&str]) {
+        for text in texts {
+            backend.push_delayed(
+                Duration::from_millis(text.parse().unwrap()),
+                FakeReply::Generate(FakeBackend::text_response(text)),
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_results_are_ordered_or_as_completed_with_bounded_concurrency() {
+        let texts = ["50", "10", "40", "20", "30", "15"];
+        let generator = BulkGenerator::new(FakeBackend::new()).with_concurrency(3);
+        push_delayed_echoes(generator.backend(), &texts);
+        push_delayed_echoes(generator.backend(), &texts);
+
+        let ordered = generator.run_all(requests(&texts)).await;
+        let started = tokio::time::Instant::now();
+        let unordered: Vec<BulkResult> = generator
+            .run_unordered(stream::iter(requests(&texts)))
+            .collect()
+            .await;
+
+        let ordered: Vec<_> = ordered
+            .iter()
+            .map(|result| (result.index, result.result.as_ref().unwrap().get_text()))
+            .collect();
+        assert_eq!(
+            ordered,
+            texts
+                .iter()
+                .enumerate()
+                .map(|(index, text)| (index, text.to_string()))
+                .collect::<Vec<_>>()
+        );
+        let completed: Vec<_> = unordered.iter().map(|result| result.index).collect();
+        assert_eq!(completed, [1, 3, 2, 0, 5, 4]);
+        // 3 at a time: 1 ends at 10, 3 at 30, 2 at 40, 0 at 50, 5 at 55 and 4 at 60 milliseconds
+        assert_eq!(started.elapsed(), Duration::from_millis(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_are_retried_or_reported_per_item() {
+        let backend = FakeBackend::new();
+        backend
+            .push(error(StatusCode::SERVICE_UNAVAILABLE))
+            .push(error(StatusCode::SERVICE_UNAVAILABLE))
+            .push_text("a")
+            .push(error(StatusCode::TOO_MANY_REQUESTS))
+            .push(error(StatusCode::TOO_MANY_REQUESTS))
+            .push(error(StatusCode::TOO_MANY_REQUESTS))
+            .push(error(StatusCode::BAD_REQUEST))
+            .push_text("d");
+        let generator = BulkGenerator::new(backend)
+            .with_concurrency(1)
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(1),
+            });
+
+        let results = generator.run_all(requests(&["a", "b", "c", "d"])).await;
+
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.attempts,
+                    result.result.as_ref().map_err(|error| error.code),
+                )
+            })
+            .map(|(attempts, result)| (attempts, result.map(|response| response.get_text())))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (3, Ok("a".to_string())),
+                (3, Err(Some(StatusCode::TOO_MANY_REQUESTS))),
+                (1, Err(Some(StatusCode::BAD_REQUEST))),
+                (1, Ok("d".to_string())),
+            ]
+        );
+        assert_eq!(generator.backend().remaining(), 0);
+        assert_eq!(
+            RetryPolicy::default().backoff(10),
+            RetryPolicy::default().max_backoff
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_spaces_out_requests_and_is_not_retried() {
+        let limiter = RateLimiter::new(RateLimits::requests_per_minute(2))
+            .with_max_wait(Duration::from_secs(90));
+        let generator = BulkGenerator::new(FakeBackend::new())
+            .with_concurrency(8)
+            .with_rate_limiter(limiter, Model::default());
+        push_delayed_echoes(generator.backend(), &["1", "1", "1", "1"]);
+        let started = tokio::time::Instant::now();
+
+        let results = generator
+            .run_all(requests(&["1", "1", "1", "1", "1", "1"]))
+            .await;
+
+        let succeeded = results
+            .iter()
+            .filter(|result| result.result.is_ok())
+            .count();
+        assert_eq!(succeeded, 4);
+        assert!(started.elapsed() >= Duration::from_secs(60));
+        let failed = &results[5];
+        assert_eq!(failed.attempts, 1);
+        assert!(matches!(
+            failed.result.as_ref().unwrap_err().rate_limit,
+            Some(RateLimitError::MaxWaitExceeded { .. })
+        ));
+        assert_eq!(generator.backend().calls().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_attempts_release_their_tokens() {
+        let request = requests(&["Tell me a story about a lighthouse keeper."]).remove(0);
+        let tokens = RateLimiter::new(RateLimits::default()).estimate(&request);
+        assert!(tokens >= 2);
+        // Room for one attempt at a time: without releasing the tokens of failed attempts, the retry would wait
+        let limiter = RateLimiter::new(RateLimits::tokens_per_minute(tokens * 3 / 2))
+            .with_max_wait(Duration::from_secs(1));
+        let backend = FakeBackend::new();
+        backend
+            .push(error(StatusCode::SERVICE_UNAVAILABLE))
+            .push_response(
+                serde_json::from_value(serde_json::json!({
+                    "candidates": [{"content": {"role": "model", "parts": [{"text": "Once"}]}}]
+                }))
+                .unwrap(),
+            );
+        let generator = BulkGenerator::new(backend)
+            .with_rate_limiter(limiter, Model::default())
+            .with_retry_policy(RetryPolicy {
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            });
+
+        let results = generator.run_all(vec![request]).await;
+
+        assert_eq!(results[0].attempts, 2);
+        assert!(results[0].result.is_ok());
+    }
+}
@@ -0,0 +1,507 @@
+//! Records and replays the HTTP interactions of a client, for deterministic tests that need no network or keys.
+//!
+//! In [`CassetteMode::Record`], every request and response is appended to a JSON fixture file as it happens.
+//! Credentials are redacted, and the boundaries of the response body chunks are kept so streamed responses replay
+//! chunk by chunk. In [`CassetteMode::Replay`], the responses are served back from the file in the recorded order,
+//! and no credentials are requested.
+//!
+//! ```no_run
+//! use google_generative_ai_rs::v1::{api::Client, cassette::Cassette};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = Client::new(String::new()).with_cassette(Cassette::replay("tests/fixtures/text_request.json")?);
+//! # Ok(())
+//! # }
+//! ```
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::v1::auth::API_KEY_HEADER;
+use crate::v1::errors::GoogleAPIError;
+
+/// The environment variable holding the path of the cassette, see [`Cassette::from_env`].
+pub const CASSETTE_ENV_VAR: &str = "GEMINI_CASSETTE";
+/// The environment variable holding the mode of the cassette, `record` or `replay`. Defaults to `replay`.
+pub const CASSETTE_MODE_ENV_VAR: &str = "GEMINI_CASSETTE_MODE";
+
+const REDACTED: &str = "<REDACTED>";
+/// The headers never written to a cassette as is.
+const SENSITIVE_HEADERS: [&str; 5] = [
+    API_KEY_HEADER,
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Sends the requests and writes the interactions to the cassette file.
+    Record,
+    /// Serves the responses from the cassette file, without sending any request.
+    Replay,
+}
+
+/// A request and its response.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    body: String,
+    /// The length in bytes of each chunk of the body, as received.
+    #[serde(default)]
+    chunks: Vec<usize>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// A fixture file of HTTP interactions, see [`crate::v1::api::Client::with_cassette`].
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+    /// The index of the next interaction to replay.
+    cursor: Mutex<usize>,
+}
+impl Cassette {
+    /// Creates a cassette that records to `path`, replacing any existing file.
+    pub fn record(path: impl AsRef<Path>) -> Self {
+        Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Record,
+            interactions: Mutex::new(Vec::new()),
+            cursor: Mutex::new(0),
+        }
+    }
+
+    /// Loads a recorded cassette from `path` to replay it.
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, GoogleAPIError> {
+        let path = path.as_ref().to_path_buf();
+        let json = std::fs::read_to_string(&path).map_err(|e| GoogleAPIError {
+            message: format!("Failed to read the cassette {}: {}", path.display(), e),
+            code: None,
+            rate_limit: None,
+        })?;
+        let file: CassetteFile = serde_json::from_str(&json).map_err(|e| GoogleAPIError {
+            message: format!("Failed to parse the cassette {}: {}", path.display(), e),
+            code: None,
+            rate_limit: None,
+        })?;
+        Ok(Cassette {
+            path,
+            mode: CassetteMode::Replay,
+            interactions: Mutex::new(file.interactions),
+            cursor: Mutex::new(0),
+        })
+    }
+
+    /// Creates a cassette from the [`CASSETTE_ENV_VAR`] and [`CASSETTE_MODE_ENV_VAR`] environment variables, or
+    /// returns `None` if no cassette is set, e.g. to run the examples offline in CI.
+    pub fn from_env() -> Result<Option<Self>, GoogleAPIError> {
+        let path = match std::env::var(CASSETTE_ENV_VAR) {
+            Ok(path) if !path.is_empty() => path,
+            _ => return Ok(None),
+        };
+        match std::env::var(CASSETTE_MODE_ENV_VAR).as_deref() {
+            Ok("record") => Ok(Some(Cassette::record(path))),
+            Ok("replay") | Err(_) => Cassette::replay(path).map(Some),
+            Ok(mode) => Err(GoogleAPIError {
+                message: format!(
+                    "Unknown cassette mode {:?}, expected \"record\" or \"replay\"",
+                    mode
+                ),
+                code: None,
+                rate_limit: None,
+            }),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of interactions recorded, or left to replay.
+    pub fn len(&self) -> usize {
+        let interactions = self.interactions.lock().unwrap().len();
+        match self.mode {
+            CassetteMode::Record => interactions,
+            CassetteMode::Replay => interactions - *self.cursor.lock().unwrap(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Serves the next recorded response, if it was recorded for the same method and URL.
+    pub(crate) fn replay_next(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, GoogleAPIError> {
+        let request = RecordedRequest::from_builder(request_builder)?;
+        let interactions = self.interactions.lock().unwrap();
+        let mut cursor = self.cursor.lock().unwrap();
+
+        let interaction = interactions.get(*cursor).ok_or_else(|| GoogleAPIError {
+            message: format!(
+                "The cassette {} has no interaction left for {} {}",
+                self.path.display(),
+                request.method,
+                request.url
+            ),
+            code: None,
+            rate_limit: None,
+        })?;
+        if interaction.request.method != request.method || interaction.request.url != request.url {
+            return Err(GoogleAPIError {
+                message: format!(
+                    "Interaction {} of the cassette {} was recorded for {} {}, not {} {}",
+                    *cursor,
+                    self.path.display(),
+                    interaction.request.method,
+                    interaction.request.url,
+                    request.method,
+                    request.url
+                ),
+                code: None,
+                rate_limit: None,
+            });
+        }
+        *cursor += 1;
+
+        interaction.response.to_response()
+    }
+
+    /// Reads the whole response, appends the interaction to the cassette file and returns an equivalent response.
+    pub(crate) async fn record_interaction(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, GoogleAPIError> {
+        let request = RecordedRequest::from_builder(request_builder)?;
+        let status = response.status().as_u16();
+        let headers = redact_headers(response.headers());
+
+        let mut body = Vec::new();
+        let mut chunks = Vec::new();
+        let mut byte_stream = response.bytes_stream();
+        while let Some(chunk) = byte_stream.next().await {
+            let chunk = chunk.map_err(|e| GoogleAPIError {
+                message: format!("Failed to read the response to record: {}", e),
+                code: None,
+                rate_limit: None,
+            })?;
+            chunks.push(chunk.len());
+            body.extend_from_slice(&chunk);
+        }
+
+        let response = RecordedResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+            chunks,
+        };
+        let replayed = response.to_response();
+
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(Interaction { request, response });
+        self.save(&interactions)?;
+
+        replayed
+    }
+
+    fn save(&self, interactions: &[Interaction]) -> Result<(), GoogleAPIError> {
+        let json = serde_json::to_string_pretty(&CassetteFile {
+            interactions: interactions.to_vec(),
+        })
+        .map_err(|e| GoogleAPIError {
+            message: format!("Failed to serialize the cassette: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        std::fs::write(&self.path, json).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to write the cassette {}: {}",
+                self.path.display(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })
+    }
+}
+
+impl RecordedRequest {
+    fn from_builder(request_builder: reqwest::RequestBuilder) -> Result<Self, GoogleAPIError> {
+        let request = request_builder.build().map_err(|e| GoogleAPIError {
+            message: format!("Failed to build the request: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| {
+                serde_json::from_slice(bytes).unwrap_or_else(|_| {
+                    serde_json::Value::String(String::from_utf8_lossy(bytes).into_owned())
+                })
+            });
+        Ok(RecordedRequest {
+            method: request.method().to_string(),
+            url: redact_url(request.url()),
+            headers: redact_headers(request.headers()),
+            body,
+        })
+    }
+}
+
+impl RecordedResponse {
+    /// Rebuilds the response, streaming the body in the recorded chunks.
+    fn to_response(&self) -> Result<reqwest::Response, GoogleAPIError> {
+        let bytes = self.body.as_bytes().to_vec();
+        let mut chunks = Vec::with_capacity(self.chunks.len() + 1);
+        let mut start = 0;
+        for length in &self.chunks {
+            let end = (start + length).min(bytes.len());
+            chunks.push(bytes[start..end].to_vec());
+            start = end;
+        }
+        // The body may have been edited by hand, so never drop the rest of it
+        if start < bytes.len() {
+            chunks.push(bytes[start..].to_vec());
+        }
+
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                builder = builder.header(name, value);
+            }
+        }
+        let body = reqwest::Body::wrap_stream(stream::iter(
+            chunks.into_iter().map(Ok::<_, std::io::Error>),
+        ));
+        let response = builder.body(body).map_err(|e| GoogleAPIError {
+            message: format!("Failed to rebuild the recorded response: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+        Ok(reqwest::Response::from(response))
+    }
+}
+
+fn is_sensitive(name: &str) -> bool {
+    SENSITIVE_HEADERS
+        .iter()
+        .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+}
+
+fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.as_str().to_string(), value)
+        })
+        .collect()
+}
+
+/// Redacts an API key passed as the `key` query parameter.
+fn redact_url(url: &reqwest::Url) -> String {
+    if !url.query_pairs().any(|(name, _)| name == "key") {
+        return url.to_string();
+    }
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if name == "key" {
+                REDACTED.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_record_redacts_credentials_and_replays_chunks() {
+        let path = temp_path("cassette-record");
+        let cassette = Cassette::record(&path);
+
+        let http_client = reqwest::Client::new();
+        let request = http_client
+            .post(
+                "https://example.com/v1/models/gemini-pro:streamGenerateContent?key=my-secret-key",
+            )
+            .header(API_KEY_HEADER, "my-secret-key")
+            .bearer_auth("my-secret-token")
+            .json(&serde_json::json!({"contents": []}));
+        let response = http::Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(reqwest::Body::wrap_stream(stream::iter(
+                ["[{\"a\": 1}", ",\r\n{\"b\": \"é\"}]"].map(Ok::<_, std::io::Error>),
+            )))
+            .unwrap();
+
+        let recorded = cassette
+            .record_interaction(
+                request.try_clone().unwrap(),
+                reqwest::Response::from(response),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            recorded.text().await.unwrap(),
+            "[{\"a\": 1},\r\n{\"b\": \"é\"}]"
+        );
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("my-secret"));
+        assert!(json.contains(REDACTED));
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert_eq!(cassette.len(), 1);
+        let replayed = cassette.replay_next(request.try_clone().unwrap()).unwrap();
+        assert_eq!(replayed.status(), reqwest::StatusCode::OK);
+        let chunks: Vec<Vec<u8>> = replayed
+            .bytes_stream()
+            .map(|chunk| chunk.unwrap().to_vec())
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], ",\r\n{\"b\": \"é\"}]".as_bytes());
+
+        let error = cassette.replay_next(request).unwrap_err();
+        assert!(error.message.contains("no interaction left"));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_a_different_request() {
+        let path = temp_path("cassette-mismatch");
+        std::fs::write(
+            &path,
+            r#"{"interactions": [{
+                "request": {"method": "GET", "url": "https://example.com/v1/models"},
+                "response": {"status": 200, "body": "{\"models\": []}"}
+            }]}"#,
+        )
+        .unwrap();
+
+        let cassette = Cassette::replay(&path).unwrap();
+        let http_client = reqwest::Client::new();
+        let error = cassette
+            .replay_next(http_client.get("https://example.com/v1/models/gemini-pro"))
+            .unwrap_err();
+        assert!(error
+            .message
+            .contains("was recorded for GET https://example.com/v1/models"));
+
+        let response = cassette
+            .replay_next(http_client.get("https://example.com/v1/models"))
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "{\"models\": []}");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_client_replays_a_streamed_response_without_credentials() {
+        use crate::v1::api::Client;
+        use crate::v1::gemini::{request::text_request, Model};
+
+        let path = temp_path("cassette-stream");
+        let chunk = |text: &str| {
+            serde_json::json!({"candidates": [{"content": {"role": "model", "parts": [{"text": text}]}}]})
+                .to_string()
+        };
+        let chunks = [
+            format!("[{}", chunk("Once upon")),
+            format!(",\r\n{}]", chunk(" a time")),
+        ];
+        let file = serde_json::json!({"interactions": [{
+            "request": {
+                "method": "POST",
+                "url": "https://generativelanguage.googleapis.com/v1/models/gemini-1.5-flash:streamGenerateContent"
+            },
+            "response": {"status": 200, "body": chunks.concat(), "chunks": [chunks[0].len(), chunks[1].len()]}
+        }]});
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        // Replay never asks a provider for a credential
+        let provider = crate::v1::auth::CallbackProvider::new(|| async {
+            Err(GoogleAPIError {
+                message: "no credentials".to_string(),
+                code: None,
+                rate_limit: None,
+            })
+        });
+        let client = Client::new_from_model(Model::Gemini1_5Flash, String::new())
+            .with_credential_provider(provider)
+            .with_base_url("https://generativelanguage.googleapis.com/v1")
+            .with_cassette(Cassette::replay(&path).unwrap());
+        let request = text_request("Tell me a story");
+
+        let response = client.stream_generate_content(30, &request).await.unwrap();
+        let values: Vec<serde_json::Value> = response
+            .response_stream
+            .unwrap()
+            .map(|value| value.unwrap())
+            .collect()
+            .await;
+        assert_eq!(values.len(), 2);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
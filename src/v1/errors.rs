@@ -2,10 +2,14 @@ use reqwest::StatusCode;
 use std::error::Error;
 use std::fmt;
 
+use crate::v1::rate_limit::RateLimitError;
+
 #[derive(Debug)]
 pub struct GoogleAPIError {
     pub message: String,
     pub code: Option<StatusCode>,
+    /// Set if the request was not sent because of the client-side rate limits, see [`crate::v1::rate_limit`].
+    pub rate_limit: Option<RateLimitError>,
 }
 impl fmt::Display for GoogleAPIError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -16,4 +20,10 @@ impl fmt::Display for GoogleAPIError {
         )
     }
 }
-impl Error for GoogleAPIError {}
+impl Error for GoogleAPIError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.rate_limit
+            .as_ref()
+            .map(|error| error as &(dyn Error + 'static))
+    }
+}
@@ -2,7 +2,10 @@
 use core::fmt;
 use serde::{Deserialize, Serialize};
 
-use self::request::{FileData, InlineData, VideoMetadata};
+use self::request::{
+    CodeExecutionResult, ExecutableCode, FileData, FunctionCall, FunctionResponse, InlineData,
+    VideoMetadata,
+};
 /// Defines the type of response expected from the API.
 /// Used at the end of the API URL for the Gemini API.
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -13,8 +16,14 @@ pub enum ResponseType {
     GetModel,
     GetModelList,
     CountTokens,
+    /// Only available on Vertex AI.
+    ComputeTokens,
     EmbedContent,
+    /// Only available on Vertex AI, used for embeddings.
+    Predict,
     BatchEmbedContents,
+    /// Only available on the public API, see [`crate::v1::batch`].
+    BatchGenerateContent,
 }
 impl fmt::Display for ResponseType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -24,8 +33,43 @@ impl fmt::Display for ResponseType {
             ResponseType::GetModel => f.write_str(""), // No display as its already in the URL
             ResponseType::GetModelList => f.write_str(""), // No display as its already in the URL
             ResponseType::CountTokens => f.write_str("countTokens"),
+            ResponseType::ComputeTokens => f.write_str("computeTokens"),
+            ResponseType::Predict => f.write_str("predict"),
             ResponseType::EmbedContent => f.write_str("embedContent"),
             ResponseType::BatchEmbedContents => f.write_str("batchEmbedContents"),
+            ResponseType::BatchGenerateContent => f.write_str("batchGenerateContent"),
+        }
+    }
+}
+impl ResponseType {
+    /// Returns the response type of an API URL path, e.g. `/v1/models/gemini-pro:generateContent`.
+    pub(crate) fn from_path(path: &str) -> Option<ResponseType> {
+        match path.rsplit_once(':') {
+            Some((_, "generateContent")) => Some(ResponseType::GenerateContent),
+            Some((_, "streamGenerateContent")) => Some(ResponseType::StreamGenerateContent),
+            Some((_, "countTokens")) => Some(ResponseType::CountTokens),
+            Some((_, "computeTokens")) => Some(ResponseType::ComputeTokens),
+            Some((_, "embedContent")) => Some(ResponseType::EmbedContent),
+            Some((_, "batchEmbedContents")) => Some(ResponseType::BatchEmbedContents),
+            Some((_, "batchGenerateContent")) => Some(ResponseType::BatchGenerateContent),
+            Some((_, "predict")) => Some(ResponseType::Predict),
+            Some(_) => None,
+            None if path.ends_with("/models") => Some(ResponseType::GetModelList),
+            None if path.contains("/models/") => Some(ResponseType::GetModel),
+            None => None,
+        }
+    }
+
+    /// Returns true if the API method used for the response type is in a model's `supportedGenerationMethods`.
+    /// Streaming is supported by every model that supports `generateContent`, and getting model information is always supported.
+    pub(crate) fn is_supported_by(&self, supported_generation_methods: &[String]) -> bool {
+        let supports = |method: &str| supported_generation_methods.iter().any(|m| m == method);
+        match self {
+            ResponseType::GetModel | ResponseType::GetModelList => true,
+            ResponseType::StreamGenerateContent => {
+                supports("streamGenerateContent") || supports("generateContent")
+            }
+            _ => supports(&self.to_string()),
         }
     }
 }
@@ -34,6 +78,7 @@ impl fmt::Display for ResponseType {
 /// ```json
 /// {
 ///    "name": "models/gemini-pro",
+///    "baseModelId": "gemini-pro",
 ///    "version": "001",
 ///    "displayName": "Gemini Pro",
 ///    "description": "The best model for scaling across a wide range of tasks",
@@ -44,82 +89,159 @@ impl fmt::Display for ResponseType {
 ///        "countTokens"
 ///    ],
 ///    "temperature": 0.9,
+///    "maxTemperature": 2,
 ///    "topP": 1,
 ///    "topK": 100,
+///    "thinking": false
 /// }
 /// ```
-#[derive(Debug, Default, Deserialize)]
+///
+/// Only `name` is required; the API omits fields that do not apply to a model, so the others default when missing.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(rename = "model")]
 pub struct ModelInformation {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_model_id: Option<String>,
+    #[serde(default)]
     pub version: String,
+    #[serde(default)]
     pub display_name: String,
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub input_token_limit: i32,
+    #[serde(default)]
     pub output_token_limit: i32,
+    #[serde(default)]
     pub supported_generation_methods: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_k: Option<i32>,
+    /// Whether the model supports thinking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<bool>,
+}
+impl ModelInformation {
+    /// Returns true if the model supports the API method used for the response type.
+    pub fn supports(&self, response_type: &ResponseType) -> bool {
+        response_type.is_supported_by(&self.supported_generation_methods)
+    }
 }
 /// Lists the available models for the Gemini API.
 #[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
 #[serde(rename = "models")]
 pub struct ModelInformationList {
+    #[serde(default)]
     pub models: Vec<ModelInformation>,
+    /// A token to retrieve the next page of results. Missing or empty on the last page.
+    #[serde(default)]
+    pub next_page_token: Option<String>,
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Serialize)]
-#[serde(rename_all = "kebab-case")]
+/// Identifies a model.
+///
+/// The named variants are well-known models. Any other model, on any API version, can be used via [`Model::Custom`],
+/// either by its ID, e.g. `gemini-2.5-pro`, or by a full resource name, e.g. `tunedModels/my-tuned-model`.
+/// Parsing a string with [`Model::from`] maps well-known IDs onto the named variants, and drops a `models/` prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub enum Model {
     #[default]
     Gemini1_0Pro,
-    #[cfg(feature = "beta")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
     Gemini1_5Pro,
-    #[cfg(feature = "beta")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
     Gemini1_5Flash,
-    #[cfg(feature = "beta")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
     Gemini1_5Flash8B,
-    #[cfg(feature = "beta")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
     Gemini2_0Flash,
-    #[cfg(feature = "beta")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+    TextEmbedding004,
     Custom(String),
-    // TODO: Embedding004
+}
+impl Model {
+    /// The well-known models, i.e. all variants except [`Model::Custom`].
+    pub const KNOWN: [Model; 6] = [
+        Model::Gemini1_0Pro,
+        Model::Gemini1_5Pro,
+        Model::Gemini1_5Flash,
+        Model::Gemini1_5Flash8B,
+        Model::Gemini2_0Flash,
+        Model::TextEmbedding004,
+    ];
+
+    /// Returns the model ID, i.e. the last segment of the resource name, e.g. `gemini-1.5-flash`.
+    pub fn id(&self) -> String {
+        let name = self.to_string();
+        match name.rsplit_once('/') {
+            Some((_, id)) => id.to_string(),
+            None => name,
+        }
+    }
+
+    /// Returns the resource name used in public API URLs, e.g. `models/gemini-1.5-flash` or `tunedModels/my-model`.
+    /// A name that is already a resource name (i.e. contains a `/`) is returned unchanged.
+    pub fn resource_name(&self) -> String {
+        let name = self.to_string();
+        if name.contains('/') {
+            name
+        } else {
+            format!("models/{}", name)
+        }
+    }
+
+    /// Returns true if the model is a tuned model, i.e. its resource name is `tunedModels/{id}`.
+    pub fn is_tuned(&self) -> bool {
+        self.resource_name().starts_with("tunedModels/")
+    }
 }
 impl fmt::Display for Model {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Model::Gemini1_0Pro => write!(f, "gemini-1.0-pro"),
-
-            #[cfg(feature = "beta")]
-            #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
             Model::Gemini1_5Pro => write!(f, "gemini-1.5-pro-latest"),
-            #[cfg(feature = "beta")]
-            #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
             Model::Gemini1_5Flash => write!(f, "gemini-1.5-flash"),
-            #[cfg(feature = "beta")]
-            #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
             Model::Gemini1_5Flash8B => write!(f, "gemini-1.5-flash-8b"),
-
-            #[cfg(feature = "beta")]
-            #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
             Model::Gemini2_0Flash => write!(f, "gemini-2.0-flash-exp"),
-
-            #[cfg(feature = "beta")]
-            #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+            Model::TextEmbedding004 => write!(f, "text-embedding-004"),
             Model::Custom(name) => write!(f, "{}", name),
-            // TODO: Model::Embedding004 => write!(f, "text-embedding-004"),
         }
     }
 }
+impl From<&str> for Model {
+    fn from(name: &str) -> Self {
+        let id = name.strip_prefix("models/").unwrap_or(name);
+        Model::KNOWN
+            .into_iter()
+            .find(|model| model.to_string() == id)
+            .unwrap_or_else(|| Model::Custom(id.to_string()))
+    }
+}
+impl From<String> for Model {
+    fn from(name: String) -> Self {
+        Model::from(name.as_str())
+    }
+}
+impl std::str::FromStr for Model {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(Model::from(name))
+    }
+}
+impl Serialize for Model {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for Model {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Model::from(String::deserialize(deserializer)?))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Content {
@@ -127,8 +249,47 @@ pub struct Content {
     #[serde(default)]
     pub parts: Vec<Part>,
 }
+impl Content {
+    /// Returns the answer text, i.e. the concatenated text of all parts that are not thoughts.
+    pub fn get_text(&self) -> String {
+        self.parts
+            .iter()
+            .filter(|part| !part.is_thought())
+            .filter_map(|part| part.text.as_deref())
+            .collect()
+    }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+    /// Returns the concatenated text of the thought summaries, if the model was asked to include them.
+    pub fn get_thought_text(&self) -> String {
+        self.parts
+            .iter()
+            .filter(|part| part.is_thought())
+            .filter_map(|part| part.text.as_deref())
+            .collect()
+    }
+
+    /// Appends the parts of a streamed chunk, so the accumulated `Content` can be sent back as chat history.
+    ///
+    /// Consecutive text parts of the same kind (thought or answer) are joined, but a part carrying a thought signature
+    /// is never merged with another part, as the API requires signatures to be returned on the part they arrived with.
+    pub fn append_chunk(&mut self, chunk: &Content) {
+        for part in &chunk.parts {
+            match self.parts.last_mut() {
+                Some(last)
+                    if last.is_mergeable_text()
+                        && part.is_mergeable_text()
+                        && last.is_thought() == part.is_thought() =>
+                {
+                    let text = last.text.get_or_insert_with(String::new);
+                    text.push_str(part.text.as_deref().unwrap_or_default());
+                }
+                _ => self.parts.push(part.clone()),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Part {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -139,6 +300,42 @@ pub struct Part {
     pub file_data: Option<FileData>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub video_metadata: Option<VideoMetadata>,
+    /// Code generated by the model when the code execution tool is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable_code: Option<ExecutableCode>,
+    /// The result of running [`Part::executable_code`], returned when the code execution tool is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_execution_result: Option<CodeExecutionResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+    /// Set by thinking models on parts containing a summary of the model's thoughts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+    /// An opaque signature of the model's thoughts. It must be sent back, on the same part, in the chat history for
+    /// the model to keep its reasoning context across turns, e.g. when returning function responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought_signature: Option<String>,
+}
+impl Part {
+    /// Returns true if the part is a summary of the model's thoughts rather than part of the answer.
+    pub fn is_thought(&self) -> bool {
+        self.thought.unwrap_or(false)
+    }
+
+    /// Returns true if the part only holds text and so can be joined with a neighbouring text part.
+    fn is_mergeable_text(&self) -> bool {
+        self.text.is_some()
+            && self.thought_signature.is_none()
+            && self.inline_data.is_none()
+            && self.file_data.is_none()
+            && self.video_metadata.is_none()
+            && self.executable_code.is_none()
+            && self.code_execution_result.is_none()
+            && self.function_call.is_none()
+            && self.function_response.is_none()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -148,6 +345,18 @@ pub enum Role {
     Model,
 }
 
+/// The modality of content, used for requested response modalities and per-modality token counts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Modality {
+    ModalityUnspecified,
+    Text,
+    Image,
+    Video,
+    Audio,
+    Document,
+}
+
 /// The request format follows the following structure:
 /// ```json
 /// {
@@ -183,6 +392,7 @@ pub enum Role {
 ///   ],
 ///   "tools": [
 ///     {
+///       /// Union field tool can be only one of the following:
 ///       "functionDeclarations": [
 ///         {
 ///           "name": string,
@@ -191,7 +401,34 @@ pub enum Role {
 ///             object (OpenAPI Object Schema)
 ///           }
 ///         }
-///       ]
+///       ],
+///       "googleSearch": {},
+///       "googleSearchRetrieval": {
+///         "dynamicRetrievalConfig": {
+///           "mode": enum (Mode),
+///           "dynamicThreshold": number
+///         }
+///       },
+///       "codeExecution": {},
+///       "retrieval": {
+///         "vertexAiSearch": {
+///           "datastore": string
+///         },
+///         "vertexRagStore": {
+///           "ragResources": [
+///             {
+///               "ragCorpus": string,
+///               "ragFileIds": [
+///                 string
+///               ]
+///             }
+///           ],
+///           "similarityTopK": integer,
+///           "vectorDistanceThreshold": number
+///         },
+///         "disableAttribution": boolean
+///       }
+///       /// End of list of possible types for union field tool.
 ///     }
 ///   ],
 ///   "safetySettings": [
@@ -208,7 +445,28 @@ pub enum Role {
 ///     "maxOutputTokens": integer,
 ///     "stopSequences": [
 ///       string
-///     ]
+///     ],
+///     "presencePenalty": number,
+///     "frequencyPenalty": number,
+///     "seed": integer,
+///     "responseLogprobs": boolean,
+///     "logprobs": integer,
+///     "responseModalities": [
+///       enum (Modality)
+///     ],
+///     "speechConfig": {
+///       "voiceConfig": {
+///         "prebuiltVoiceConfig": {
+///           "voiceName": string
+///         }
+///       },
+///       "languageCode": string
+///     },
+///     "thinkingConfig": {
+///       "thinkingBudget": integer,
+///       "includeThoughts": boolean
+///     },
+///     "mediaResolution": enum (MediaResolution)
 ///   }
 /// }
 /// ```
@@ -218,14 +476,15 @@ pub mod request {
 
     use super::{
         safety::{HarmBlockThreshold, HarmCategory},
-        Content,
+        Content, Modality, Part, Role,
     };
+    use crate::v1::errors::GoogleAPIError;
 
     /// Holds the data to be used for a specific text request
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct Request {
         pub contents: Vec<Content>,
-        #[serde(skip_serializing_if = "Vec::is_empty")]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pub tools: Vec<Tools>,
         #[serde(skip_serializing_if = "Vec::is_empty")]
         #[serde(default, rename = "safetySettings")]
@@ -284,6 +543,24 @@ pub mod request {
             text_count
         }
     }
+
+    /// A request of a single user message of text, shared by the tests.
+    #[cfg(test)]
+    pub(crate) fn text_request(text: &str) -> Request {
+        Request::new(
+            vec![Content {
+                role: Role::User,
+                parts: vec![Part {
+                    text: Some(text.to_string()),
+                    ..Default::default()
+                }],
+            }],
+            vec![],
+            vec![],
+            None,
+        )
+    }
+
     #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct InlineData {
@@ -313,9 +590,178 @@ pub mod request {
         pub nanos: i32,
     }
     #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ExecutableCode {
+        pub language: Language,
+        pub code: String,
+    }
+    /// The programming language of [`ExecutableCode`].
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum Language {
+        LanguageUnspecified,
+        Python,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CodeExecutionResult {
+        pub outcome: Outcome,
+        /// Contains stdout when code execution is successful, stderr or other description otherwise.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub output: Option<String>,
+    }
+    /// The outcome of running [`ExecutableCode`].
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum Outcome {
+        OutcomeUnspecified,
+        OutcomeOk,
+        OutcomeFailed,
+        OutcomeDeadlineExceeded,
+    }
+
+    /// A tool the model may use to generate a response.
+    ///
+    /// The API treats the fields as a union, so each `Tools` value should only set one kind of tool.
+    /// Use the constructors, e.g. [`Tools::google_search`], to build one.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
     pub struct Tools {
-        #[serde(rename = "functionDeclarations")]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pub function_declarations: Vec<FunctionDeclaration>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub google_search: Option<GoogleSearch>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub google_search_retrieval: Option<GoogleSearchRetrieval>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub code_execution: Option<CodeExecution>,
+        /// Vertex AI only.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub retrieval: Option<Retrieval>,
+    }
+    impl Tools {
+        pub fn function_declarations(function_declarations: Vec<FunctionDeclaration>) -> Self {
+            Tools {
+                function_declarations,
+                ..Default::default()
+            }
+        }
+        /// Grounding with Google Search, as used by Gemini 2.0 and later models.
+        pub fn google_search() -> Self {
+            Tools {
+                google_search: Some(GoogleSearch {}),
+                ..Default::default()
+            }
+        }
+        /// Grounding with Google Search, as used by Gemini 1.5 models.
+        /// A `dynamic_threshold` enables dynamic retrieval, i.e. only grounding when the prediction score is above the threshold.
+        pub fn google_search_retrieval(dynamic_threshold: Option<f32>) -> Self {
+            Tools {
+                google_search_retrieval: Some(GoogleSearchRetrieval {
+                    dynamic_retrieval_config: dynamic_threshold.map(|threshold| {
+                        DynamicRetrievalConfig {
+                            mode: DynamicRetrievalMode::ModeDynamic,
+                            dynamic_threshold: Some(threshold),
+                        }
+                    }),
+                }),
+                ..Default::default()
+            }
+        }
+        /// Enables the model to generate and run Python code.
+        pub fn code_execution() -> Self {
+            Tools {
+                code_execution: Some(CodeExecution {}),
+                ..Default::default()
+            }
+        }
+        /// Grounding with a Vertex AI Search datastore, in the form of:
+        /// `projects/{project_id}/locations/{location}/collections/default_collection/dataStores/{datastore_id}`
+        pub fn vertex_ai_search(datastore: String) -> Self {
+            Tools {
+                retrieval: Some(Retrieval {
+                    vertex_ai_search: Some(VertexAiSearch { datastore }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        }
+        /// Grounding with one or more Vertex AI RAG Engine corpora, in the form of:
+        /// `projects/{project_id}/locations/{location}/ragCorpora/{rag_corpus_id}`
+        pub fn vertex_rag_store(rag_corpora: Vec<String>) -> Self {
+            Tools {
+                retrieval: Some(Retrieval {
+                    vertex_rag_store: Some(VertexRagStore {
+                        rag_resources: rag_corpora
+                            .into_iter()
+                            .map(|rag_corpus| RagResource {
+                                rag_corpus,
+                                rag_file_ids: vec![],
+                            })
+                            .collect(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        }
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct GoogleSearch {}
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GoogleSearchRetrieval {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub dynamic_retrieval_config: Option<DynamicRetrievalConfig>,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DynamicRetrievalConfig {
+        pub mode: DynamicRetrievalMode,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub dynamic_threshold: Option<f32>,
+    }
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum DynamicRetrievalMode {
+        ModeUnspecified,
+        ModeDynamic,
+    }
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct CodeExecution {}
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Retrieval {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vertex_ai_search: Option<VertexAiSearch>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vertex_rag_store: Option<VertexRagStore>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub disable_attribution: Option<bool>,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct VertexAiSearch {
+        pub datastore: String,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct VertexRagStore {
+        pub rag_resources: Vec<RagResource>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub similarity_top_k: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vector_distance_threshold: Option<f64>,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RagResource {
+        pub rag_corpus: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub rag_file_ids: Vec<String>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -325,28 +771,404 @@ pub mod request {
         pub parameters: serde_json::Value,
     }
 
+    /// A call to a declared function, predicted by the model.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct FunctionCall {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        pub name: String,
+        #[serde(default)]
+        pub args: serde_json::Value,
+    }
+
+    /// The result of a [`FunctionCall`], sent back to the model.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct FunctionResponse {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        pub name: String,
+        pub response: serde_json::Value,
+    }
+
+    /// A request to embed a content - see [`crate::v1::api::Client::embed`].
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct EmbedContentRequest {
+        /// The resource name of the model, e.g. `models/text-embedding-004`. Set by the client for batches.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub model: Option<String>,
+        pub content: Content,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub task_type: Option<TaskType>,
+        /// The title of the document, only used with [`TaskType::RetrievalDocument`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+        /// Truncates the embedding to this many dimensions, if supported by the model.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub output_dimensionality: Option<u32>,
+    }
+    impl EmbedContentRequest {
+        /// Creates a request to embed a text.
+        pub fn new(text: impl Into<String>) -> Self {
+            EmbedContentRequest {
+                model: None,
+                content: Content {
+                    role: Role::User,
+                    parts: vec![Part {
+                        text: Some(text.into()),
+                        ..Default::default()
+                    }],
+                },
+                task_type: None,
+                title: None,
+                output_dimensionality: None,
+            }
+        }
+    }
+
+    /// The task the embedding will be used for, which lets the model optimise it.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum TaskType {
+        TaskTypeUnspecified,
+        RetrievalQuery,
+        RetrievalDocument,
+        SemanticSimilarity,
+        Classification,
+        Clustering,
+        QuestionAnswering,
+        FactVerification,
+        CodeRetrievalQuery,
+    }
+
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct SafetySettings {
         pub category: HarmCategory,
         pub threshold: HarmBlockThreshold,
     }
-    #[derive(Debug, Clone, Deserialize, Serialize)]
+    /// Configuration options for model generation and outputs.
+    /// Unset (`None`) fields are omitted from the request, so the model defaults apply.
+    ///
+    /// Use [`GenerationConfig::builder`] to build a validated configuration.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct GenerationConfig {
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub temperature: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub top_p: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub top_k: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub candidate_count: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub max_output_tokens: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub stop_sequences: Option<Vec<String>>,
 
         #[cfg(feature = "beta")]
         #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub response_mime_type: Option<String>,
 
         #[cfg(feature = "beta")]
         #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub response_schema: Option<serde_json::Value>,
+
+        /// Penalises tokens that already appear in the generated text. Range: `[-2.0, 2.0)`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub presence_penalty: Option<f32>,
+        /// Penalises tokens proportionally to how often they appear in the generated text. Range: `[-2.0, 2.0)`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub frequency_penalty: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub seed: Option<i32>,
+        /// If true, the log probabilities of the chosen tokens are returned in [`super::response::Candidate::logprobs_result`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub response_logprobs: Option<bool>,
+        /// The number of top candidate log probabilities to return at each step. Requires `response_logprobs`. Range: `[0, 20]`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub logprobs: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub response_modalities: Option<Vec<Modality>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub speech_config: Option<SpeechConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub thinking_config: Option<ThinkingConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub media_resolution: Option<MediaResolution>,
+    }
+    impl GenerationConfig {
+        pub fn builder() -> GenerationConfigBuilder {
+            GenerationConfigBuilder::default()
+        }
+
+        /// Checks the values are within the ranges accepted by the API, so an invalid config fails locally rather
+        /// than with an HTTP 400.
+        pub fn validate(&self) -> Result<(), GoogleAPIError> {
+            let problems = self.get_range_violations();
+            if problems.is_empty() {
+                Ok(())
+            } else {
+                Err(GoogleAPIError {
+                    message: format!("Invalid generation config: {}", problems.join("; ")),
+                    code: None,
+                    rate_limit: None,
+                })
+            }
+        }
+
+        /// Returns a description of each value that is out of range.
+        pub(crate) fn get_range_violations(&self) -> Vec<String> {
+            fn check<T: PartialOrd + std::fmt::Display>(
+                problems: &mut Vec<String>,
+                name: &str,
+                value: Option<T>,
+                in_range: impl Fn(&T) -> bool,
+                range: &str,
+            ) {
+                if let Some(value) = value {
+                    if !in_range(&value) {
+                        problems.push(format!("{} must be in {}, got {}", name, range, value));
+                    }
+                }
+            }
+
+            let mut problems = Vec::new();
+            check(
+                &mut problems,
+                "temperature",
+                self.temperature,
+                |v| (0.0..=2.0).contains(v),
+                "[0.0, 2.0]",
+            );
+            check(
+                &mut problems,
+                "top_p",
+                self.top_p,
+                |v| (0.0..=1.0).contains(v),
+                "[0.0, 1.0]",
+            );
+            check(&mut problems, "top_k", self.top_k, |v| *v >= 1, "[1, ..)");
+            check(
+                &mut problems,
+                "candidate_count",
+                self.candidate_count,
+                |v| (1..=8).contains(v),
+                "[1, 8]",
+            );
+            check(
+                &mut problems,
+                "max_output_tokens",
+                self.max_output_tokens,
+                |v| *v >= 1,
+                "[1, ..)",
+            );
+            check(
+                &mut problems,
+                "presence_penalty",
+                self.presence_penalty,
+                |v| (-2.0..2.0).contains(v),
+                "[-2.0, 2.0)",
+            );
+            check(
+                &mut problems,
+                "frequency_penalty",
+                self.frequency_penalty,
+                |v| (-2.0..2.0).contains(v),
+                "[-2.0, 2.0)",
+            );
+            check(
+                &mut problems,
+                "logprobs",
+                self.logprobs,
+                |v| (0..=20).contains(v),
+                "[0, 20]",
+            );
+            if let Some(thinking_config) = &self.thinking_config {
+                // -1 enables dynamic thinking
+                check(
+                    &mut problems,
+                    "thinking_budget",
+                    thinking_config.thinking_budget,
+                    |v| *v >= -1,
+                    "[-1, ..)",
+                );
+            }
+            if let Some(stop_sequences) = &self.stop_sequences {
+                if stop_sequences.len() > MAX_STOP_SEQUENCES {
+                    problems.push(format!(
+                        "stop_sequences must have at most {} entries, got {}",
+                        MAX_STOP_SEQUENCES,
+                        stop_sequences.len()
+                    ));
+                }
+            }
+            if self.logprobs.is_some() && self.response_logprobs != Some(true) {
+                problems.push("logprobs requires response_logprobs to be true".to_string());
+            }
+            problems
+        }
+    }
+
+    /// The maximum number of stop sequences accepted by the API.
+    pub(crate) const MAX_STOP_SEQUENCES: usize = 5;
+
+    /// Builds a [`GenerationConfig`], validating the values on [`GenerationConfigBuilder::build`].
+    ///
+    /// ```
+    /// use google_generative_ai_rs::v1::gemini::request::GenerationConfig;
+    ///
+    /// let config = GenerationConfig::builder()
+    ///     .temperature(0.4)
+    ///     .max_output_tokens(1024)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert!(GenerationConfig::builder().temperature(2.5).build().is_err());
+    /// ```
+    #[derive(Debug, Clone, Default)]
+    pub struct GenerationConfigBuilder {
+        config: GenerationConfig,
+    }
+    impl GenerationConfigBuilder {
+        pub fn temperature(mut self, temperature: f32) -> Self {
+            self.config.temperature = Some(temperature);
+            self
+        }
+        pub fn top_p(mut self, top_p: f32) -> Self {
+            self.config.top_p = Some(top_p);
+            self
+        }
+        pub fn top_k(mut self, top_k: i32) -> Self {
+            self.config.top_k = Some(top_k);
+            self
+        }
+        pub fn candidate_count(mut self, candidate_count: i32) -> Self {
+            self.config.candidate_count = Some(candidate_count);
+            self
+        }
+        pub fn max_output_tokens(mut self, max_output_tokens: i32) -> Self {
+            self.config.max_output_tokens = Some(max_output_tokens);
+            self
+        }
+        pub fn stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+            self.config.stop_sequences = Some(stop_sequences);
+            self
+        }
+        #[cfg(feature = "beta")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+        pub fn response_mime_type(mut self, response_mime_type: String) -> Self {
+            self.config.response_mime_type = Some(response_mime_type);
+            self
+        }
+        #[cfg(feature = "beta")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "beta")))]
+        pub fn response_schema(mut self, response_schema: serde_json::Value) -> Self {
+            self.config.response_schema = Some(response_schema);
+            self
+        }
+        pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+            self.config.presence_penalty = Some(presence_penalty);
+            self
+        }
+        pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+            self.config.frequency_penalty = Some(frequency_penalty);
+            self
+        }
+        pub fn seed(mut self, seed: i32) -> Self {
+            self.config.seed = Some(seed);
+            self
+        }
+        /// Returns the log probabilities of the chosen tokens and, if `top` is set, of the `top` candidates at each step.
+        pub fn logprobs(mut self, top: Option<i32>) -> Self {
+            self.config.response_logprobs = Some(true);
+            self.config.logprobs = top;
+            self
+        }
+        pub fn response_modalities(mut self, response_modalities: Vec<Modality>) -> Self {
+            self.config.response_modalities = Some(response_modalities);
+            self
+        }
+        /// Uses a prebuilt voice, e.g. `"Kore"`, for audio output.
+        pub fn voice(mut self, voice_name: String) -> Self {
+            self.config.speech_config = Some(SpeechConfig {
+                voice_config: Some(VoiceConfig {
+                    prebuilt_voice_config: Some(PrebuiltVoiceConfig { voice_name }),
+                }),
+                language_code: None,
+            });
+            self
+        }
+        pub fn speech_config(mut self, speech_config: SpeechConfig) -> Self {
+            self.config.speech_config = Some(speech_config);
+            self
+        }
+        /// Sets the thinking budget in tokens. `0` disables thinking and `-1` lets the model decide.
+        pub fn thinking_budget(mut self, thinking_budget: i32) -> Self {
+            let thinking_config = self
+                .config
+                .thinking_config
+                .get_or_insert_with(Default::default);
+            thinking_config.thinking_budget = Some(thinking_budget);
+            self
+        }
+        /// Returns summaries of the model's thoughts as parts flagged as thoughts.
+        pub fn include_thoughts(mut self, include_thoughts: bool) -> Self {
+            let thinking_config = self
+                .config
+                .thinking_config
+                .get_or_insert_with(Default::default);
+            thinking_config.include_thoughts = Some(include_thoughts);
+            self
+        }
+        pub fn media_resolution(mut self, media_resolution: MediaResolution) -> Self {
+            self.config.media_resolution = Some(media_resolution);
+            self
+        }
+        pub fn build(self) -> Result<GenerationConfig, GoogleAPIError> {
+            self.config.validate()?;
+            Ok(self.config)
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SpeechConfig {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub voice_config: Option<VoiceConfig>,
+        /// BCP 47 language code, e.g. `en-US`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub language_code: Option<String>,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct VoiceConfig {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub prebuilt_voice_config: Option<PrebuiltVoiceConfig>,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PrebuiltVoiceConfig {
+        pub voice_name: String,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ThinkingConfig {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub thinking_budget: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub include_thoughts: Option<bool>,
+    }
+    /// The resolution used for media inputs.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum MediaResolution {
+        MediaResolutionUnspecified,
+        MediaResolutionLow,
+        MediaResolutionMedium,
+        MediaResolutionHigh,
     }
 
     #[cfg(feature = "beta")]
@@ -365,6 +1187,133 @@ pub mod request {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub text: Option<String>,
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::v1::gemini::{Modality, Part};
+
+        #[test]
+        fn test_tools_serialize_as_single_tool_kind() {
+            let tools = vec![
+                Tools::google_search(),
+                Tools::google_search_retrieval(Some(0.5)),
+                Tools::code_execution(),
+                Tools::vertex_ai_search(
+                    "projects/p/locations/global/collections/default_collection/dataStores/d"
+                        .to_string(),
+                ),
+                Tools::vertex_rag_store(vec![
+                    "projects/p/locations/us-central1/ragCorpora/1".to_string()
+                ]),
+            ];
+
+            assert_eq!(
+                serde_json::to_value(&tools).unwrap(),
+                serde_json::json!([
+                    { "googleSearch": {} },
+                    { "googleSearchRetrieval": { "dynamicRetrievalConfig": { "mode": "MODE_DYNAMIC", "dynamicThreshold": 0.5 } } },
+                    { "codeExecution": {} },
+                    { "retrieval": { "vertexAiSearch": { "datastore": "projects/p/locations/global/collections/default_collection/dataStores/d" } } },
+                    { "retrieval": { "vertexRagStore": { "ragResources": [{ "ragCorpus": "projects/p/locations/us-central1/ragCorpora/1" }] } } }
+                ])
+            );
+        }
+
+        #[test]
+        fn test_function_declarations_tool_is_unchanged() {
+            let tool: Tools = serde_json::from_value(serde_json::json!({
+                "functionDeclarations": [{ "name": "f", "description": "d", "parameters": {} }]
+            }))
+            .unwrap();
+
+            assert_eq!(tool.function_declarations.len(), 1);
+            assert!(tool.google_search.is_none());
+            assert_eq!(
+                serde_json::to_value(&tool).unwrap(),
+                serde_json::json!({ "functionDeclarations": [{ "name": "f", "description": "d", "parameters": {} }] })
+            );
+        }
+
+        #[test]
+        fn test_generation_config_omits_unset_fields() {
+            let config = GenerationConfig::builder()
+                .temperature(0.5)
+                .seed(42)
+                .thinking_budget(1024)
+                .include_thoughts(true)
+                .response_modalities(vec![Modality::Text, Modality::Audio])
+                .voice("Kore".to_string())
+                .media_resolution(MediaResolution::MediaResolutionLow)
+                .build()
+                .unwrap();
+
+            assert_eq!(
+                serde_json::to_value(&config).unwrap(),
+                serde_json::json!({
+                    "temperature": 0.5,
+                    "seed": 42,
+                    "responseModalities": ["TEXT", "AUDIO"],
+                    "speechConfig": { "voiceConfig": { "prebuiltVoiceConfig": { "voiceName": "Kore" } } },
+                    "thinkingConfig": { "thinkingBudget": 1024, "includeThoughts": true },
+                    "mediaResolution": "MEDIA_RESOLUTION_LOW"
+                })
+            );
+            assert_eq!(
+                serde_json::to_value(GenerationConfig::default()).unwrap(),
+                serde_json::json!({})
+            );
+        }
+
+        #[test]
+        fn test_generation_config_validation_reports_every_violation() {
+            let error = GenerationConfig::builder()
+                .temperature(2.5)
+                .top_p(1.5)
+                .presence_penalty(2.0)
+                .stop_sequences(vec!["a".to_string(); 6])
+                .build()
+                .unwrap_err();
+
+            assert!(error
+                .message
+                .contains("temperature must be in [0.0, 2.0], got 2.5"));
+            assert!(error
+                .message
+                .contains("top_p must be in [0.0, 1.0], got 1.5"));
+            assert!(error.message.contains("presence_penalty"));
+            assert!(error
+                .message
+                .contains("stop_sequences must have at most 5 entries, got 6"));
+
+            let config = GenerationConfig {
+                logprobs: Some(3),
+                ..Default::default()
+            };
+            assert!(config.validate().is_err());
+            assert!(GenerationConfig::builder()
+                .logprobs(Some(3))
+                .build()
+                .is_ok());
+        }
+
+        #[test]
+        fn test_code_execution_parts_deserialize() {
+            let parts: Vec<Part> = serde_json::from_value(serde_json::json!([
+                { "executableCode": { "language": "PYTHON", "code": "print(1 + 1)" } },
+                { "codeExecutionResult": { "outcome": "OUTCOME_OK", "output": "2\n" } }
+            ]))
+            .unwrap();
+
+            assert_eq!(
+                parts[0].executable_code.as_ref().unwrap().language,
+                Language::Python
+            );
+            let result = parts[1].code_execution_result.as_ref().unwrap();
+            assert_eq!(result.outcome, Outcome::OutcomeOk);
+            assert_eq!(result.output.as_deref(), Some("2\n"));
+        }
+    }
 }
 
 /// The response format follows the following structure:
@@ -402,7 +1351,41 @@ pub mod request {
 ///             }
 ///           }
 ///         ]
-///       }
+///       },
+///       "groundingMetadata": {
+///         "webSearchQueries": [
+///           string
+///         ],
+///         "searchEntryPoint": {
+///           "renderedContent": string,
+///           "sdkBlob": string
+///         },
+///         "groundingChunks": [
+///           {
+///             "web": {
+///               "uri": string,
+///               "title": string
+///             }
+///           }
+///         ],
+///         "groundingSupports": [
+///           {
+///             "segment": {
+///               "partIndex": integer,
+///               "startIndex": integer,
+///               "endIndex": integer,
+///               "text": string
+///             },
+///             "groundingChunkIndices": [
+///               integer
+///             ],
+///             "confidenceScores": [
+///               number
+///             ]
+///           }
+///         ]
+///       },
+///       "avgLogprobs": number
 ///     }
 ///   ],
 ///   "usageMetadata": {
@@ -416,12 +1399,12 @@ pub mod response {
     use core::fmt;
     use futures::Stream;
     use reqwest_streams::error::StreamBodyError;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use std::pin::Pin;
 
     use super::{
         safety::{HarmCategory, HarmProbability},
-        Content,
+        Content, Modality,
     };
 
     impl fmt::Debug for StreamedGeminiResponse {
@@ -434,10 +1417,110 @@ pub mod response {
         Pin<Box<dyn Stream<Item = Result<serde_json::Value, StreamBodyError>> + Send>>;
 
     /// The token count for a given prompt.
-    #[derive(Debug, Default, Deserialize)]
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct TokenCount {
         pub total_tokens: u64,
+        /// The number of billable characters in the prompt. Only returned by Vertex AI.
+        #[serde(default)]
+        pub total_billable_characters: Option<u64>,
+        /// The number of tokens in the cached part of the prompt, if cached content is used.
+        #[serde(default)]
+        pub cached_content_token_count: Option<u64>,
+        /// The token count of the prompt, per modality.
+        #[serde(default)]
+        pub prompt_tokens_details: Vec<ModalityTokenCount>,
+        /// The token count of the cached content, per modality.
+        #[serde(default)]
+        pub cache_tokens_details: Vec<ModalityTokenCount>,
+    }
+    impl TokenCount {
+        /// Returns the prompt token count for the modality, if the API reported it.
+        pub fn prompt_tokens_for(&self, modality: &Modality) -> Option<u64> {
+            self.prompt_tokens_details
+                .iter()
+                .find(|details| &details.modality == modality)
+                .map(|details| details.token_count)
+        }
+    }
+
+    /// The number of tokens for a single modality.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModalityTokenCount {
+        pub modality: Modality,
+        #[serde(default)]
+        pub token_count: u64,
+    }
+
+    /// An embedding of a content.
+    #[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+    pub struct ContentEmbedding {
+        #[serde(default)]
+        pub values: Vec<f32>,
+    }
+
+    /// The `embedContent` response.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct EmbedContentResponse {
+        pub embedding: ContentEmbedding,
+    }
+
+    /// The `batchEmbedContents` response.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct BatchEmbedContentsResponse {
+        #[serde(default)]
+        pub embeddings: Vec<ContentEmbedding>,
+    }
+
+    /// The tokens of a prompt, as returned by the Vertex AI `computeTokens` method.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ComputedTokens {
+        #[serde(default)]
+        pub tokens_info: Vec<TokensInfo>,
+    }
+    impl ComputedTokens {
+        /// Returns the total number of tokens over all the contents.
+        pub fn total_tokens(&self) -> usize {
+            self.tokens_info
+                .iter()
+                .map(|info| info.token_ids.len())
+                .sum()
+        }
+    }
+
+    /// The tokens of a single content.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TokensInfo {
+        /// The tokens, as base64 encoded bytes.
+        #[serde(default)]
+        pub tokens: Vec<String>,
+        #[serde(default, deserialize_with = "deserialize_token_ids")]
+        pub token_ids: Vec<i64>,
+        pub role: Option<String>,
+    }
+
+    /// Token IDs are int64s, which the API encodes as JSON strings.
+    fn deserialize_token_ids<'de, D>(deserializer: D) -> Result<Vec<i64>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum TokenId {
+            Number(i64),
+            String(String),
+        }
+
+        Vec::<TokenId>::deserialize(deserializer)?
+            .into_iter()
+            .map(|id| match id {
+                TokenId::Number(id) => Ok(id),
+                TokenId::String(id) => id.parse().map_err(serde::de::Error::custom),
+            })
+            .collect()
     }
 
     // The streamGenerateContent response
@@ -446,14 +1529,19 @@ pub mod response {
         pub response_stream: Option<ResponseJsonStream>,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct GeminiResponse {
         pub candidates: Vec<Candidate>,
         pub prompt_feedback: Option<PromptFeedback>,
         pub usage_metadata: Option<UsageMetadata>,
+        /// The model version used to generate the response.
+        #[serde(default)]
+        pub model_version: Option<String>,
+        #[serde(default)]
+        pub response_id: Option<String>,
     }
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub enum GeminiErrorResponse {
         Error {
@@ -479,8 +1567,26 @@ pub mod response {
             }
             text_count
         }
+
+        /// Returns the answer text of the first candidate, excluding any thoughts.
+        /// For a streamed response, this is the answer text of the chunk.
+        pub fn get_text(&self) -> String {
+            self.candidates
+                .first()
+                .map(|candidate| candidate.content.get_text())
+                .unwrap_or_default()
+        }
+
+        /// Returns the thought summary text of the first candidate.
+        /// For a streamed response, this is the thought text of the chunk.
+        pub fn get_thought_text(&self) -> String {
+            self.candidates
+                .first()
+                .map(|candidate| candidate.content.get_thought_text())
+                .unwrap_or_default()
+        }
     }
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Candidate {
         pub content: Content,
@@ -488,20 +1594,299 @@ pub mod response {
         pub index: Option<i32>,
         #[serde(default)]
         pub safety_ratings: Vec<SafetyRating>,
+        pub citation_metadata: Option<CitationMetadata>,
+        pub grounding_metadata: Option<GroundingMetadata>,
+        pub avg_logprobs: Option<f64>,
+        /// Returned when [`super::request::GenerationConfig::response_logprobs`] is set.
+        pub logprobs_result: Option<LogprobsResult>,
     }
-    #[derive(Debug, Clone, Deserialize)]
+    impl Candidate {
+        /// Renders the candidate text with inline citation markers, e.g. `"Some claim.[1][2]"`, and returns the
+        /// numbered list of sources the markers refer to.
+        ///
+        /// Grounding supports are positioned using their segment `partIndex` and byte offsets within that part, while
+        /// citations are positioned using byte offsets into the concatenated text of the candidate.
+        /// Sources are numbered in order of first appearance and de-duplicated by URI.
+        pub fn get_text_with_citations(&self) -> CitedText {
+            let parts: Vec<&str> = self
+                .content
+                .parts
+                .iter()
+                .map(|part| part.text.as_deref().unwrap_or_default())
+                .collect();
+
+            let mut sources: Vec<CitationSource> = Vec::new();
+            // (part index, byte offset, source number)
+            let mut markers: Vec<(usize, usize, usize)> = Vec::new();
+
+            if let Some(grounding) = &self.grounding_metadata {
+                for support in &grounding.grounding_supports {
+                    let segment = match &support.segment {
+                        Some(segment) => segment,
+                        None => continue,
+                    };
+                    for chunk_index in &support.grounding_chunk_indices {
+                        let source = match grounding.grounding_chunks.get(*chunk_index) {
+                            Some(chunk) => chunk.to_source(),
+                            None => continue,
+                        };
+                        let number = CitationSource::number_for(&mut sources, source);
+                        markers.push((segment.part_index, segment.end_index, number));
+                    }
+                }
+            }
+
+            if let Some(citation_metadata) = &self.citation_metadata {
+                for citation in &citation_metadata.citations {
+                    let number = CitationSource::number_for(
+                        &mut sources,
+                        CitationSource {
+                            number: 0,
+                            title: citation.title.clone(),
+                            uri: citation.uri.clone(),
+                            license: citation.license.clone(),
+                        },
+                    );
+                    // Map the offset into the concatenated text onto a part and an offset within it
+                    let mut offset = citation.end_index;
+                    let mut part_index = 0;
+                    while part_index + 1 < parts.len() && offset > parts[part_index].len() {
+                        offset -= parts[part_index].len();
+                        part_index += 1;
+                    }
+                    markers.push((part_index, offset, number));
+                }
+            }
+
+            markers.sort();
+            markers.dedup();
+
+            let mut text = String::new();
+            for (part_index, part) in parts.iter().enumerate() {
+                let mut position = 0;
+                for (_, offset, number) in markers.iter().filter(|(i, _, _)| *i == part_index) {
+                    // Offsets are in bytes, so never split a multi-byte character
+                    let mut offset = (*offset).min(part.len());
+                    while !part.is_char_boundary(offset) {
+                        offset += 1;
+                    }
+                    if offset > position {
+                        text.push_str(&part[position..offset]);
+                        position = offset;
+                    }
+                    text.push_str(&format!("[{}]", number));
+                }
+                text.push_str(&part[position..]);
+            }
+
+            CitedText { text, sources }
+        }
+    }
+    /// Candidate text annotated with citation markers, along with the sources the markers refer to.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct CitedText {
+        pub text: String,
+        pub sources: Vec<CitationSource>,
+    }
+    impl fmt::Display for CitedText {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.text)?;
+            if !self.sources.is_empty() {
+                write!(f, "\n\nSources:")?;
+                for source in &self.sources {
+                    write!(f, "\n{}", source)?;
+                }
+            }
+            Ok(())
+        }
+    }
+    /// A numbered source referred to by a citation marker.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct CitationSource {
+        pub number: usize,
+        pub title: Option<String>,
+        pub uri: Option<String>,
+        pub license: Option<String>,
+    }
+    impl CitationSource {
+        /// Returns the number of an already listed source with the same URI, or appends the source to the list.
+        fn number_for(sources: &mut Vec<CitationSource>, mut source: CitationSource) -> usize {
+            if source.uri.is_some() {
+                if let Some(existing) = sources.iter().find(|s| s.uri == source.uri) {
+                    return existing.number;
+                }
+            }
+            source.number = sources.len() + 1;
+            sources.push(source);
+            sources.len()
+        }
+    }
+    impl fmt::Display for CitationSource {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "[{}]", self.number)?;
+            if let Some(title) = &self.title {
+                write!(f, " {}", title)?;
+            }
+            if let Some(uri) = &self.uri {
+                write!(f, " <{}>", uri)?;
+            }
+            if let Some(license) = &self.license {
+                write!(f, " ({})", license)?;
+            }
+            Ok(())
+        }
+    }
+    /// A collection of source attributions for the content of a candidate.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct CitationMetadata {
+        /// Vertex AI returns `citations`, the public API returns `citationSources`.
+        #[serde(default, alias = "citationSources")]
+        pub citations: Vec<Citation>,
+    }
+    /// A citation to a source for a portion of a candidate's content.
+    /// The indexes are measured in bytes.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Citation {
+        #[serde(default)]
+        pub start_index: usize,
+        #[serde(default)]
+        pub end_index: usize,
+        pub uri: Option<String>,
+        pub title: Option<String>,
+        pub license: Option<String>,
+        pub publication_date: Option<PublicationDate>,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct PublicationDate {
+        pub year: Option<i32>,
+        pub month: Option<i32>,
+        pub day: Option<i32>,
+    }
+    /// Metadata returned when grounding (e.g. Google Search or a retrieval tool) is enabled.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GroundingMetadata {
+        #[serde(default)]
+        pub web_search_queries: Vec<String>,
+        pub search_entry_point: Option<SearchEntryPoint>,
+        #[serde(default)]
+        pub grounding_chunks: Vec<GroundingChunk>,
+        #[serde(default)]
+        pub grounding_supports: Vec<GroundingSupport>,
+        pub retrieval_metadata: Option<RetrievalMetadata>,
+    }
+    /// The Google Search entry point that must be displayed alongside grounded responses.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SearchEntryPoint {
+        /// Web content snippet (HTML and CSS) that can be embedded in a web page or app webview.
+        pub rendered_content: Option<String>,
+        /// Base64 encoded JSON representing an array of search term and search url tuples.
+        pub sdk_blob: Option<String>,
+    }
+    /// A source the response was grounded on. Only one of the fields is set.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GroundingChunk {
+        pub web: Option<WebChunk>,
+        pub retrieved_context: Option<RetrievedContextChunk>,
+    }
+    impl GroundingChunk {
+        fn to_source(&self) -> CitationSource {
+            let (title, uri) = match (&self.web, &self.retrieved_context) {
+                (Some(web), _) => (web.title.clone(), web.uri.clone()),
+                (None, Some(context)) => (context.title.clone(), context.uri.clone()),
+                (None, None) => (None, None),
+            };
+            CitationSource {
+                number: 0,
+                title,
+                uri,
+                license: None,
+            }
+        }
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct WebChunk {
+        pub uri: Option<String>,
+        pub title: Option<String>,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct RetrievedContextChunk {
+        pub uri: Option<String>,
+        pub title: Option<String>,
+        pub text: Option<String>,
+    }
+    /// Links a segment of the response to the grounding chunks that support it.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GroundingSupport {
+        pub segment: Option<Segment>,
+        #[serde(default)]
+        pub grounding_chunk_indices: Vec<usize>,
+        #[serde(default)]
+        pub confidence_scores: Vec<f32>,
+    }
+    /// A segment of a candidate part. The indexes are measured in bytes from the start of the part.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Segment {
+        #[serde(default)]
+        pub part_index: usize,
+        #[serde(default)]
+        pub start_index: usize,
+        #[serde(default)]
+        pub end_index: usize,
+        pub text: Option<String>,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RetrievalMetadata {
+        pub google_search_dynamic_retrieval_score: Option<f32>,
+    }
+    /// The log probabilities of the chosen tokens and of the top candidate tokens at each decoding step.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct LogprobsResult {
+        /// Has length equal to the number of decoding steps.
+        #[serde(default)]
+        pub top_candidates: Vec<TopCandidates>,
+        /// Has length equal to the number of decoding steps. The chosen candidates may or may not be in `top_candidates`.
+        #[serde(default)]
+        pub chosen_candidates: Vec<LogprobCandidate>,
+    }
+    /// The candidates with the top log probabilities at a decoding step, sorted by log probability in descending order.
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct TopCandidates {
+        #[serde(default)]
+        pub candidates: Vec<LogprobCandidate>,
+    }
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct LogprobCandidate {
+        pub token: Option<String>,
+        pub token_id: Option<i64>,
+        pub log_probability: Option<f32>,
+    }
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct UsageMetadata {
+        #[serde(default)]
         pub prompt_token_count: u64,
+        /// Missing from the chunks of a streamed response before the last one.
+        #[serde(default)]
         pub candidates_token_count: u64,
+        #[serde(default)]
+        pub total_token_count: u64,
     }
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct PromptFeedback {
         #[serde(rename = "safetyRatings")]
         pub safety_ratings: Vec<SafetyRating>,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct SafetyRating {
         pub category: HarmCategory,
         pub probability: HarmProbability,
@@ -510,7 +1895,7 @@ pub mod response {
     }
 
     /// The reason why the model stopped generating tokens. If empty, the model has not stopped generating the tokens.
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
     pub enum FinishReason {
         FinishReasonUnspecified, // The finish reason is unspecified.
@@ -521,7 +1906,154 @@ pub mod response {
         FinishReasonOther,      // All other reasons that stopped the token
     }
     #[cfg(test)]
-    mod tests {}
+    mod tests {
+        use super::*;
+
+        fn candidate_from_json(json: serde_json::Value) -> Candidate {
+            serde_json::from_value(json).unwrap()
+        }
+
+        #[test]
+        fn test_token_count_breakdown() {
+            let count: TokenCount = serde_json::from_value(serde_json::json!({
+                "totalTokens": 300,
+                "cachedContentTokenCount": 40,
+                "promptTokensDetails": [
+                    {"modality": "TEXT", "tokenCount": 42},
+                    {"modality": "IMAGE", "tokenCount": 258}
+                ]
+            }))
+            .unwrap();
+
+            assert_eq!(count.total_tokens, 300);
+            assert_eq!(count.cached_content_token_count, Some(40));
+            assert_eq!(count.prompt_tokens_for(&Modality::Image), Some(258));
+            assert_eq!(count.prompt_tokens_for(&Modality::Audio), None);
+        }
+
+        #[test]
+        fn test_computed_tokens_accepts_string_token_ids() {
+            let computed: ComputedTokens = serde_json::from_value(serde_json::json!({
+                "tokensInfo": [
+                    {"tokens": ["SGVsbG8="], "tokenIds": ["9259"], "role": "user"},
+                    {"tokens": ["d29y", "bGQ="], "tokenIds": [1917, 7]}
+                ]
+            }))
+            .unwrap();
+
+            assert_eq!(computed.tokens_info[0].token_ids, vec![9259]);
+            assert_eq!(computed.tokens_info[0].role.as_deref(), Some("user"));
+            assert_eq!(computed.total_tokens(), 3);
+        }
+
+        #[test]
+        fn test_thoughts_are_separated_from_the_answer() {
+            let response: GeminiResponse = serde_json::from_value(serde_json::json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [
+                        { "text": "Considering the options...", "thought": true },
+                        { "text": "The answer is 42." , "thoughtSignature": "c2lnbmF0dXJl" }
+                    ] }
+                }]
+            }))
+            .unwrap();
+
+            assert_eq!(response.get_thought_text(), "Considering the options...");
+            assert_eq!(response.get_text(), "The answer is 42.");
+        }
+
+        #[test]
+        fn test_thought_signatures_round_trip_in_history() {
+            let mut history = Content {
+                role: crate::v1::gemini::Role::Model,
+                parts: vec![],
+            };
+            let chunks: Vec<Content> = serde_json::from_value(serde_json::json!([
+                { "role": "model", "parts": [{ "text": "Let me", "thought": true }] },
+                { "role": "model", "parts": [{ "text": " check.", "thought": true }] },
+                { "role": "model", "parts": [{ "functionCall": { "name": "get_weather", "args": { "city": "Paris" } }, "thoughtSignature": "c2ln" }] },
+                { "role": "model", "parts": [{ "text": "Calling" }] },
+                { "role": "model", "parts": [{ "text": "", "thoughtSignature": "c2lnMg==" }] }
+            ]))
+            .unwrap();
+            for chunk in &chunks {
+                history.append_chunk(chunk);
+            }
+
+            assert_eq!(
+                serde_json::to_value(&history).unwrap(),
+                serde_json::json!({ "role": "model", "parts": [
+                    { "text": "Let me check.", "thought": true },
+                    { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } }, "thoughtSignature": "c2ln" },
+                    { "text": "Calling" },
+                    { "text": "", "thoughtSignature": "c2lnMg==" }
+                ] })
+            );
+        }
+
+        #[test]
+        fn test_citation_metadata_accepts_both_api_field_names() {
+            let public = candidate_from_json(serde_json::json!({
+                "content": { "role": "model", "parts": [{ "text": "Hello" }] },
+                "citationMetadata": { "citationSources": [{ "endIndex": 5, "uri": "https://a.example" }] }
+            }));
+            let vertex = candidate_from_json(serde_json::json!({
+                "content": { "role": "model", "parts": [{ "text": "Hello" }] },
+                "citationMetadata": { "citations": [{ "startIndex": 1, "endIndex": 5, "title": "A" }] },
+                "avgLogprobs": -0.25
+            }));
+
+            assert_eq!(public.citation_metadata.unwrap().citations[0].end_index, 5);
+            assert_eq!(
+                vertex.citation_metadata.unwrap().citations[0].start_index,
+                1
+            );
+            assert_eq!(vertex.avg_logprobs, Some(-0.25));
+        }
+
+        #[test]
+        fn test_get_text_with_citations_from_grounding_supports() {
+            let candidate = candidate_from_json(serde_json::json!({
+                "content": { "role": "model", "parts": [{ "text": "Rust is fast. It is safe." }] },
+                "groundingMetadata": {
+                    "webSearchQueries": ["rust language"],
+                    "groundingChunks": [
+                        { "web": { "uri": "https://rust-lang.org", "title": "rust-lang.org" } },
+                        { "web": { "uri": "https://doc.rust-lang.org", "title": "doc.rust-lang.org" } }
+                    ],
+                    "groundingSupports": [
+                        { "segment": { "endIndex": 13, "text": "Rust is fast." }, "groundingChunkIndices": [0] },
+                        { "segment": { "startIndex": 14, "endIndex": 25 }, "groundingChunkIndices": [1, 0] }
+                    ]
+                }
+            }));
+
+            let cited = candidate.get_text_with_citations();
+
+            assert_eq!(cited.text, "Rust is fast.[1] It is safe.[1][2]");
+            assert_eq!(cited.sources.len(), 2);
+            assert_eq!(
+                cited.to_string(),
+                "Rust is fast.[1] It is safe.[1][2]\n\nSources:\n[1] rust-lang.org <https://rust-lang.org>\n[2] doc.rust-lang.org <https://doc.rust-lang.org>"
+            );
+        }
+
+        #[test]
+        fn test_get_text_with_citations_respects_char_boundaries() {
+            let candidate = candidate_from_json(serde_json::json!({
+                "content": { "role": "model", "parts": [{ "text": "Caf\u{e9} " }, { "text": "ol\u{e9}" }] },
+                "citationMetadata": { "citations": [
+                    { "endIndex": 4, "uri": "https://a.example" },
+                    { "endIndex": 9, "uri": "https://b.example", "license": "MIT" }
+                ] }
+            }));
+
+            let cited = candidate.get_text_with_citations();
+
+            assert_eq!(cited.text, "Caf\u{e9}[1] ol\u{e9}[2]");
+            assert_eq!(cited.sources[1].license.as_deref(), Some("MIT"));
+        }
+    }
 }
 
 /// The safety data for HarmCategory, HarmBlockThreshold and HarmProbability
@@ -557,3 +2089,75 @@ pub mod safety {
         BlockHighAndAbove,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_information_tolerates_missing_fields() {
+        let list: ModelInformationList = serde_json::from_value(serde_json::json!({
+            "models": [
+                {
+                    "name": "models/gemini-2.5-flash",
+                    "baseModelId": "gemini-2.5-flash",
+                    "inputTokenLimit": 1048576,
+                    "supportedGenerationMethods": ["generateContent", "countTokens"],
+                    "maxTemperature": 2,
+                    "thinking": true
+                },
+                { "name": "models/aqa" }
+            ],
+            "nextPageToken": "Chxtb2RlbHM"
+        }))
+        .unwrap();
+
+        assert_eq!(list.models[0].description, "");
+        assert_eq!(list.models[0].max_temperature, Some(2.0));
+        assert_eq!(list.models[0].thinking, Some(true));
+        assert!(list.models[0].supports(&ResponseType::StreamGenerateContent));
+        assert!(!list.models[1].supports(&ResponseType::GenerateContent));
+        assert_eq!(list.next_page_token.as_deref(), Some("Chxtb2RlbHM"));
+    }
+
+    #[test]
+    fn test_model_from_str_maps_known_ids() {
+        assert_eq!(Model::from("gemini-1.5-flash"), Model::Gemini1_5Flash);
+        assert_eq!(
+            Model::from("models/text-embedding-004"),
+            Model::TextEmbedding004
+        );
+        assert_eq!(
+            "gemini-2.5-pro".parse::<Model>().unwrap(),
+            Model::Custom("gemini-2.5-pro".to_string())
+        );
+    }
+
+    #[test]
+    fn test_model_resource_names() {
+        let tuned = Model::from("tunedModels/my-tuned-model");
+
+        assert_eq!(Model::Gemini1_0Pro.resource_name(), "models/gemini-1.0-pro");
+        assert_eq!(
+            Model::from("gemini-2.5-pro").resource_name(),
+            "models/gemini-2.5-pro"
+        );
+        assert_eq!(Model::from("models/gemini-2.5-pro").id(), "gemini-2.5-pro");
+        // Both spellings are the same model, e.g. as a key of the rate limits
+        assert_eq!(
+            Model::from("models/gemini-2.5-pro"),
+            Model::from("gemini-2.5-pro")
+        );
+        assert_eq!(
+            Model::from("models/gemini-2.5-pro").resource_name(),
+            "models/gemini-2.5-pro"
+        );
+        assert_eq!(tuned.resource_name(), "tunedModels/my-tuned-model");
+        assert_eq!(tuned.id(), "my-tuned-model");
+        assert!(tuned.is_tuned());
+        assert_eq!(
+            serde_json::to_value(Model::Gemini1_5Flash).unwrap(),
+            serde_json::json!("gemini-1.5-flash")
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Runs a JSON Lines file of generation requests, e.g. a prompt file of a data pipeline, and writes the response or
+//! the error of each request, with its usage, to a JSON Lines file.
+//!
+//! Each input line is a [`JsonlRequest`], a request with a unique ID:
+//!
+//! ```text
+//! {"id": "q1", "request": {"contents": [{"role": "user", "parts": [{"text": "Hello"}]}]}}
+//! ```
+//!
+//! and each output line is a [`JsonlResult`], written as soon as its request completes. The output file is also the
+//! checkpoint of the run: running the same files again, e.g. after an interruption, only sends the requests whose ID
+//! is not in the output yet. Requests run with the concurrency, retries and rate limiting of a [`BulkGenerator`].
+//!
+//! ```no_run
+//! use google_generative_ai_rs::v1::api::Client;
+//! use google_generative_ai_rs::v1::bulk::BulkGenerator;
+//! use google_generative_ai_rs::v1::jsonl::JsonlRunner;
+//!
+//! # async fn run() -> Result<(), google_generative_ai_rs::v1::errors::GoogleAPIError> {
+//! let generator = BulkGenerator::new(Client::new("api-key".to_string())).with_concurrency(16);
+//! let summary = JsonlRunner::new(generator)
+//!     .run("prompts.jsonl", "responses.jsonl")
+//!     .await?;
+//! println!("{} succeeded, {} failed", summary.succeeded, summary.failed);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With the `cli` feature, the `gemini-jsonl` binary runs a file from the command line.
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+use crate::v1::backend::GenerativeBackend;
+use crate::v1::bulk::{BulkGenerator, BulkResult};
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::{GeminiResponse, UsageMetadata};
+
+/// A line of the input file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonlRequest {
+    /// Identifies the request in the output, unique within the file.
+    pub id: String,
+    pub request: Request,
+}
+
+/// A line of the output file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonlResult {
+    pub id: String,
+    /// The attempts made, including retries.
+    #[serde(default)]
+    pub attempts: u32,
+    /// Set if the request succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<GeminiResponse>,
+    /// Set if the request failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonlError>,
+    /// The usage of a successful request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageMetadata>,
+}
+impl JsonlResult {
+    fn new(id: String, result: BulkResult) -> Self {
+        match result.result {
+            Ok(response) => JsonlResult {
+                id,
+                attempts: result.attempts,
+                usage: response.usage_metadata.clone(),
+                response: Some(response),
+                error: None,
+            },
+            Err(error) => JsonlResult {
+                id,
+                attempts: result.attempts,
+                response: None,
+                error: Some(JsonlError {
+                    message: error.message,
+                    code: error.code.map(|code| code.as_u16()),
+                }),
+                usage: None,
+            },
+        }
+    }
+}
+
+/// The error of a failed request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct JsonlError {
+    pub message: String,
+    /// The HTTP status code, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<u16>,
+}
+
+/// The outcome of a run, see [`JsonlRunner::run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonlSummary {
+    /// The requests of the input file.
+    pub total: usize,
+    /// The requests already in the output file, which were not sent again.
+    pub skipped: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// The tokens used by the requests of this run.
+    pub prompt_token_count: u64,
+    pub candidates_token_count: u64,
+    pub total_token_count: u64,
+}
+
+/// Runs a JSON Lines file of requests with a [`BulkGenerator`] - see the [module documentation](self).
+pub struct JsonlRunner<B> {
+    generator: BulkGenerator<B>,
+    retry_failed: bool,
+}
+impl<B: GenerativeBackend> JsonlRunner<B> {
+    pub fn new(generator: BulkGenerator<B>) -> Self {
+        JsonlRunner {
+            generator,
+            retry_failed: false,
+        }
+    }
+
+    /// Also sends again the requests that failed in a previous run. Their new result is appended to the output, and
+    /// the last result of an ID is the one that counts.
+    pub fn with_retry_failed(mut self, retry_failed: bool) -> Self {
+        self.retry_failed = retry_failed;
+        self
+    }
+
+    pub fn generator(&self) -> &BulkGenerator<B> {
+        &self.generator
+    }
+
+    /// Runs the requests of the input file that are not completed in the output file, appending their results to it
+    /// as they complete.
+    ///
+    /// Errors if a file cannot be read or written, or if a line of either file is invalid. Failed requests are
+    /// written to the output, and counted in the summary, but do not fail the run.
+    /// Parameters:
+    /// * input - the path of the JSON Lines file of [`JsonlRequest`]
+    /// * output - the path of the JSON Lines file of [`JsonlResult`], created if missing
+    pub async fn run(
+        &self,
+        input: impl AsRef<Path>,
+        output: impl AsRef<Path>,
+    ) -> Result<JsonlSummary, GoogleAPIError> {
+        let (input, output) = (input.as_ref(), output.as_ref());
+        let requests = read_requests(input).await?;
+        let completed = read_checkpoint(output).await?;
+
+        let mut summary = JsonlSummary {
+            total: requests.len(),
+            ..Default::default()
+        };
+        let mut ids = Vec::new();
+        let mut pending = Vec::new();
+        for JsonlRequest { id, request } in requests {
+            match completed.get(&id) {
+                Some(succeeded) if *succeeded || !self.retry_failed => summary.skipped += 1,
+                _ => {
+                    ids.push(id);
+                    pending.push(request);
+                }
+            }
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output)
+            .await
+            .map_err(|e| file_error("open", output, e))?;
+        let mut results = self.generator.run_unordered(stream::iter(pending));
+        while let Some(result) = results.next().await {
+            let result = JsonlResult::new(std::mem::take(&mut ids[result.index]), result);
+            if let Some(usage) = &result.usage {
+                summary.prompt_token_count += usage.prompt_token_count;
+                summary.candidates_token_count += usage.candidates_token_count;
+                summary.total_token_count += usage.total_token_count;
+            }
+            if result.error.is_some() {
+                summary.failed += 1;
+            } else {
+                summary.succeeded += 1;
+            }
+
+            let mut line = serde_json::to_string(&result).map_err(|e| GoogleAPIError {
+                message: format!("Failed to serialize the result of {}: {}", result.id, e),
+                code: None,
+                rate_limit: None,
+            })?;
+            line.push('\n');
+            // Flushed line by line, so that an interrupted run loses at most the requests in flight
+            file.write_all(line.as_bytes())
+                .await
+                .map_err(|e| file_error("write", output, e))?;
+            file.flush()
+                .await
+                .map_err(|e| file_error("write", output, e))?;
+        }
+        Ok(summary)
+    }
+}
+
+/// Reads the requests of the input file, checking that their IDs are unique.
+async fn read_requests(input: &Path) -> Result<Vec<JsonlRequest>, GoogleAPIError> {
+    let content = tokio::fs::read_to_string(input)
+        .await
+        .map_err(|e| file_error("read", input, e))?;
+    let mut ids = HashSet::new();
+    let mut requests = Vec::new();
+    for (number, line) in numbered_lines(&content) {
+        let request: JsonlRequest =
+            serde_json::from_str(line).map_err(|e| line_error(input, number, e))?;
+        if !ids.insert(request.id.clone()) {
+            return Err(line_error(
+                input,
+                number,
+                format!("duplicate id {}", request.id),
+            ));
+        }
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+/// Reads the IDs of the output file, and whether their last result succeeded.
+///
+/// A last line without a newline was cut off by an interruption, and is removed so that the run can append to the file.
+async fn read_checkpoint(output: &Path) -> Result<HashMap<String, bool>, GoogleAPIError> {
+    let mut content = match tokio::fs::read_to_string(output).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(file_error("read", output, e)),
+    };
+    if !content.is_empty() && !content.ends_with('\n') {
+        let complete = content.rfind('\n').map_or(0, |newline| newline + 1);
+        content.truncate(complete);
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(output)
+            .await
+            .map_err(|e| file_error("open", output, e))?;
+        file.set_len(complete as u64)
+            .await
+            .map_err(|e| file_error("truncate", output, e))?;
+    }
+
+    let mut completed = HashMap::new();
+    for (number, line) in numbered_lines(&content) {
+        let result: JsonlResult =
+            serde_json::from_str(line).map_err(|e| line_error(output, number, e))?;
+        completed.insert(result.id, result.error.is_none());
+    }
+    Ok(completed)
+}
+
+/// Returns the non-blank lines, numbered from 1.
+fn numbered_lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+}
+
+fn file_error(action: &str, path: &Path, error: std::io::Error) -> GoogleAPIError {
+    GoogleAPIError {
+        message: format!("Failed to {} {}: {}", action, path.display(), error),
+        code: None,
+        rate_limit: None,
+    }
+}
+
+fn line_error(path: &Path, number: usize, error: impl std::fmt::Display) -> GoogleAPIError {
+    GoogleAPIError {
+        message: format!("Invalid line {} of {}: {}", number, path.display(), error),
+        code: None,
+        rate_limit: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::v1::backend::{FakeBackend, FakeCall, FakeReply};
+    use crate::v1::bulk::RetryPolicy;
+    use crate::v1::gemini::request::text_request;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jsonl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn input_line(id: &str) -> String {
+        serde_json::json!({
+            "id": id,
+            "request": text_request(id)
+        })
+        .to_string()
+    }
+
+    fn response(text: &str, tokens: u64) -> GeminiResponse {
+        serde_json::from_value(serde_json::json!({
+            "candidates": [{"content": {"role": "model", "parts": [{"text": text}]}}],
+            "usageMetadata": {"promptTokenCount": tokens, "candidatesTokenCount": 1, "totalTokenCount": tokens + 1}
+        }))
+        .unwrap()
+    }
+
+    fn sent_texts(backend: &FakeBackend) -> Vec<String> {
+        backend
+            .calls()
+            .into_iter()
+            .map(|call| match call {
+                FakeCall::GenerateContent(request) => {
+                    request.contents[0].parts[0].text.clone().unwrap()
+                }
+                call => panic!("unexpected call {:?}", call),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_writes_responses_and_errors_and_resumes_from_the_output() {
+        let dir = temp_dir("resume");
+        let (input, output) = (dir.join("prompts.jsonl"), dir.join("responses.jsonl"));
+        let lines: Vec<String> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|id| input_line(id))
+            .collect();
+        std::fs::write(&input, lines.join("\n\n")).unwrap();
+        // An interrupted run completed "a", and was cut off while writing "b"
+        std::fs::write(
+            &output,
+            format!(
+                "{}\n{{\"id\":\"b\",\"resp",
+                serde_json::json!({"id": "a", "attempts": 1, "response": response("A", 2)})
+            ),
+        )
+        .unwrap();
+
+        let backend = FakeBackend::new();
+        backend
+            .push_response(response("B", 3))
+            .push(FakeReply::Error(GoogleAPIError {
+                message: "Invalid argument".to_string(),
+                code: Some(StatusCode::BAD_REQUEST),
+                rate_limit: None,
+            }))
+            .push_response(response("D", 5));
+        let generator = BulkGenerator::new(backend)
+            .with_concurrency(1)
+            .with_retry_policy(RetryPolicy::none());
+        let runner = JsonlRunner::new(generator);
+
+        let summary = runner.run(&input, &output).await.unwrap();
+
+        assert_eq!(sent_texts(runner.generator().backend()), ["b", "c", "d"]);
+        assert_eq!(
+            summary,
+            JsonlSummary {
+                total: 4,
+                skipped: 1,
+                succeeded: 2,
+                failed: 1,
+                prompt_token_count: 8,
+                candidates_token_count: 2,
+                total_token_count: 10,
+            }
+        );
+        let results: Vec<JsonlResult> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let ids: Vec<&str> = results.iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c", "d"]);
+        assert_eq!(results[1].response.as_ref().unwrap().get_text(), "B");
+        assert_eq!(results[1].usage.as_ref().unwrap().prompt_token_count, 3);
+        assert_eq!(
+            results[2].error,
+            Some(JsonlError {
+                message: "Invalid argument".to_string(),
+                code: Some(400),
+            })
+        );
+        assert!(results[2].usage.is_none());
+
+        // A completed run sends nothing, unless failed requests are retried
+        let summary = runner.run(&input, &output).await.unwrap();
+        assert_eq!((summary.skipped, summary.succeeded), (4, 0));
+
+        runner.generator().backend().push_response(response("C", 1));
+        let summary = runner
+            .with_retry_failed(true)
+            .run(&input, &output)
+            .await
+            .unwrap();
+        assert_eq!((summary.skipped, summary.succeeded), (3, 1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_run_rejects_invalid_input_lines() {
+        let dir = temp_dir("invalid");
+        let (input, output) = (dir.join("prompts.jsonl"), dir.join("responses.jsonl"));
+        let runner = JsonlRunner::new(BulkGenerator::new(FakeBackend::new()));
+
+        std::fs::write(
+            &input,
+            format!("{}\n{}\n", input_line("a"), input_line("a")),
+        )
+        .unwrap();
+        let error = runner.run(&input, &output).await.unwrap_err();
+        assert!(error.message.contains("line 2"), "{}", error.message);
+        assert!(
+            error.message.contains("duplicate id a"),
+            "{}",
+            error.message
+        );
+
+        std::fs::write(&input, "{\"id\": \"a\"}\n").unwrap();
+        let error = runner.run(&input, &output).await.unwrap_err();
+        assert!(error.message.contains("line 1"), "{}", error.message);
+        assert!(runner.generator().backend().calls().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,566 @@
+//! A middleware chain around the HTTP calls of a client, see [`crate::v1::api::Client::with_middleware`].
+//!
+//! Each [`Middleware`] can inspect and rewrite a [`Call`] before it is sent, e.g. to inject headers, audit prompts or
+//! add default safety settings, short-circuit it with a response, e.g. from a cache, inspect and rewrite the
+//! responses, and recover from errors. The hooks work on JSON, so the same middleware applies to every operation.
+//!
+//! The middleware is layered like an onion: `before_request` hooks run in the order the middleware was added, while
+//! `after_response` and `on_error` hooks run in reverse order, only for the middleware whose `before_request` ran.
+//! For streamed calls, `after_response` is called for each chunk as it arrives.
+//!
+//! ```
+//! use async_trait::async_trait;
+//! use google_generative_ai_rs::v1::{
+//!     errors::GoogleAPIError,
+//!     middleware::{Call, Middleware},
+//! };
+//!
+//! /// Tags every request with a header.
+//! struct TenantHeader(&'static str);
+//!
+//! #[async_trait]
+//! impl Middleware for TenantHeader {
+//!     async fn before_request(&self, call: &mut Call) -> Result<Option<serde_json::Value>, GoogleAPIError> {
+//!         call.headers.insert("x-tenant", self.0.parse().unwrap());
+//!         Ok(None)
+//!     }
+//! }
+//! ```
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use reqwest_streams::JsonStreamResponse;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::v1::api::Client;
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::ResponseType;
+
+/// An HTTP call to the API, as seen by the middleware.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// The operation, from the URL path, e.g. [`ResponseType::GenerateContent`].
+    pub operation: Option<ResponseType>,
+    pub method: Method,
+    pub url: String,
+    /// The headers to send, not including the credential.
+    pub headers: HeaderMap,
+    /// The JSON body, if any.
+    pub body: Option<serde_json::Value>,
+}
+impl Call {
+    /// Returns true if the response is streamed.
+    pub fn is_streamed(&self) -> bool {
+        self.operation == Some(ResponseType::StreamGenerateContent)
+    }
+
+    /// Deserializes the body, e.g. into a [`crate::v1::gemini::request::Request`].
+    pub fn body_as<T: DeserializeOwned>(&self) -> Result<T, GoogleAPIError> {
+        serde_json::from_value(self.body.clone().unwrap_or_default()).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to deserialize the call body into {}: {}",
+                std::any::type_name::<T>(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })
+    }
+
+    /// Replaces the body, e.g. with a rewritten [`crate::v1::gemini::request::Request`].
+    pub fn set_body<T: Serialize>(&mut self, body: &T) -> Result<(), GoogleAPIError> {
+        self.body = Some(serde_json::to_value(body).map_err(|e| GoogleAPIError {
+            message: format!("Failed to serialize the call body: {}", e),
+            code: None,
+            rate_limit: None,
+        })?);
+        Ok(())
+    }
+}
+
+/// Hooks around the HTTP calls of a client. Every hook has a default that does nothing.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Called before the call is sent. The call can be modified, or a response returned to short-circuit it, in which
+    /// case the call is not sent and no later middleware is called.
+    /// For a streamed call, the response is the array of chunks.
+    async fn before_request(
+        &self,
+        _call: &mut Call,
+    ) -> Result<Option<serde_json::Value>, GoogleAPIError> {
+        Ok(None)
+    }
+
+    /// Called with the JSON body of a successful response, or with each chunk of a streamed response.
+    async fn after_response(
+        &self,
+        _call: &Call,
+        _response: &mut serde_json::Value,
+    ) -> Result<(), GoogleAPIError> {
+        Ok(())
+    }
+
+    /// Called when the call fails, including when it is rejected by the API. Return a response to recover.
+    /// Errors in the middle of a streamed response are returned by the stream instead.
+    async fn on_error(
+        &self,
+        _call: &Call,
+        error: GoogleAPIError,
+    ) -> Result<serde_json::Value, GoogleAPIError> {
+        Err(error)
+    }
+}
+
+#[async_trait]
+impl<T: Middleware + ?Sized> Middleware for Arc<T> {
+    async fn before_request(
+        &self,
+        call: &mut Call,
+    ) -> Result<Option<serde_json::Value>, GoogleAPIError> {
+        self.as_ref().before_request(call).await
+    }
+
+    async fn after_response(
+        &self,
+        call: &Call,
+        response: &mut serde_json::Value,
+    ) -> Result<(), GoogleAPIError> {
+        self.as_ref().after_response(call, response).await
+    }
+
+    async fn on_error(
+        &self,
+        call: &Call,
+        error: GoogleAPIError,
+    ) -> Result<serde_json::Value, GoogleAPIError> {
+        self.as_ref().on_error(call, error).await
+    }
+}
+
+type Chain = Arc<[Arc<dyn Middleware>]>;
+
+/// Sends the request through the client's middleware chain.
+///
+/// Every outcome that reaches the caller as a response is rebuilt as an HTTP 200 response with the final JSON, so the
+/// operations deserialize it as usual. Errors that no middleware recovers from are returned as errors.
+pub(crate) async fn dispatch(
+    client: &Client,
+    request_builder: reqwest::RequestBuilder,
+) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
+    let (http_client, request) = request_builder.build_split();
+    let mut request = request.map_err(|e| GoogleAPIError {
+        message: format!("Failed to build the request: {}", e.without_url()),
+        code: None,
+        rate_limit: None,
+    })?;
+    let chain: Chain = client.middleware.clone().into();
+
+    let mut call = Call {
+        operation: ResponseType::from_path(request.url().path()),
+        method: request.method().clone(),
+        url: request.url().to_string(),
+        headers: request.headers().clone(),
+        body: request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .and_then(|bytes| serde_json::from_slice(bytes).ok()),
+    };
+
+    // The number of middleware whose `before_request` ran
+    let mut ran = 0;
+    let mut short_circuit = None;
+    for middleware in chain.iter() {
+        ran += 1;
+        match middleware.before_request(&mut call).await {
+            Ok(None) => {}
+            Ok(Some(response)) => {
+                short_circuit = Some(response);
+                // The middleware that responded does not see its own response
+                ran -= 1;
+                break;
+            }
+            Err(error) => return recover(&chain[..ran], &call, error).await,
+        }
+    }
+
+    if let Some(response) = short_circuit {
+        let response = if call.is_streamed() {
+            let chunks = match response {
+                serde_json::Value::Array(chunks) => chunks,
+                chunk => vec![chunk],
+            };
+            let mut processed = Vec::with_capacity(chunks.len());
+            for mut chunk in chunks {
+                if let Err(error) = after_response(&chain[..ran], &call, &mut chunk).await {
+                    return recover(&chain[..ran], &call, error).await;
+                }
+                processed.push(chunk);
+            }
+            serde_json::Value::Array(processed)
+        } else {
+            let mut response = response;
+            if let Err(error) = after_response(&chain[..ran], &call, &mut response).await {
+                return recover(&chain[..ran], &call, error).await;
+            }
+            response
+        };
+        return Ok(Ok(json_response(&response)));
+    }
+
+    if let Err(error) = apply(&call, &mut request) {
+        return recover(&chain, &call, error).await;
+    }
+    let result = client
+        .transmit(reqwest::RequestBuilder::from_parts(http_client, request))
+        .await;
+
+    let response = match result {
+        Ok(Ok(response)) if response.status() == StatusCode::OK => response,
+        Ok(Ok(response)) => {
+            let error = client.new_error_from_response(response).await;
+            return recover(&chain, &call, error).await;
+        }
+        Ok(Err(e)) => {
+            let error = client.new_error_from_reqwest_error(e);
+            return recover(&chain, &call, error).await;
+        }
+        Err(error) => return recover(&chain, &call, error).await,
+    };
+
+    if call.is_streamed() {
+        return Ok(Ok(streamed_response(chain, call, response)));
+    }
+
+    let mut value = match response.json::<serde_json::Value>().await {
+        Ok(value) => value,
+        Err(e) => {
+            let error = GoogleAPIError {
+                message: format!("Failed to read the API response: {}", e.without_url()),
+                code: None,
+                rate_limit: None,
+            };
+            return recover(&chain, &call, error).await;
+        }
+    };
+    if let Err(error) = after_response(&chain, &call, &mut value).await {
+        return recover(&chain, &call, error).await;
+    }
+    Ok(Ok(json_response(&value)))
+}
+
+/// Applies the changes made by the middleware to the request.
+fn apply(call: &Call, request: &mut reqwest::Request) -> Result<(), GoogleAPIError> {
+    *request.method_mut() = call.method.clone();
+    *request.url_mut() = reqwest::Url::parse(&call.url).map_err(|e| GoogleAPIError {
+        message: format!("Invalid URL set by a middleware: {}", e),
+        code: None,
+        rate_limit: None,
+    })?;
+    *request.headers_mut() = call.headers.clone();
+    if let Some(body) = &call.body {
+        let body = serde_json::to_vec(body).map_err(|e| GoogleAPIError {
+            message: format!("Failed to serialize the call body: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+        *request.body_mut() = Some(body.into());
+    }
+    Ok(())
+}
+
+async fn after_response(
+    chain: &[Arc<dyn Middleware>],
+    call: &Call,
+    response: &mut serde_json::Value,
+) -> Result<(), GoogleAPIError> {
+    for middleware in chain.iter().rev() {
+        middleware.after_response(call, response).await?;
+    }
+    Ok(())
+}
+
+/// Passes the error through the `on_error` hooks, until one of them recovers.
+async fn recover(
+    chain: &[Arc<dyn Middleware>],
+    call: &Call,
+    mut error: GoogleAPIError,
+) -> Result<Result<reqwest::Response, reqwest::Error>, GoogleAPIError> {
+    for middleware in chain.iter().rev() {
+        match middleware.on_error(call, error).await {
+            Ok(response) => return Ok(Ok(json_response(&response))),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+fn json_response(body: &serde_json::Value) -> reqwest::Response {
+    let response = http::Response::builder()
+        .status(StatusCode::OK)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .expect("a JSON response is always valid");
+    reqwest::Response::from(response)
+}
+
+/// Re-frames a streamed response as a JSON array, passing each chunk through the `after_response` hooks as it
+/// arrives.
+fn streamed_response(chain: Chain, call: Call, response: reqwest::Response) -> reqwest::Response {
+    let call = Arc::new(call);
+    let chunks = response
+        .json_array_stream::<serde_json::Value>(2048)
+        .then(move |chunk| {
+            let chain = chain.clone();
+            let call = call.clone();
+            async move {
+                let mut chunk = chunk.map_err(|e| std::io::Error::other(e.to_string()))?;
+                after_response(&chain, &call, &mut chunk)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.message))?;
+                Ok::<_, std::io::Error>(chunk)
+            }
+        });
+    // `None` marks the end of the chunks, to close the array
+    let body = chunks
+        .map(Some)
+        .chain(stream::once(async { None }))
+        .scan(false, |opened, chunk| {
+            let piece = match chunk {
+                Some(Ok(chunk)) => {
+                    let separator = if *opened { ",\r\n" } else { "[" };
+                    *opened = true;
+                    Ok(format!("{}{}", separator, chunk))
+                }
+                Some(Err(e)) => Err(e),
+                None if *opened => Ok("]".to_string()),
+                None => Ok("[]".to_string()),
+            };
+            futures::future::ready(Some(piece))
+        });
+
+    let response = http::Response::builder()
+        .status(StatusCode::OK)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(reqwest::Body::wrap_stream(body))
+        .expect("a streamed JSON response is always valid");
+    reqwest::Response::from(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::v1::gemini::request::{text_request, Request};
+    use crate::v1::gemini::response::GeminiResponse;
+    use crate::v1::mock_server::{MockResponse, MockServer};
+
+    /// Records the hooks it runs, and optionally short-circuits or recovers.
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        respond_with: Option<serde_json::Value>,
+        recover_with: Option<serde_json::Value>,
+    }
+    impl Recorder {
+        fn new(name: &'static str, log: &Arc<Mutex<Vec<String>>>) -> Self {
+            Recorder {
+                name,
+                log: log.clone(),
+                respond_with: None,
+                recover_with: None,
+            }
+        }
+        fn record(&self, hook: &str) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{}.{}", self.name, hook));
+        }
+    }
+    #[async_trait]
+    impl Middleware for Recorder {
+        async fn before_request(
+            &self,
+            call: &mut Call,
+        ) -> Result<Option<serde_json::Value>, GoogleAPIError> {
+            self.record("before");
+            call.headers
+                .insert("x-middleware", self.name.parse().unwrap());
+            Ok(self.respond_with.clone())
+        }
+
+        async fn after_response(
+            &self,
+            _call: &Call,
+            response: &mut serde_json::Value,
+        ) -> Result<(), GoogleAPIError> {
+            self.record("after");
+            let text = &mut response["candidates"][0]["content"]["parts"][0]["text"];
+            *text = format!("{}+{}", text.as_str().unwrap_or_default(), self.name).into();
+            Ok(())
+        }
+
+        async fn on_error(
+            &self,
+            _call: &Call,
+            error: GoogleAPIError,
+        ) -> Result<serde_json::Value, GoogleAPIError> {
+            self.record("error");
+            self.recover_with.clone().ok_or(error)
+        }
+    }
+
+    /// Adds a default safety setting to generate requests.
+    struct DefaultSafetySettings;
+    #[async_trait]
+    impl Middleware for DefaultSafetySettings {
+        async fn before_request(
+            &self,
+            call: &mut Call,
+        ) -> Result<Option<serde_json::Value>, GoogleAPIError> {
+            if call.operation == Some(ResponseType::GenerateContent) {
+                let mut request: Request = call.body_as()?;
+                request.safety_settings = serde_json::from_value(serde_json::json!([
+                    {"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_LOW_AND_ABOVE"}
+                ]))
+                .unwrap();
+                call.set_body(&request)?;
+            }
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_onion_order_and_rewrite_the_call() {
+        let server = MockServer::start().await;
+        server.enqueue(ResponseType::GenerateContent, MockResponse::text("Hi"));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_middleware(Recorder::new("outer", &log))
+            .with_middleware(DefaultSafetySettings)
+            .with_middleware(Recorder::new("inner", &log));
+
+        let response = client
+            .generate_content(5, &text_request("Hello?"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.get_text(), "Hi+inner+outer");
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["outer.before", "inner.before", "inner.after", "outer.after"]
+        );
+        let request = &server.requests()[0];
+        assert_eq!(request.headers["x-middleware"], "inner");
+        assert_eq!(request.headers["x-goog-api-key"], "test-api-key");
+        assert_eq!(
+            request.json()["safetySettings"][0]["threshold"],
+            "BLOCK_LOW_AND_ABOVE"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_short_circuit_skips_the_request_and_later_middleware() {
+        let server = MockServer::start().await;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let cached =
+            serde_json::to_value(crate::v1::backend::FakeBackend::text_response("Cached")).unwrap();
+        let mut cache = Recorder::new("cache", &log);
+        cache.respond_with = Some(serde_json::Value::Array(vec![cached.clone(), cached]));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_middleware(Recorder::new("outer", &log))
+            .with_middleware(cache)
+            .with_middleware(Recorder::new("inner", &log));
+
+        let streamed = client
+            .stream_generate_content(5, &text_request("Hello?"))
+            .await
+            .unwrap();
+        let texts: Vec<String> = streamed
+            .response_stream
+            .unwrap()
+            .map(|chunk| {
+                serde_json::from_value::<GeminiResponse>(chunk.unwrap())
+                    .unwrap()
+                    .get_text()
+            })
+            .collect()
+            .await;
+
+        assert_eq!(texts, ["Cached+outer", "Cached+outer"]);
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["outer.before", "cache.before", "outer.after", "outer.after"]
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chunks_pass_through_after_response() {
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::StreamGenerateContent,
+            MockResponse::stream_text(&["a", "b", "c"]),
+        );
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_middleware(Recorder::new("m", &log));
+
+        let streamed = client
+            .stream_generate_content(5, &text_request("Hello?"))
+            .await
+            .unwrap();
+        let texts: Vec<String> = streamed
+            .response_stream
+            .unwrap()
+            .map(|chunk| {
+                serde_json::from_value::<GeminiResponse>(chunk.unwrap())
+                    .unwrap()
+                    .get_text()
+            })
+            .collect()
+            .await;
+
+        assert_eq!(texts, ["a+m", "b+m", "c+m"]);
+    }
+
+    #[tokio::test]
+    async fn test_on_error_can_recover_or_pass_the_error_on() {
+        let server = MockServer::start().await;
+        server
+            .enqueue(ResponseType::CountTokens, MockResponse::rate_limited())
+            .enqueue(ResponseType::CountTokens, MockResponse::rate_limited());
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_middleware(Recorder::new("m", &log));
+        let error = client
+            .count_tokens(5, &text_request("Hello?"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, Some(StatusCode::TOO_MANY_REQUESTS));
+
+        let mut fallback = Recorder::new("fallback", &log);
+        fallback.recover_with = Some(serde_json::json!({"totalTokens": 42}));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_middleware(fallback);
+        let count = client
+            .count_tokens(5, &text_request("Hello?"))
+            .await
+            .unwrap();
+        assert_eq!(count.total_tokens, 42);
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["m.before", "m.error", "fallback.before", "fallback.error"]
+        );
+    }
+}
@@ -0,0 +1,652 @@
+//! A local mock of the Gemini API for integration tests, enabled by the `test-support` feature.
+//!
+//! The server implements `generateContent`, `streamGenerateContent` (as a JSON array, or as server-sent events with
+//! `?alt=sse`), `countTokens`, `embedContent`, `batchEmbedContents`, `models.get` and `models.list` with canned
+//! responses. Responses can be scripted per operation, including delays, error bodies, 429s and malformed chunks,
+//! so tests can exercise the real [`crate::v1::api::Client`] code paths. Other endpoints, e.g. batches and file
+//! uploads, are served from responses scripted per method and path.
+//!
+//! ```
+//! use google_generative_ai_rs::v1::{api::Client, gemini::ResponseType, mock_server::{MockResponse, MockServer}};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let server = MockServer::start().await;
+//! server.enqueue(ResponseType::CountTokens, MockResponse::rate_limited());
+//!
+//! let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+//! # let request = serde_json::from_value(serde_json::json!({"contents": [], "tools": []})).unwrap();
+//! let error = client.count_tokens(5, &request).await.unwrap_err();
+//! assert_eq!(error.code, Some(reqwest::StatusCode::TOO_MANY_REQUESTS));
+//! assert_eq!(server.requests().len(), 1);
+//! # });
+//! ```
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::v1::backend::FakeBackend;
+use crate::v1::gemini::ResponseType;
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// The path, without the query, e.g. `/models/gemini-pro:generateContent`.
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// The headers, with lower-case names.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+impl MockRequest {
+    /// Returns the body parsed as JSON, or `Value::Null`.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_default()
+    }
+
+    /// Returns the operation of the request, from its path.
+    pub fn operation(&self) -> Option<ResponseType> {
+        ResponseType::from_path(&self.path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MockBody {
+    Json(String),
+    /// A body of any content type, e.g. a JSON Lines file.
+    Raw {
+        content_type: String,
+        body: String,
+    },
+    /// The raw chunks of a streamed response, framed as a JSON array or as server-sent events.
+    Stream(Vec<String>),
+}
+
+/// A scripted response of the [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: MockBody,
+    /// Waited before the response is sent.
+    delay: Duration,
+    /// Waited before each chunk of a streamed response is sent.
+    chunk_delay: Duration,
+}
+impl MockResponse {
+    /// A response with a JSON body.
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: MockBody::Json(body.to_string()),
+            delay: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    /// A response with a body of the content type, e.g. `application/jsonl`.
+    pub fn body(status: u16, content_type: &str, body: &str) -> Self {
+        MockResponse {
+            body: MockBody::Raw {
+                content_type: content_type.to_string(),
+                body: body.to_string(),
+            },
+            ..MockResponse::json(status, serde_json::Value::Null)
+        }
+    }
+
+    /// A `generateContent` response with a single candidate answering `text`.
+    pub fn text(text: &str) -> Self {
+        MockResponse::json(
+            200,
+            serde_json::to_value(FakeBackend::text_response(text)).unwrap_or_default(),
+        )
+    }
+
+    /// A streamed response with a chunk per value.
+    pub fn stream(chunks: Vec<serde_json::Value>) -> Self {
+        MockResponse::stream_raw(chunks.iter().map(|chunk| chunk.to_string()).collect())
+    }
+
+    /// A streamed `streamGenerateContent` response with a chunk per text.
+    pub fn stream_text(texts: &[&str]) -> Self {
+        MockResponse::stream(
+            texts
+                .iter()
+                .map(|text| {
+                    serde_json::to_value(FakeBackend::text_response(text)).unwrap_or_default()
+                })
+                .collect(),
+        )
+    }
+
+    /// A streamed response with chunks sent as is, e.g. to send malformed JSON.
+    pub fn stream_raw(chunks: Vec<String>) -> Self {
+        MockResponse {
+            status: 200,
+            headers: vec![],
+            body: MockBody::Stream(chunks),
+            delay: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    /// A Google API error body, e.g. `{"error": {"code": 404, "message": "...", "status": "NOT_FOUND"}}`.
+    pub fn error(status: u16, message: &str) -> Self {
+        MockResponse::json(
+            status,
+            serde_json::json!({
+                "error": {
+                    "code": status,
+                    "message": message,
+                    "status": error_status(status),
+                }
+            }),
+        )
+    }
+
+    /// A 429 quota error, with the retry delay the API suggests.
+    pub fn rate_limited() -> Self {
+        MockResponse::json(
+            429,
+            serde_json::json!({
+                "error": {
+                    "code": 429,
+                    "message": "Resource has been exhausted (e.g. check quota).",
+                    "status": "RESOURCE_EXHAUSTED",
+                    "details": [{
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "1s"
+                    }]
+                }
+            }),
+        )
+        .with_header("retry-after", "1")
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Waits before sending the response, e.g. to trigger a client timeout.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Waits before sending each chunk of a streamed response.
+    pub fn with_chunk_delay(mut self, chunk_delay: Duration) -> Self {
+        self.chunk_delay = chunk_delay;
+        self
+    }
+}
+
+/// The canonical status of an HTTP error code, as used in Google API error bodies.
+fn error_status(status: u16) -> &'static str {
+    match status {
+        400 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        409 => "ALREADY_EXISTS",
+        429 => "RESOURCE_EXHAUSTED",
+        499 => "CANCELLED",
+        500 => "INTERNAL",
+        503 => "UNAVAILABLE",
+        504 => "DEADLINE_EXCEEDED",
+        _ => "UNKNOWN",
+    }
+}
+
+/// What a scripted response answers.
+#[derive(Debug, Clone, PartialEq)]
+enum Route {
+    Operation(ResponseType),
+    /// The method, and the end of the path, e.g. `/batches/123:cancel`.
+    Path(String, String),
+}
+impl Route {
+    fn matches(&self, request: &MockRequest) -> bool {
+        match self {
+            Route::Operation(operation) => request.operation().as_ref() == Some(operation),
+            Route::Path(method, path) => {
+                request.method.eq_ignore_ascii_case(method) && request.path.ends_with(path.as_str())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    scripts: Mutex<Vec<(Route, MockResponse)>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+impl MockState {
+    /// Pops the first response scripted for the request, or returns the canned response.
+    fn response_for(&self, request: &MockRequest) -> MockResponse {
+        let mut scripts = self.scripts.lock().unwrap();
+        if let Some(index) = scripts.iter().position(|(route, _)| route.matches(request)) {
+            return scripts.remove(index).1;
+        }
+        drop(scripts);
+
+        match request.operation() {
+            Some(ResponseType::GenerateContent) => MockResponse::text("Hello from the mock server"),
+            Some(ResponseType::StreamGenerateContent) => {
+                MockResponse::stream_text(&["Hello", " from the", " mock server"])
+            }
+            Some(ResponseType::CountTokens) => {
+                // Roughly the heuristic of four characters per token
+                let total_tokens = (request.body.len() as u64).div_ceil(4);
+                MockResponse::json(200, serde_json::json!({ "totalTokens": total_tokens }))
+            }
+            Some(ResponseType::EmbedContent) => MockResponse::json(
+                200,
+                serde_json::json!({"embedding": {"values": [0.1, 0.2, 0.3]}}),
+            ),
+            Some(ResponseType::BatchEmbedContents) => {
+                let count = request.json()["requests"]
+                    .as_array()
+                    .map(|requests| requests.len())
+                    .unwrap_or_default();
+                let embeddings = vec![serde_json::json!({"values": [0.1, 0.2, 0.3]}); count];
+                MockResponse::json(200, serde_json::json!({ "embeddings": embeddings }))
+            }
+            Some(ResponseType::GetModel) => {
+                let name = request
+                    .path
+                    .find("models/")
+                    .map(|index| &request.path[index..])
+                    .unwrap_or_default();
+                MockResponse::json(200, model_json(name))
+            }
+            Some(ResponseType::GetModelList) => MockResponse::json(
+                200,
+                serde_json::json!({
+                    "models": [
+                        model_json("models/gemini-1.5-flash"),
+                        model_json("models/gemini-1.5-pro"),
+                        model_json("models/text-embedding-004"),
+                    ]
+                }),
+            ),
+            _ => MockResponse::error(
+                404,
+                &format!("{} {} is not implemented", request.method, request.path),
+            ),
+        }
+    }
+}
+
+fn model_json(name: &str) -> serde_json::Value {
+    let id = name.trim_start_matches("models/");
+    let methods = if id.contains("embedding") {
+        vec!["embedContent", "batchEmbedContents"]
+    } else {
+        vec!["generateContent", "countTokens"]
+    };
+    serde_json::json!({
+        "name": name,
+        "baseModelId": id,
+        "version": "001",
+        "displayName": id,
+        "description": "A mock model",
+        "inputTokenLimit": 1048576,
+        "outputTokenLimit": 8192,
+        "supportedGenerationMethods": methods,
+        "temperature": 1.0,
+        "topP": 0.95,
+        "topK": 40
+    })
+}
+
+/// A local mock of the Gemini API, listening on a random port until dropped.
+pub struct MockServer {
+    base_url: String,
+    state: Arc<MockState>,
+    handle: JoinHandle<()>,
+}
+impl MockServer {
+    /// Starts the server on `127.0.0.1`.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind the mock server");
+        let base_url = format!(
+            "http://{}/v1",
+            listener
+                .local_addr()
+                .expect("failed to get the mock server address")
+        );
+        let state = Arc::new(MockState::default());
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, accept_state.clone()));
+            }
+        });
+
+        MockServer {
+            base_url,
+            state,
+            handle,
+        }
+    }
+
+    /// The base URL of the API, to pass to [`crate::v1::api::Client::with_base_url`].
+    pub fn base_url(&self) -> String {
+        self.base_url.clone()
+    }
+
+    /// Scripts the next response to an operation. Responses for the same operation are returned in order, after
+    /// which the canned response is returned again.
+    pub fn enqueue(&self, operation: ResponseType, response: MockResponse) -> &Self {
+        self.state
+            .scripts
+            .lock()
+            .unwrap()
+            .push((Route::Operation(operation), response));
+        self
+    }
+
+    /// Scripts the next response to the requests of the method whose path ends with `path`, e.g. `GET` and
+    /// `/batches/123`, for the endpoints that are not operations on a model. Responses for the same route are
+    /// returned in order, after which the server responds with 404.
+    pub fn enqueue_route(&self, method: &str, path: &str, response: MockResponse) -> &Self {
+        self.state
+            .scripts
+            .lock()
+            .unwrap()
+            .push((Route::Path(method.to_string(), path.to_string()), response));
+        self
+    }
+
+    /// Returns the requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Serves a single request, then closes the connection.
+async fn serve(socket: TcpStream, state: Arc<MockState>) {
+    let mut reader = BufReader::new(socket);
+    let request = match read_request(&mut reader).await {
+        Some(request) => request,
+        None => return,
+    };
+    let sse = request.query.get("alt").map(String::as_str) == Some("sse");
+    state.requests.lock().unwrap().push(request.clone());
+    let response = state.response_for(&request);
+
+    tokio::time::sleep(response.delay).await;
+    let _ = write_response(reader.get_mut(), &response, sse).await;
+}
+
+async fn read_request(reader: &mut BufReader<TcpStream>) -> Option<MockRequest> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await.ok()?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    Some(MockRequest {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+async fn write_response(
+    socket: &mut TcpStream,
+    response: &MockResponse,
+    sse: bool,
+) -> std::io::Result<()> {
+    let reason = StatusCode::from_u16(response.status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("Unknown");
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Connection: close\r\n");
+
+    match &response.body {
+        MockBody::Json(body) => {
+            head.push_str(&format!(
+                "Content-Type: application/json; charset=UTF-8\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ));
+            socket.write_all(head.as_bytes()).await?;
+        }
+        MockBody::Raw { content_type, body } => {
+            head.push_str(&format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            ));
+            socket.write_all(head.as_bytes()).await?;
+        }
+        MockBody::Stream(chunks) => {
+            let content_type = if sse {
+                "text/event-stream"
+            } else {
+                "application/json; charset=UTF-8"
+            };
+            head.push_str(&format!(
+                "Content-Type: {}\r\nTransfer-Encoding: chunked\r\n\r\n",
+                content_type
+            ));
+            socket.write_all(head.as_bytes()).await?;
+
+            // Each piece is sent as its own HTTP chunk, so the client sees the same boundaries as with the API
+            let mut pieces: Vec<String> = chunks
+                .iter()
+                .enumerate()
+                .map(|(index, chunk)| match (sse, index) {
+                    (true, _) => format!("data: {}\r\n\r\n", chunk),
+                    (false, 0) => format!("[{}", chunk),
+                    (false, _) => format!(",\r\n{}", chunk),
+                })
+                .collect();
+            if !sse {
+                match pieces.last_mut() {
+                    Some(last) => last.push(']'),
+                    None => pieces.push("[]".to_string()),
+                }
+            }
+            for piece in pieces {
+                tokio::time::sleep(response.chunk_delay).await;
+                socket
+                    .write_all(format!("{:x}\r\n{}\r\n", piece.len(), piece).as_bytes())
+                    .await?;
+                socket.flush().await?;
+            }
+            socket.write_all(b"0\r\n\r\n").await?;
+        }
+    }
+    socket.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::v1::api::Client;
+    use crate::v1::gemini::request::{text_request, EmbedContentRequest};
+    use crate::v1::gemini::response::GeminiResponse;
+
+    #[tokio::test]
+    async fn test_client_against_the_canned_responses() {
+        let server = MockServer::start().await;
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+        let request = text_request("Hello?");
+
+        let response = client.generate_content(5, &request).await.unwrap();
+        assert_eq!(response.get_text(), "Hello from the mock server");
+
+        let streamed = client.stream_generate_content(5, &request).await.unwrap();
+        let text: String = streamed
+            .response_stream
+            .unwrap()
+            .map(|value| {
+                serde_json::from_value::<GeminiResponse>(value.unwrap())
+                    .unwrap()
+                    .get_text()
+            })
+            .collect()
+            .await;
+        assert_eq!(text, "Hello from the mock server");
+
+        assert!(client.count_tokens(5, &request).await.unwrap().total_tokens > 0);
+        assert_eq!(
+            client
+                .embed(5, &EmbedContentRequest::new("Hello"))
+                .await
+                .unwrap()
+                .values
+                .len(),
+            3
+        );
+        assert_eq!(
+            client.get_model(5).await.unwrap().input_token_limit,
+            1048576
+        );
+        assert_eq!(client.get_model_list(5).await.unwrap().models.len(), 3);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[0].headers["x-goog-api-key"], "test-api-key");
+        assert_eq!(
+            requests[0].json()["contents"][0]["parts"][0]["text"],
+            "Hello?"
+        );
+        assert_eq!(
+            requests[1].operation(),
+            Some(ResponseType::StreamGenerateContent)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_handles_scripted_errors_and_malformed_chunks() {
+        let server = MockServer::start().await;
+        server
+            .enqueue(
+                ResponseType::StreamGenerateContent,
+                MockResponse::rate_limited(),
+            )
+            .enqueue(
+                ResponseType::StreamGenerateContent,
+                MockResponse::stream_raw(vec![
+                    serde_json::to_string(&FakeBackend::text_response("Once")).unwrap(),
+                    "{\"candidates\": not json}".to_string(),
+                ]),
+            )
+            .enqueue(
+                ResponseType::GenerateContent,
+                MockResponse::error(400, "Invalid JSON payload"),
+            );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+        let request = text_request("Tell me a story");
+
+        let error = client
+            .stream_generate_content(5, &request)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, Some(StatusCode::TOO_MANY_REQUESTS));
+
+        let streamed = client.stream_generate_content(5, &request).await.unwrap();
+        let chunks: Vec<_> = streamed.response_stream.unwrap().collect().await;
+        assert!(chunks[0].is_ok());
+        assert!(chunks.last().unwrap().is_err());
+
+        let error = client.generate_content(5, &request).await.unwrap_err();
+        assert_eq!(error.code, Some(StatusCode::BAD_REQUEST));
+        assert!(error.message.contains("Invalid JSON payload"));
+    }
+
+    #[tokio::test]
+    async fn test_server_sent_events_and_delays() {
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::StreamGenerateContent,
+            MockResponse::stream_text(&["a", "b"]).with_chunk_delay(Duration::from_millis(10)),
+        );
+        let url = format!(
+            "{}/models/gemini-1.5-flash:streamGenerateContent?alt=sse",
+            server.base_url()
+        );
+        let response = reqwest::Client::new()
+            .post(url)
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let body = response.text().await.unwrap();
+        assert_eq!(body.matches("data: ").count(), 2);
+
+        server.enqueue(
+            ResponseType::GenerateContent,
+            MockResponse::text("Too late").with_delay(Duration::from_secs(3)),
+        );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+        let error = client
+            .generate_content(1, &text_request("Hello?"))
+            .await
+            .unwrap_err();
+        assert!(error.code.is_none());
+    }
+}
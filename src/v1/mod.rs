@@ -1,4 +1,19 @@
 pub mod api;
+pub mod auth;
+pub mod backend;
+pub mod batch;
+pub mod bulk;
+pub mod cassette;
 pub mod errors;
 pub mod gemini;
+pub mod jsonl;
+pub mod middleware;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock_server;
+pub mod operation;
+pub mod rate_limit;
+pub mod registry;
+pub mod telemetry;
+pub mod tokens;
+pub mod validation;
 pub mod vertexai;
@@ -0,0 +1,407 @@
+//! Long-running operations, returned by the asynchronous endpoints, e.g. batches, and a poller to wait for them.
+//!
+//! ```no_run
+//! use google_generative_ai_rs::v1::api::Client;
+//! use google_generative_ai_rs::v1::operation::{Operation, OperationPoller};
+//!
+//! # async fn wait(client: &Client) -> Result<(), google_generative_ai_rs::v1::errors::GoogleAPIError> {
+//! let poller = OperationPoller::default();
+//! let operation: Operation = client.wait_for_operation(30, "operations/123", &poller).await?;
+//! let response = operation.into_result()?;
+//! # Ok(())
+//! # }
+//! ```
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::v1::api::Client;
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::vertexai::VertexOptions;
+
+/// A long-running operation, see: "https://google.aip.dev/151".
+///
+/// The metadata, e.g. the progress, and the response are specific to each endpoint, and default to JSON values.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation<M = serde_json::Value, R = serde_json::Value> {
+    /// The resource name, used to get the latest state of the operation, e.g. `batches/123`.
+    pub name: String,
+    pub metadata: Option<M>,
+    #[serde(default)]
+    pub done: bool,
+    /// Set if the operation failed.
+    pub error: Option<Status>,
+    /// Set if the operation succeeded.
+    pub response: Option<R>,
+}
+impl<M, R> Operation<M, R> {
+    /// Returns the response of a successful operation, or the error of a failed one.
+    pub fn into_result(self) -> Result<R, GoogleAPIError> {
+        if !self.done {
+            return Err(GoogleAPIError {
+                message: format!("Operation {} is not done", self.name),
+                code: None,
+                rate_limit: None,
+            });
+        }
+        match (self.error, self.response) {
+            (Some(status), _) => Err(status.into()),
+            (None, Some(response)) => Ok(response),
+            (None, None) => Err(GoogleAPIError {
+                message: format!("Operation {} is done without a response", self.name),
+                code: None,
+                rate_limit: None,
+            }),
+        }
+    }
+}
+
+/// The error of an operation, or of an item of a batch, see: "https://google.aip.dev/193".
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Status {
+    /// The gRPC status code, e.g. `3` for `INVALID_ARGUMENT`.
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<serde_json::Value>,
+}
+impl Status {
+    /// Returns the HTTP status code equivalent to the gRPC status code, if any.
+    pub fn http_status(&self) -> Option<StatusCode> {
+        let code = match self.code {
+            1 => 499,
+            2 => 500,
+            3 | 9 | 11 => 400,
+            4 => 504,
+            5 => 404,
+            6 | 10 => 409,
+            7 => 403,
+            8 => 429,
+            12 => 501,
+            13 | 15 => 500,
+            14 => 503,
+            16 => 401,
+            _ => return None,
+        };
+        StatusCode::from_u16(code).ok()
+    }
+}
+impl From<Status> for GoogleAPIError {
+    fn from(status: Status) -> Self {
+        GoogleAPIError {
+            message: format!("API message: {}.", status.message),
+            code: status.http_status(),
+            rate_limit: None,
+        }
+    }
+}
+
+/// Waits for a long-running operation, or a job, to finish by getting it repeatedly.
+///
+/// The interval between polls starts at `initial_interval` and grows by `multiplier` after each poll, up to
+/// `max_interval`. With a `deadline`, polling stops with an error once it has elapsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationPoller {
+    pub initial_interval: Duration,
+    pub max_interval: Duration,
+    /// Multipliers below 1, or NaN, keep the interval constant.
+    pub multiplier: f64,
+    pub deadline: Option<Duration>,
+}
+impl Default for OperationPoller {
+    /// Polls after 5 seconds, then up to every minute, with no deadline.
+    fn default() -> Self {
+        OperationPoller {
+            initial_interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(60),
+            multiplier: 1.5,
+            deadline: None,
+        }
+    }
+}
+impl OperationPoller {
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fetches the value until it is done, returning the first done value.
+    /// Errors from `fetch` stop the polling.
+    /// Parameters:
+    /// * fetch - gets the latest state, e.g. of an operation
+    /// * is_done - returns true once the state is final
+    pub async fn poll_until<T, F, Fut>(
+        &self,
+        mut fetch: F,
+        is_done: impl Fn(&T) -> bool,
+    ) -> Result<T, GoogleAPIError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, GoogleAPIError>>,
+    {
+        // A deadline too far away to represent is no deadline
+        let deadline = self
+            .deadline
+            .and_then(|deadline| Instant::now().checked_add(deadline));
+        let mut interval = self.initial_interval;
+        loop {
+            let value = fetch().await?;
+            if is_done(&value) {
+                return Ok(value);
+            }
+            // An interval too long to represent ends past any deadline
+            if deadline.is_some_and(|deadline| {
+                Instant::now()
+                    .checked_add(interval)
+                    .is_none_or(|next_poll| next_poll > deadline)
+            }) {
+                return Err(GoogleAPIError {
+                    message: format!(
+                        "Still not done after the polling deadline of {:?}",
+                        self.deadline.unwrap_or_default()
+                    ),
+                    code: None,
+                    rate_limit: None,
+                });
+            }
+            tokio::time::sleep(interval).await;
+            interval = self.next_interval(interval);
+        }
+    }
+
+    /// Returns the interval after `interval`, without overflowing.
+    fn next_interval(&self, interval: Duration) -> Duration {
+        let multiplier = if self.multiplier.is_nan() {
+            1.0
+        } else {
+            self.multiplier.max(1.0)
+        };
+        Duration::try_from_secs_f64(interval.as_secs_f64() * multiplier)
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval)
+    }
+}
+
+impl Client {
+    /// Gets the latest state of a long-running operation.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the operation, e.g. `operations/123`
+    pub async fn get_operation<M: DeserializeOwned, R: DeserializeOwned>(
+        &self,
+        timeout: u64,
+        name: &str,
+    ) -> Result<Operation<M, R>, GoogleAPIError> {
+        let url = format!("{}/{}", self.api_base_url(), name);
+        let result = self.get(timeout, &url, &[]).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Polls a long-running operation until it is done.
+    /// Parameters:
+    /// * timeout - the timeout in seconds of each poll
+    /// * name - the resource name of the operation
+    /// * poller - when to poll, and for how long
+    pub async fn wait_for_operation<M: DeserializeOwned, R: DeserializeOwned>(
+        &self,
+        timeout: u64,
+        name: &str,
+        poller: &OperationPoller,
+    ) -> Result<Operation<M, R>, GoogleAPIError> {
+        poller
+            .poll_until(
+                || self.get_operation(timeout, name),
+                |operation| operation.done,
+            )
+            .await
+    }
+
+    /// Returns the base URL of the API, before the resource names.
+    pub(crate) fn api_base_url(&self) -> String {
+        match (&self.base_url, &self.region) {
+            (Some(base_url), _) => base_url.clone(),
+            (None, Some(region)) if self.is_vertex() => {
+                VertexOptions::base_url(region, self.vertex_options.api_version)
+            }
+            (None, _) => crate::v1::api::PUBLIC_API_URL_BASE.to_string(),
+        }
+    }
+}
+
+/// Deserializes an int64, which the API encodes as a JSON string.
+pub(crate) fn deserialize_int64<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int64 {
+        Number(i64),
+        String(String),
+    }
+
+    match Int64::deserialize(deserializer)? {
+        Int64::Number(value) => Ok(value),
+        Int64::String(value) => value.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::mock_server::{MockResponse, MockServer};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poller_backs_off_until_done_or_the_deadline() {
+        let poller = OperationPoller {
+            initial_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(3),
+            multiplier: 2.0,
+            deadline: None,
+        };
+        let started = Instant::now();
+        let mut polls = 0;
+
+        let done = poller
+            .poll_until(
+                || {
+                    polls += 1;
+                    let polled = polls;
+                    async move { Ok::<_, GoogleAPIError>(polled) }
+                },
+                |polled| *polled == 5,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(done, 5);
+        // 1 + 2 + 3 + 3 seconds between the 5 polls
+        assert_eq!(started.elapsed(), Duration::from_secs(9));
+
+        let error = poller
+            .with_deadline(Duration::from_secs(4))
+            .poll_until(|| async { Ok::<_, GoogleAPIError>(false) }, |done| *done)
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("deadline"));
+
+        for multiplier in [-1.0, 0.5, f64::NAN, f64::INFINITY, f64::MAX] {
+            let poller = OperationPoller {
+                multiplier,
+                max_interval: Duration::MAX,
+                ..Default::default()
+            };
+            let next = poller.next_interval(Duration::from_secs(2));
+            assert!(next >= Duration::from_secs(2), "{}: {:?}", multiplier, next);
+        }
+        let constant = OperationPoller {
+            multiplier: -1.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            constant.next_interval(Duration::from_secs(2)),
+            Duration::from_secs(2)
+        );
+
+        // Overflowing intervals are capped, instead of panicking in the loop
+        let mut polls = 0;
+        let huge = OperationPoller {
+            multiplier: f64::MAX,
+            ..Default::default()
+        };
+        huge.poll_until(
+            || {
+                polls += 1;
+                let polled = polls;
+                async move { Ok::<_, GoogleAPIError>(polled) }
+            },
+            |polled| *polled == 3,
+        )
+        .await
+        .unwrap();
+
+        // Neither does a deadline or an interval too long to represent
+        let mut polls = 0;
+        let done = huge
+            .with_deadline(Duration::MAX)
+            .poll_until(
+                || {
+                    polls += 1;
+                    let polled = polls;
+                    async move { Ok::<_, GoogleAPIError>(polled) }
+                },
+                |polled| *polled == 3,
+            )
+            .await
+            .unwrap();
+        assert_eq!(done, 3);
+        let error = OperationPoller {
+            initial_interval: Duration::MAX,
+            max_interval: Duration::MAX,
+            ..Default::default()
+        }
+        .with_deadline(Duration::from_secs(60))
+        .poll_until(|| async { Ok::<_, GoogleAPIError>(false) }, |done| *done)
+        .await
+        .unwrap_err();
+        assert!(error.message.contains("deadline"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_operation_returns_the_response_or_the_error() {
+        let server = MockServer::start().await;
+        server
+            .enqueue_route(
+                "GET",
+                "/operations/1",
+                MockResponse::json(200, serde_json::json!({"name": "operations/1"})),
+            )
+            .enqueue_route(
+                "GET",
+                "/operations/1",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "name": "operations/1",
+                        "done": true,
+                        "response": {"answer": 42}
+                    }),
+                ),
+            )
+            .enqueue_route(
+                "GET",
+                "/operations/2",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "name": "operations/2",
+                        "done": true,
+                        "error": {"code": 8, "message": "Quota exceeded"}
+                    }),
+                ),
+            );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+        let poller = OperationPoller {
+            initial_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let operation: Operation = client
+            .wait_for_operation(5, "operations/1", &poller)
+            .await
+            .unwrap();
+        assert_eq!(operation.into_result().unwrap()["answer"], 42);
+        assert_eq!(server.requests().len(), 2);
+
+        let operation: Operation = client.get_operation(5, "operations/2").await.unwrap();
+        let error = operation.into_result().unwrap_err();
+        assert_eq!(error.code, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(error.message, "API message: Quota exceeded.");
+    }
+}
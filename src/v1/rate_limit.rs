@@ -0,0 +1,510 @@
+//! Client-side rate limiting, to stay within the requests-per-minute (RPM) and tokens-per-minute (TPM) quotas of each
+//! model instead of being rejected with HTTP 429.
+//!
+//! A [`RateLimiter`] tracks the requests and tokens sent to each model over a sliding one minute window. The token
+//! cost of a request is estimated before it is sent, see [`crate::v1::tokens`], and reconciled with the
+//! [`UsageMetadata`] of the response, so the budget counts the prompt and output tokens actually used.
+//!
+//! Callers that would exceed a quota wait for their turn, first come first served. With a maximum wait, a request
+//! that would wait longer fails with [`RateLimitError::MaxWaitExceeded`] instead.
+//!
+//! A limiter is shared by cloning it, e.g. between the clients of a project:
+//! ```
+//! use std::time::Duration;
+//! use google_generative_ai_rs::v1::api::Client;
+//! use google_generative_ai_rs::v1::gemini::Model;
+//! use google_generative_ai_rs::v1::rate_limit::{RateLimiter, RateLimits};
+//!
+//! let limiter = RateLimiter::new(RateLimits::new(15, 1_000_000))
+//!     .with_model_limits(&Model::Gemini1_5Pro, RateLimits::new(2, 32_000))
+//!     .with_max_wait(Duration::from_secs(30));
+//! let client = Client::new("api-key".to_string()).with_rate_limiter(limiter.clone());
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::time::Instant;
+
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::{StreamedGeminiResponse, UsageMetadata};
+use crate::v1::gemini::Model;
+use crate::v1::tokens::{HeuristicEstimator, TokenEstimator};
+
+/// The window the quotas apply to.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// The quotas of a model. A quota of `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    /// The prompt and output tokens per minute.
+    pub tokens_per_minute: Option<u64>,
+}
+impl RateLimits {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u64) -> Self {
+        RateLimits {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+
+    pub fn requests_per_minute(requests_per_minute: u32) -> Self {
+        RateLimits {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: None,
+        }
+    }
+
+    pub fn tokens_per_minute(tokens_per_minute: u64) -> Self {
+        RateLimits {
+            requests_per_minute: None,
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+}
+
+/// Why a request was not allowed by a [`RateLimiter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitError {
+    /// The request needs more tokens than the tokens-per-minute quota, so it can never be sent.
+    ExceedsQuota {
+        model: String,
+        estimated_tokens: u64,
+        tokens_per_minute: u64,
+    },
+    /// The request would have waited longer than the maximum wait for its turn.
+    MaxWaitExceeded { model: String, max_wait: Duration },
+}
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimitError::ExceedsQuota {
+                model,
+                estimated_tokens,
+                tokens_per_minute,
+            } => write!(
+                f,
+                "Rate limit: the request to {} needs an estimated {} tokens, more than the quota of {} tokens per minute",
+                model, estimated_tokens, tokens_per_minute
+            ),
+            RateLimitError::MaxWaitExceeded { model, max_wait } => write!(
+                f,
+                "Rate limit: the request to {} would wait longer than the maximum of {:?}",
+                model, max_wait
+            ),
+        }
+    }
+}
+impl Error for RateLimitError {}
+
+/// The client methods report a request that was not allowed with the [`RateLimitError`] in
+/// [`GoogleAPIError::rate_limit`], and no status code, as the request was never sent.
+impl From<RateLimitError> for GoogleAPIError {
+    fn from(error: RateLimitError) -> Self {
+        GoogleAPIError {
+            message: error.to_string(),
+            code: None,
+            rate_limit: Some(error),
+        }
+    }
+}
+
+/// Enforces the RPM and TPM quotas of each model - see the [module documentation](self).
+#[derive(Clone)]
+pub struct RateLimiter {
+    default_limits: RateLimits,
+    model_limits: HashMap<String, RateLimits>,
+    max_wait: Option<Duration>,
+    estimator: Arc<dyn TokenEstimator>,
+    /// The queue and window of each model, by model ID.
+    models: Arc<Mutex<HashMap<String, Arc<ModelQueue>>>>,
+}
+impl RateLimiter {
+    /// Creates a limiter applying the limits to each model, with no maximum wait.
+    /// Token costs are estimated with the default [`HeuristicEstimator`].
+    pub fn new(default_limits: RateLimits) -> Self {
+        RateLimiter {
+            default_limits,
+            model_limits: HashMap::new(),
+            max_wait: None,
+            estimator: Arc::new(HeuristicEstimator::default()),
+            models: Arc::default(),
+        }
+    }
+
+    /// Overrides the limits of a model.
+    pub fn with_model_limits(mut self, model: &Model, limits: RateLimits) -> Self {
+        self.model_limits.insert(model.id(), limits);
+        self
+    }
+
+    /// Fails requests that would wait longer than `max_wait` with [`RateLimitError::MaxWaitExceeded`].
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Replaces the estimator of the token cost of requests, e.g. with a calibrated one.
+    pub fn with_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.estimator = Arc::new(estimator);
+        self
+    }
+
+    /// Returns the limits applied to a model.
+    pub fn limits(&self, model: &Model) -> RateLimits {
+        self.model_limits
+            .get(&model.id())
+            .copied()
+            .unwrap_or(self.default_limits)
+    }
+
+    /// Estimates the token cost of a request before it is sent.
+    pub fn estimate(&self, request: &Request) -> u64 {
+        self.estimator.estimate_request(request)
+    }
+
+    /// Waits until a request of the estimated number of tokens can be sent to the model without exceeding its
+    /// quotas, and counts it against them.
+    /// Reconcile the returned permit with the actual usage once the response is received.
+    pub async fn acquire(
+        &self,
+        model: &Model,
+        estimated_tokens: u64,
+    ) -> Result<RateLimitPermit, RateLimitError> {
+        let limits = self.limits(model);
+        if let Some(tokens_per_minute) = limits.tokens_per_minute {
+            if estimated_tokens > tokens_per_minute {
+                return Err(RateLimitError::ExceedsQuota {
+                    model: model.id(),
+                    estimated_tokens,
+                    tokens_per_minute,
+                });
+            }
+        }
+        let max_wait_exceeded = || RateLimitError::MaxWaitExceeded {
+            model: model.id(),
+            max_wait: self.max_wait.unwrap_or_default(),
+        };
+
+        let queue = self.queue(model);
+        // A maximum wait too long to represent is no deadline
+        let deadline = self
+            .max_wait
+            .and_then(|max_wait| Instant::now().checked_add(max_wait));
+        // The lock is fair, so holding it while waiting for the budget keeps the callers in order
+        let _turn = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, queue.turn.lock())
+                .await
+                .map_err(|_| max_wait_exceeded())?,
+            None => queue.turn.lock().await,
+        };
+
+        loop {
+            let now = Instant::now();
+            let wait = {
+                let mut window = queue.window.lock().unwrap();
+                let wait = window.wait(&limits, estimated_tokens, now);
+                if wait.is_zero() {
+                    let id = window.push(now, estimated_tokens);
+                    return Ok(RateLimitPermit {
+                        queue: queue.clone(),
+                        id,
+                        estimated_tokens,
+                    });
+                }
+                wait
+            };
+            if deadline
+                .is_some_and(|deadline| now.checked_add(wait).is_none_or(|ready| ready > deadline))
+            {
+                return Err(max_wait_exceeded());
+            }
+            // Re-checked after the wait, as reconciled usage may have changed the budget
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn queue(&self, model: &Model) -> Arc<ModelQueue> {
+        self.models
+            .lock()
+            .unwrap()
+            .entry(model.id())
+            .or_default()
+            .clone()
+    }
+}
+
+/// A request counted against the quotas of a model.
+pub struct RateLimitPermit {
+    queue: Arc<ModelQueue>,
+    id: u64,
+    estimated_tokens: u64,
+}
+impl RateLimitPermit {
+    pub fn estimated_tokens(&self) -> u64 {
+        self.estimated_tokens
+    }
+
+    /// Replaces the estimated token cost with the actual one, e.g. `0` for a request that failed, so that failed
+    /// attempts and their retries do not use up the tokens-per-minute quota.
+    pub fn reconcile(&self, actual_tokens: u64) {
+        let mut window = self.queue.window.lock().unwrap();
+        if let Some(entry) = window.entries.iter_mut().find(|entry| entry.id == self.id) {
+            entry.tokens = actual_tokens;
+        }
+    }
+
+    /// Replaces the estimated token cost with the prompt and output tokens of the response.
+    pub fn reconcile_usage(&self, usage: &UsageMetadata) {
+        let total_tokens = if usage.total_token_count > 0 {
+            usage.total_token_count
+        } else {
+            usage.prompt_token_count + usage.candidates_token_count
+        };
+        self.reconcile(total_tokens);
+    }
+
+    /// Reconciles the permit with the usage of each chunk of a streamed response as it arrives. The last chunk
+    /// carries the usage of the whole response.
+    pub(crate) fn reconcile_stream(
+        self,
+        response: StreamedGeminiResponse,
+    ) -> StreamedGeminiResponse {
+        let json_stream = match response.response_stream {
+            Some(json_stream) => json_stream,
+            None => return response,
+        };
+        let chunks = json_stream.map(move |chunk| {
+            if let Some(usage) = chunk
+                .as_ref()
+                .ok()
+                .and_then(|value| value.get("usageMetadata"))
+                .and_then(|usage| serde_json::from_value::<UsageMetadata>(usage.clone()).ok())
+            {
+                self.reconcile_usage(&usage);
+            }
+            chunk
+        });
+        StreamedGeminiResponse {
+            response_stream: Some(Box::pin(chunks)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ModelQueue {
+    /// Held by the caller whose turn it is.
+    turn: tokio::sync::Mutex<()>,
+    window: Mutex<Window>,
+}
+
+/// The requests sent to a model within the last minute.
+#[derive(Default)]
+struct Window {
+    next_id: u64,
+    entries: VecDeque<Entry>,
+}
+struct Entry {
+    id: u64,
+    sent: Instant,
+    tokens: u64,
+}
+impl Window {
+    fn push(&mut self, sent: Instant, tokens: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push_back(Entry { id, sent, tokens });
+        id
+    }
+
+    /// Returns how long until a request of the tokens fits within the limits, zero if it fits now.
+    fn wait(&mut self, limits: &RateLimits, tokens: u64, now: Instant) -> Duration {
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.sent + WINDOW <= now)
+        {
+            self.entries.pop_front();
+        }
+
+        let fits = |requests: usize, used: u64| {
+            limits
+                .requests_per_minute
+                .is_none_or(|limit| requests < limit.max(1) as usize)
+                && limits
+                    .tokens_per_minute
+                    .is_none_or(|limit| used + tokens <= limit)
+        };
+        let mut requests = self.entries.len();
+        let mut used: u64 = self.entries.iter().map(|entry| entry.tokens).sum();
+        if fits(requests, used) {
+            return Duration::ZERO;
+        }
+        // Waits for the oldest requests to leave the window until it fits
+        for entry in &self.entries {
+            requests -= 1;
+            used -= entry.tokens;
+            if fits(requests, used) {
+                return entry.sent + WINDOW - now;
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::api::Client;
+    use crate::v1::gemini::request::text_request;
+    use crate::v1::gemini::ResponseType;
+    use crate::v1::mock_server::{MockResponse, MockServer};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_wait_for_the_rpm_quota_in_order() {
+        // Duration::MAX must not overflow the deadline
+        let limiter =
+            RateLimiter::new(RateLimits::requests_per_minute(2)).with_max_wait(Duration::MAX);
+        let started = Instant::now();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|caller| {
+                let limiter = limiter.clone();
+                let order = order.clone();
+                tokio::spawn(async move {
+                    limiter.acquire(&Model::default(), 1).await.unwrap();
+                    order.lock().unwrap().push((caller, started.elapsed()));
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let order = order.lock().unwrap().clone();
+        let callers: Vec<_> = order.iter().map(|(caller, _)| *caller).collect();
+        assert_eq!(callers, [0, 1, 2, 3]);
+        assert!(order[1].1 < WINDOW);
+        assert!(order[2].1 >= WINDOW);
+        assert!(order[3].1 >= WINDOW);
+        // Other models have their own quota
+        let other = limiter.acquire(&Model::Gemini1_5Pro, 1);
+        assert!(tokio::time::timeout(Duration::from_millis(1), other)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tpm_quota_is_reconciled_with_actual_usage() {
+        let limiter = RateLimiter::new(RateLimits::tokens_per_minute(100))
+            .with_max_wait(Duration::from_secs(10));
+        let model = Model::default();
+
+        let permit = limiter.acquire(&model, 60).await.unwrap();
+        // Without reconciliation, 60 + 70 would exceed the quota
+        permit.reconcile_usage(&UsageMetadata {
+            prompt_token_count: 15,
+            candidates_token_count: 5,
+            total_token_count: 20,
+        });
+        limiter.acquire(&model, 70).await.unwrap();
+
+        assert_eq!(
+            limiter.acquire(&model, 50).await.err(),
+            Some(RateLimitError::MaxWaitExceeded {
+                model: model.id(),
+                max_wait: Duration::from_secs(10),
+            })
+        );
+        let exceeds_quota = limiter.acquire(&model, 101).await.err().unwrap();
+        assert_eq!(
+            exceeds_quota,
+            RateLimitError::ExceedsQuota {
+                model: model.id(),
+                estimated_tokens: 101,
+                tokens_per_minute: 100,
+            }
+        );
+        let error = GoogleAPIError::from(exceeds_quota.clone());
+        assert_eq!(error.code, None);
+        assert_eq!(error.rate_limit, Some(exceeds_quota));
+        assert!(!crate::v1::bulk::RetryPolicy::default().is_retryable(&error));
+        tokio::time::advance(WINDOW).await;
+        limiter.acquire(&model, 100).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_fast_with_a_typed_error_past_the_max_wait() {
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::GenerateContent,
+            MockResponse::json(
+                200,
+                serde_json::json!({
+                    "candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}],
+                    "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1, "totalTokenCount": 4}
+                }),
+            ),
+        );
+        let limiter = RateLimiter::new(RateLimits::requests_per_minute(1))
+            .with_max_wait(Duration::from_millis(10));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_rate_limiter(limiter.clone());
+        let request = text_request("Hello?");
+
+        client.generate_content(5, &request).await.unwrap();
+        let error = client.generate_content(5, &request).await.unwrap_err();
+
+        assert_eq!(error.code, None);
+        assert!(matches!(
+            error.rate_limit,
+            Some(RateLimitError::MaxWaitExceeded { .. })
+        ));
+        assert!(error.message.contains("maximum"));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(
+            limiter.queue(&client.model).window.lock().unwrap().entries[0].tokens,
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_releases_the_tokens_of_failed_requests() {
+        let server = MockServer::start().await;
+        server
+            .enqueue(
+                ResponseType::GenerateContent,
+                MockResponse::error(503, "Unavailable"),
+            )
+            .enqueue(
+                ResponseType::StreamGenerateContent,
+                MockResponse::error(429, "Resource exhausted"),
+            );
+        let limiter = RateLimiter::new(RateLimits::tokens_per_minute(1_000));
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_rate_limiter(limiter.clone());
+        let request = text_request("Hello?");
+
+        client.generate_content(5, &request).await.unwrap_err();
+        assert!(client.stream_generate_content(5, &request).await.is_err());
+
+        let queue = limiter.queue(&client.model);
+        let tokens: Vec<u64> = queue
+            .window
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| entry.tokens)
+            .collect();
+        assert_eq!(tokens, [0, 0]);
+    }
+}
@@ -0,0 +1,324 @@
+//! A registry of models and their capabilities, e.g. supported generation methods, token limits and modalities.
+//!
+//! The registry is seeded with the well-known [`Model`]s and can be refreshed from the [`ModelInformation`] returned by
+//! the API, then cached locally as JSON so the list does not need to be fetched on every start.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::{Modality, Model, ModelInformation, ModelInformationList, ResponseType};
+
+/// The capabilities of a specific model.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelCapabilities {
+    /// The resource name, e.g. `models/gemini-1.5-flash`.
+    pub name: String,
+    pub display_name: Option<String>,
+    pub input_token_limit: Option<u32>,
+    pub output_token_limit: Option<u32>,
+    /// The API methods supported by the model, e.g. `generateContent`, `countTokens` or `embedContent`.
+    pub supported_generation_methods: Vec<String>,
+    pub input_modalities: Vec<Modality>,
+    pub output_modalities: Vec<Modality>,
+}
+impl ModelCapabilities {
+    /// Returns true if the model supports the API method used for the response type.
+    /// Streaming is supported by every model that supports `generateContent`.
+    pub fn supports(&self, response_type: &ResponseType) -> bool {
+        response_type.is_supported_by(&self.supported_generation_methods)
+    }
+
+    pub fn supports_generation_method(&self, method: &str) -> bool {
+        self.supported_generation_methods
+            .iter()
+            .any(|supported| supported == method)
+    }
+
+    pub fn supports_input_modality(&self, modality: &Modality) -> bool {
+        self.input_modalities.contains(modality)
+    }
+
+    pub fn supports_output_modality(&self, modality: &Modality) -> bool {
+        self.output_modalities.contains(modality)
+    }
+
+    fn known(
+        model: Model,
+        limits: (u32, u32),
+        methods: &[&str],
+        input_modalities: Vec<Modality>,
+        output_modalities: Vec<Modality>,
+    ) -> Self {
+        ModelCapabilities {
+            name: model.resource_name(),
+            display_name: None,
+            input_token_limit: Some(limits.0),
+            output_token_limit: Some(limits.1),
+            supported_generation_methods: methods.iter().map(|m| m.to_string()).collect(),
+            input_modalities,
+            output_modalities,
+        }
+    }
+}
+/// The API does not report modalities, so they are inferred from the supported methods.
+impl From<&ModelInformation> for ModelCapabilities {
+    fn from(info: &ModelInformation) -> Self {
+        let generates = info
+            .supported_generation_methods
+            .iter()
+            .any(|m| m == "generateContent");
+        let embeds = info
+            .supported_generation_methods
+            .iter()
+            .any(|m| m == "embedContent");
+
+        ModelCapabilities {
+            name: Model::from(info.name.as_str()).resource_name(),
+            display_name: Some(info.display_name.clone()),
+            input_token_limit: u32::try_from(info.input_token_limit).ok(),
+            output_token_limit: u32::try_from(info.output_token_limit).ok(),
+            supported_generation_methods: info.supported_generation_methods.clone(),
+            input_modalities: if generates || embeds {
+                vec![Modality::Text]
+            } else {
+                vec![]
+            },
+            output_modalities: if generates {
+                vec![Modality::Text]
+            } else {
+                vec![]
+            },
+        }
+    }
+}
+
+/// A registry of models, keyed by resource name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModelRegistry {
+    models: BTreeMap<String, ModelCapabilities>,
+}
+impl ModelRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        ModelRegistry::default()
+    }
+
+    /// Creates a registry holding the well-known models.
+    pub fn with_known_models() -> Self {
+        use Modality::*;
+
+        let multimodal = || vec![Text, Image, Video, Audio, Document];
+        let generate = ["generateContent", "countTokens"];
+
+        let mut registry = ModelRegistry::new();
+        for capabilities in [
+            ModelCapabilities::known(
+                Model::Gemini1_0Pro,
+                (30720, 2048),
+                &generate,
+                vec![Text],
+                vec![Text],
+            ),
+            ModelCapabilities::known(
+                Model::Gemini1_5Pro,
+                (2097152, 8192),
+                &generate,
+                multimodal(),
+                vec![Text],
+            ),
+            ModelCapabilities::known(
+                Model::Gemini1_5Flash,
+                (1048576, 8192),
+                &generate,
+                multimodal(),
+                vec![Text],
+            ),
+            ModelCapabilities::known(
+                Model::Gemini1_5Flash8B,
+                (1048576, 8192),
+                &generate,
+                multimodal(),
+                vec![Text],
+            ),
+            ModelCapabilities::known(
+                Model::Gemini2_0Flash,
+                (1048576, 8192),
+                &generate,
+                multimodal(),
+                vec![Text],
+            ),
+            ModelCapabilities::known(
+                Model::TextEmbedding004,
+                (2048, 1),
+                &["embedContent"],
+                vec![Text],
+                vec![],
+            ),
+        ] {
+            registry.insert(capabilities);
+        }
+        registry
+    }
+
+    /// Creates a registry holding the well-known models, updated with the models returned by the API.
+    pub fn from_model_information_list(list: &ModelInformationList) -> Self {
+        let mut registry = ModelRegistry::with_known_models();
+        for info in &list.models {
+            registry.update_from_model_information(info);
+        }
+        registry
+    }
+
+    /// Adds or replaces a model.
+    pub fn insert(&mut self, capabilities: ModelCapabilities) {
+        self.models.insert(capabilities.name.clone(), capabilities);
+    }
+
+    /// Adds or updates a model from the information returned by the API.
+    /// The modalities of an already registered model are kept, as the API does not report them.
+    pub fn update_from_model_information(&mut self, info: &ModelInformation) {
+        let mut capabilities = ModelCapabilities::from(info);
+        if let Some(existing) = self.models.get(&capabilities.name) {
+            capabilities.input_modalities = existing.input_modalities.clone();
+            capabilities.output_modalities = existing.output_modalities.clone();
+        }
+        self.insert(capabilities);
+    }
+
+    pub fn get(&self, model: &Model) -> Option<&ModelCapabilities> {
+        self.models.get(&model.resource_name())
+    }
+
+    /// Returns true if the model is registered and supports the API method used for the response type.
+    pub fn supports(&self, model: &Model, response_type: &ResponseType) -> bool {
+        self.get(model)
+            .map(|capabilities| capabilities.supports(response_type))
+            .unwrap_or(false)
+    }
+
+    /// Iterates over the registered models, ordered by resource name.
+    pub fn models(&self) -> impl Iterator<Item = &ModelCapabilities> {
+        self.models.values()
+    }
+
+    /// Loads a registry previously cached with [`ModelRegistry::save_to_file`].
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, GoogleAPIError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to read model registry from {}: {}",
+                path.display(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })?;
+        serde_json::from_str(&json).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to deserialize model registry from {}: {}",
+                path.display(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })
+    }
+
+    /// Caches the registry locally as JSON.
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> Result<(), GoogleAPIError> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).map_err(|e| GoogleAPIError {
+            message: format!("Failed to serialize model registry: {}", e),
+            code: None,
+            rate_limit: None,
+        })?;
+        std::fs::write(path, json).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to write model registry to {}: {}",
+                path.display(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_models_are_registered() {
+        let registry = ModelRegistry::with_known_models();
+
+        for model in Model::KNOWN {
+            assert!(
+                registry.get(&model).is_some(),
+                "{} is not registered",
+                model
+            );
+        }
+        assert!(registry.supports(&Model::Gemini1_5Flash, &ResponseType::StreamGenerateContent));
+        assert!(!registry.supports(&Model::TextEmbedding004, &ResponseType::GenerateContent));
+        assert!(registry.supports(&Model::TextEmbedding004, &ResponseType::EmbedContent));
+        assert!(!registry.supports(
+            &Model::Custom("unknown".to_string()),
+            &ResponseType::GenerateContent
+        ));
+    }
+
+    #[test]
+    fn test_registry_is_seeded_from_model_information() {
+        let list: ModelInformationList = serde_json::from_value(serde_json::json!({
+            "models": [
+                {
+                    "name": "models/gemini-1.5-flash",
+                    "version": "001",
+                    "displayName": "Gemini 1.5 Flash",
+                    "description": "Fast and versatile",
+                    "inputTokenLimit": 1000000,
+                    "outputTokenLimit": 8192,
+                    "supportedGenerationMethods": ["generateContent", "countTokens", "createCachedContent"]
+                },
+                {
+                    "name": "models/gemini-2.5-pro",
+                    "version": "2.5",
+                    "displayName": "Gemini 2.5 Pro",
+                    "description": "Thinking model",
+                    "inputTokenLimit": 1048576,
+                    "outputTokenLimit": 65536,
+                    "supportedGenerationMethods": ["generateContent", "countTokens"]
+                }
+            ]
+        }))
+        .unwrap();
+
+        let registry = ModelRegistry::from_model_information_list(&list);
+
+        let flash = registry.get(&Model::Gemini1_5Flash).unwrap();
+        assert_eq!(flash.input_token_limit, Some(1000000));
+        assert!(flash.supports_generation_method("createCachedContent"));
+        // The modalities of a known model are kept
+        assert!(flash.supports_input_modality(&Modality::Video));
+
+        let pro = registry.get(&Model::from("gemini-2.5-pro")).unwrap();
+        assert_eq!(pro.output_token_limit, Some(65536));
+        assert_eq!(pro.input_modalities, vec![Modality::Text]);
+    }
+
+    #[test]
+    fn test_registry_save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("model-registry-{}.json", std::process::id()));
+        let registry = ModelRegistry::with_known_models();
+
+        registry.save_to_file(&path).unwrap();
+        let loaded = ModelRegistry::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, registry);
+        assert!(ModelRegistry::load_from_file(&path).is_err());
+    }
+}
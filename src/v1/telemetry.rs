@@ -0,0 +1,492 @@
+//! `tracing` instrumentation of the API calls, following the OpenTelemetry GenAI semantic conventions.
+//!
+//! Every API call runs in an `INFO` span named `gen_ai`, with an `otel.name` of `"{operation} {model}"`, e.g.
+//! `"generate_content gemini-1.5-flash"`, and the attributes:
+//! * `gen_ai.operation.name`, `gen_ai.provider.name`, `gen_ai.request.model` and `server.address`
+//! * `gen_ai.request.*` - the generation parameters, e.g. `gen_ai.request.temperature`
+//! * `gen_ai.response.finish_reasons`, `gen_ai.response.id` and `gen_ai.response.model`
+//! * `gen_ai.usage.input_tokens` and `gen_ai.usage.output_tokens`
+//! * `gen_ai.client.time_to_first_chunk` - for streamed responses, in seconds
+//! * `error.type` and `otel.status_code` - when the call fails
+//!
+//! Prompts and completions are not recorded unless enabled with [`crate::v1::api::Client::with_content_capture`], in
+//! which case they are sent as `gen_ai.*.message` and `gen_ai.choice` events, optionally redacted.
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+use crate::v1::api::Client;
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::{GeminiResponse, StreamedGeminiResponse, UsageMetadata};
+use crate::v1::gemini::{ResponseType, Role};
+
+/// Whether prompt and completion content is recorded as span events.
+#[derive(Clone, Default)]
+pub enum ContentCapture {
+    /// The content is never recorded.
+    #[default]
+    Off,
+    /// The content is recorded as is.
+    Full,
+    /// The content is recorded after it is passed through the redaction function.
+    Redacted(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+impl ContentCapture {
+    /// Records the content, redacted by `redact`, e.g. to mask e-mail addresses.
+    pub fn redacted(redact: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        ContentCapture::Redacted(Arc::new(redact))
+    }
+
+    fn capture(&self, text: &str) -> Option<String> {
+        match self {
+            ContentCapture::Off => None,
+            ContentCapture::Full => Some(text.to_string()),
+            ContentCapture::Redacted(redact) => Some(redact(text)),
+        }
+    }
+}
+impl fmt::Debug for ContentCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContentCapture::Off => f.write_str("Off"),
+            ContentCapture::Full => f.write_str("Full"),
+            ContentCapture::Redacted(_) => f.write_str("Redacted(..)"),
+        }
+    }
+}
+
+/// Creates the span of an API call on the client's model.
+/// Parameters:
+/// * operation - the `gen_ai.operation.name`, e.g. `generate_content`
+pub(crate) fn operation_span(client: &Client, operation: &'static str) -> Span {
+    let model = client.model.id();
+    let span = tracing::info_span!(
+        "gen_ai",
+        otel.name = %format_args!("{} {}", operation, model),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        gen_ai.operation.name = operation,
+        gen_ai.provider.name = if client.is_vertex() { "gcp.vertex_ai" } else { "gcp.gemini" },
+        gen_ai.request.model = %model,
+        server.address = Empty,
+        gen_ai.request.temperature = Empty,
+        gen_ai.request.top_p = Empty,
+        gen_ai.request.top_k = Empty,
+        gen_ai.request.max_tokens = Empty,
+        gen_ai.request.stop_sequences = Empty,
+        gen_ai.request.choice.count = Empty,
+        gen_ai.request.presence_penalty = Empty,
+        gen_ai.request.frequency_penalty = Empty,
+        gen_ai.request.seed = Empty,
+        gen_ai.response.finish_reasons = Empty,
+        gen_ai.response.id = Empty,
+        gen_ai.response.model = Empty,
+        gen_ai.usage.input_tokens = Empty,
+        gen_ai.usage.output_tokens = Empty,
+        gen_ai.client.time_to_first_chunk = Empty,
+        error.type = Empty,
+    );
+    if !span.is_disabled() {
+        let url = client.operation_url(&ResponseType::GenerateContent);
+        if let Some(host) = reqwest::Url::parse(&url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            span.record("server.address", host);
+        }
+    }
+    span
+}
+
+/// Runs the call in the span, recording the error if it fails.
+pub(crate) async fn traced<T>(
+    span: &Span,
+    call: impl Future<Output = Result<T, GoogleAPIError>>,
+) -> Result<T, GoogleAPIError> {
+    let result = call.instrument(span.clone()).await;
+    if let Err(error) = &result {
+        record_error(span, error);
+    }
+    result
+}
+
+/// Records the generation parameters and, if captured, the prompt.
+pub(crate) fn record_request(span: &Span, request: &Request, capture: &ContentCapture) {
+    if span.is_disabled() {
+        return;
+    }
+    if let Some(config) = &request.generation_config {
+        if let Some(temperature) = config.temperature {
+            span.record("gen_ai.request.temperature", temperature as f64);
+        }
+        if let Some(top_p) = config.top_p {
+            span.record("gen_ai.request.top_p", top_p as f64);
+        }
+        if let Some(top_k) = config.top_k {
+            span.record("gen_ai.request.top_k", top_k as i64);
+        }
+        if let Some(max_output_tokens) = config.max_output_tokens {
+            span.record("gen_ai.request.max_tokens", max_output_tokens as i64);
+        }
+        if let Some(stop_sequences) = &config.stop_sequences {
+            span.record(
+                "gen_ai.request.stop_sequences",
+                tracing::field::debug(stop_sequences),
+            );
+        }
+        if let Some(candidate_count) = config.candidate_count {
+            span.record("gen_ai.request.choice.count", candidate_count as i64);
+        }
+        if let Some(presence_penalty) = config.presence_penalty {
+            span.record("gen_ai.request.presence_penalty", presence_penalty as f64);
+        }
+        if let Some(frequency_penalty) = config.frequency_penalty {
+            span.record("gen_ai.request.frequency_penalty", frequency_penalty as f64);
+        }
+        if let Some(seed) = config.seed {
+            span.record("gen_ai.request.seed", seed as i64);
+        }
+    }
+
+    #[cfg(feature = "beta")]
+    if let Some(system_instruction) = &request.system_instruction {
+        let text: String = system_instruction
+            .parts
+            .iter()
+            .filter_map(|part| part.text.as_deref())
+            .collect();
+        if let Some(content) = capture.capture(&text) {
+            tracing::info!(parent: span, event.name = "gen_ai.system.message", content = %content);
+        }
+    }
+    for message in &request.contents {
+        if let Some(content) = capture.capture(&message.get_text()) {
+            match message.role {
+                Role::User => {
+                    tracing::info!(parent: span, event.name = "gen_ai.user.message", content = %content)
+                }
+                Role::Model => {
+                    tracing::info!(parent: span, event.name = "gen_ai.assistant.message", content = %content)
+                }
+            }
+        }
+    }
+}
+
+/// Records the finish reasons, token usage and, if captured, the completions of a response.
+pub(crate) fn record_response(span: &Span, response: &GeminiResponse, capture: &ContentCapture) {
+    if span.is_disabled() {
+        return;
+    }
+    record_metadata(span, response);
+    for (index, candidate) in response.candidates.iter().enumerate() {
+        if let Some(content) = capture.capture(&candidate.content.get_text()) {
+            tracing::info!(
+                parent: span,
+                event.name = "gen_ai.choice",
+                index = candidate.index.map(|i| i as usize).unwrap_or(index),
+                finish_reason = candidate.finish_reason.as_deref().unwrap_or_default(),
+                content = %content
+            );
+        }
+    }
+}
+
+/// Records the finish reasons, ids and usage present in a response or a chunk of a streamed response.
+fn record_metadata(span: &Span, response: &GeminiResponse) {
+    let finish_reasons: Vec<&str> = response
+        .candidates
+        .iter()
+        .filter_map(|candidate| candidate.finish_reason.as_deref())
+        .collect();
+    if !finish_reasons.is_empty() {
+        span.record(
+            "gen_ai.response.finish_reasons",
+            tracing::field::debug(&finish_reasons),
+        );
+    }
+    if let Some(response_id) = &response.response_id {
+        span.record("gen_ai.response.id", response_id.as_str());
+    }
+    if let Some(model_version) = &response.model_version {
+        span.record("gen_ai.response.model", model_version.as_str());
+    }
+    if let Some(usage) = &response.usage_metadata {
+        record_usage(span, usage);
+    }
+}
+
+pub(crate) fn record_usage(span: &Span, usage: &UsageMetadata) {
+    span.record("gen_ai.usage.input_tokens", usage.prompt_token_count);
+    span.record("gen_ai.usage.output_tokens", usage.candidates_token_count);
+}
+
+pub(crate) fn record_error(span: &Span, error: &GoogleAPIError) {
+    let error_type = match error.code {
+        Some(code) => code.as_u16().to_string(),
+        None => "_OTHER".to_string(),
+    };
+    span.record("error.type", error_type);
+    span.record("otel.status_code", "ERROR");
+}
+
+/// Records the time to the first chunk, and the metadata of each chunk as it arrives. The span is kept open until the
+/// stream is dropped, and the completion, if captured, is recorded when the stream ends.
+pub(crate) fn instrument_stream(
+    span: Span,
+    response: StreamedGeminiResponse,
+    capture: ContentCapture,
+    started: Instant,
+) -> StreamedGeminiResponse {
+    let json_stream = match response.response_stream {
+        Some(json_stream) if !span.is_disabled() => json_stream,
+        _ => return response,
+    };
+
+    // The text of each candidate so far
+    let completions: Arc<Mutex<Vec<String>>> = Arc::default();
+    let chunk_span = span.clone();
+    let chunk_completions = completions.clone();
+    let chunk_capture = capture.clone();
+    let mut first = true;
+    let chunks = json_stream.map(move |chunk| {
+        if first {
+            first = false;
+            chunk_span.record(
+                "gen_ai.client.time_to_first_chunk",
+                started.elapsed().as_secs_f64(),
+            );
+        }
+        if let Ok(value) = &chunk {
+            if let Ok(response) = serde_json::from_value::<GeminiResponse>(value.clone()) {
+                record_metadata(&chunk_span, &response);
+                if !matches!(chunk_capture, ContentCapture::Off) {
+                    let mut completions = chunk_completions.lock().unwrap();
+                    for (index, candidate) in response.candidates.iter().enumerate() {
+                        let index = candidate.index.map(|i| i as usize).unwrap_or(index);
+                        if completions.len() <= index {
+                            completions.resize(index + 1, String::new());
+                        }
+                        completions[index].push_str(&candidate.content.get_text());
+                    }
+                }
+            }
+        }
+        Some(chunk)
+    });
+    let end = stream::once(async move {
+        let completions = completions.lock().unwrap();
+        for (index, completion) in completions.iter().enumerate() {
+            if let Some(content) = capture.capture(completion) {
+                tracing::info!(parent: &span, event.name = "gen_ai.choice", index, content = %content);
+            }
+        }
+        None
+    });
+
+    StreamedGeminiResponse {
+        response_stream: Some(Box::pin(chunks.chain(end).filter_map(future::ready))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use super::*;
+    use crate::v1::mock_server::{MockResponse, MockServer};
+
+    /// The recorded fields of the spans and events, as strings.
+    #[derive(Clone, Default)]
+    struct Captured {
+        spans: Arc<Mutex<HashMap<Id, HashMap<String, String>>>>,
+        events: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    }
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+    impl<S: Subscriber> Layer<S> for Captured {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            self.spans.lock().unwrap().insert(id.clone(), fields);
+        }
+        fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            if let Some(fields) = self.spans.lock().unwrap().get_mut(id) {
+                values.record(&mut Fields(fields));
+            }
+        }
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+    }
+    impl Captured {
+        fn install(&self) -> tracing::subscriber::DefaultGuard {
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(self.clone()))
+        }
+        fn span(&self, operation: &str) -> HashMap<String, String> {
+            self.spans
+                .lock()
+                .unwrap()
+                .values()
+                .find(|span| {
+                    span.get("gen_ai.operation.name").map(String::as_str) == Some(operation)
+                })
+                .cloned()
+                .unwrap()
+        }
+        fn events(&self, name: &str) -> Vec<HashMap<String, String>> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event.get("event.name").map(String::as_str) == Some(name))
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn request() -> Request {
+        serde_json::from_value(serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "My e-mail is jo@example.com"}]}],
+            "generationConfig": {"temperature": 0.5, "maxOutputTokens": 64, "stopSequences": ["END"]}
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_span_carries_the_semantic_convention_attributes() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::GenerateContent,
+            MockResponse::json(
+                200,
+                serde_json::json!({
+                    "candidates": [{
+                        "content": {"role": "model", "parts": [{"text": "Noted, jo@example.com"}]},
+                        "finishReason": "STOP"
+                    }],
+                    "usageMetadata": {"promptTokenCount": 7, "candidatesTokenCount": 4, "totalTokenCount": 11},
+                    "responseId": "response-1",
+                    "modelVersion": "gemini-1.5-flash-002"
+                }),
+            ),
+        );
+        let client = Client::new("test-api-key".to_string())
+            .with_base_url(server.base_url())
+            .with_content_capture(ContentCapture::redacted(|text| {
+                text.replace("jo@example.com", "<email>")
+            }));
+
+        client.generate_content(5, &request()).await.unwrap();
+
+        let span = captured.span("generate_content");
+        assert_eq!(span["gen_ai.provider.name"], "gcp.gemini");
+        assert_eq!(span["server.address"], "127.0.0.1");
+        assert_eq!(span["gen_ai.request.temperature"], "0.5");
+        assert_eq!(span["gen_ai.request.max_tokens"], "64");
+        assert_eq!(span["gen_ai.request.stop_sequences"], r#"["END"]"#);
+        assert_eq!(span["gen_ai.response.finish_reasons"], r#"["STOP"]"#);
+        assert_eq!(span["gen_ai.response.id"], "response-1");
+        assert_eq!(span["gen_ai.response.model"], "gemini-1.5-flash-002");
+        assert_eq!(span["gen_ai.usage.input_tokens"], "7");
+        assert_eq!(span["gen_ai.usage.output_tokens"], "4");
+        assert!(!span.contains_key("error.type"));
+        assert_eq!(
+            captured.events("gen_ai.user.message")[0]["content"],
+            "My e-mail is <email>"
+        );
+        assert_eq!(
+            captured.events("gen_ai.choice")[0]["content"],
+            "Noted, <email>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_is_not_captured_by_default_and_errors_are_recorded() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::GenerateContent,
+            MockResponse::error(400, "Invalid argument"),
+        );
+        let client = Client::new("test-api-key".to_string()).with_base_url(server.base_url());
+
+        assert!(client.generate_content(5, &request()).await.is_err());
+
+        let span = captured.span("generate_content");
+        assert_eq!(span["error.type"], "400");
+        assert_eq!(span["otel.status_code"], "ERROR");
+        assert!(captured.events("gen_ai.user.message").is_empty());
+        assert!(captured.events("gen_ai.choice").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_records_time_to_first_chunk_and_completion() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let server = MockServer::start().await;
+        server.enqueue(
+            ResponseType::StreamGenerateContent,
+            MockResponse::stream(vec![
+                serde_json::json!({
+                    "candidates": [{"content": {"role": "model", "parts": [{"text": "Hello"}]}}]
+                }),
+                serde_json::json!({
+                    "candidates": [{
+                        "content": {"role": "model", "parts": [{"text": " world"}]},
+                        "finishReason": "STOP"
+                    }],
+                    "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 2, "totalTokenCount": 5}
+                }),
+            ])
+            .with_delay(Duration::from_millis(20)),
+        );
+        let client = Client::new_from_response_type(
+            ResponseType::StreamGenerateContent,
+            "test-api-key".to_string(),
+        )
+        .with_base_url(server.base_url())
+        .with_content_capture(ContentCapture::Full);
+
+        let response = client.stream_generate_content(5, &request()).await.unwrap();
+        let chunks: Vec<_> = response.response_stream.unwrap().collect().await;
+
+        assert_eq!(chunks.len(), 2);
+        let span = captured.span("generate_content");
+        let time_to_first_chunk: f64 = span["gen_ai.client.time_to_first_chunk"].parse().unwrap();
+        assert!(time_to_first_chunk >= 0.02);
+        assert_eq!(span["gen_ai.response.finish_reasons"], r#"["STOP"]"#);
+        assert_eq!(span["gen_ai.usage.output_tokens"], "2");
+        assert_eq!(
+            captured.events("gen_ai.choice")[0]["content"],
+            "Hello world"
+        );
+    }
+}
@@ -0,0 +1,560 @@
+//! Offline token estimation, to budget prompts without a `countTokens` round-trip per prompt.
+//!
+//! Two estimators are provided:
+//! * [`HeuristicEstimator`] - a characters-per-token heuristic, which can be calibrated against counts from the API
+//! * [`SentencePieceEstimator`] - tokenizes text with a SentencePiece model loaded from a local `.model` file
+//!
+//! [`EstimateErrorBounds`] measures how far the estimates of an estimator are from the [`TokenCount`]s returned by the
+//! API, so callers know how much headroom to leave.
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::response::TokenCount;
+use crate::v1::gemini::{Content, Part};
+
+/// The number of tokens the API counts for an image, and per second of video.
+const TOKENS_PER_MEDIA_PART: u64 = 258;
+
+/// Estimates the number of tokens the API would count for text, contents and requests.
+///
+/// Only [`TokenEstimator::estimate_text`] needs to be implemented.
+pub trait TokenEstimator: Send + Sync {
+    /// Estimates the number of tokens in the text.
+    fn estimate_text(&self, text: &str) -> u64;
+
+    /// Estimates the number of tokens in a part. Media parts are counted as a fixed number of tokens.
+    fn estimate_part(&self, part: &Part) -> u64 {
+        let mut tokens = part
+            .text
+            .as_deref()
+            .map(|text| self.estimate_text(text))
+            .unwrap_or(0);
+        if part.inline_data.is_some() || part.file_data.is_some() {
+            tokens += TOKENS_PER_MEDIA_PART;
+        }
+        if let Some(function_call) = &part.function_call {
+            tokens += self.estimate_text(&function_call.name)
+                + self.estimate_text(&function_call.args.to_string());
+        }
+        if let Some(function_response) = &part.function_response {
+            tokens += self.estimate_text(&function_response.name)
+                + self.estimate_text(&function_response.response.to_string());
+        }
+        tokens
+    }
+
+    fn estimate_content(&self, content: &Content) -> u64 {
+        content
+            .parts
+            .iter()
+            .map(|part| self.estimate_part(part))
+            .sum()
+    }
+
+    /// Estimates the prompt tokens of a request, including the system instruction and function declarations.
+    fn estimate_request(&self, request: &Request) -> u64 {
+        let mut tokens: u64 = request
+            .contents
+            .iter()
+            .map(|content| self.estimate_content(content))
+            .sum();
+
+        for tool in &request.tools {
+            for declaration in &tool.function_declarations {
+                let json = serde_json::to_string(declaration).unwrap_or_default();
+                tokens += self.estimate_text(&json);
+            }
+        }
+
+        #[cfg(feature = "beta")]
+        if let Some(system_instruction) = &request.system_instruction {
+            tokens += system_instruction
+                .parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .map(|text| self.estimate_text(text))
+                .sum::<u64>();
+        }
+
+        tokens
+    }
+}
+
+impl Request {
+    /// Estimates the prompt token count locally, see [`TokenEstimator`].
+    pub fn estimate_tokens(&self, estimator: &dyn TokenEstimator) -> u64 {
+        estimator.estimate_request(self)
+    }
+}
+
+impl Content {
+    /// Estimates the token count locally, see [`TokenEstimator`].
+    pub fn estimate_tokens(&self, estimator: &dyn TokenEstimator) -> u64 {
+        estimator.estimate_content(self)
+    }
+}
+
+/// Estimates tokens from the number of characters. The default of four characters per token is a reasonable average
+/// for English text; use [`HeuristicEstimator::calibrated`] to fit the ratio to your own prompts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeuristicEstimator {
+    pub chars_per_token: f64,
+}
+impl Default for HeuristicEstimator {
+    fn default() -> Self {
+        HeuristicEstimator {
+            chars_per_token: 4.0,
+        }
+    }
+}
+impl HeuristicEstimator {
+    pub fn new(chars_per_token: f64) -> Self {
+        HeuristicEstimator { chars_per_token }
+    }
+
+    /// Fits the characters-per-token ratio to text-only requests and the token counts the API returned for them.
+    /// Falls back to the default ratio if there are no usable samples.
+    pub fn calibrated(samples: &[(Request, TokenCount)]) -> Self {
+        let (chars, tokens) =
+            samples
+                .iter()
+                .fold((0u64, 0u64), |(chars, tokens), (request, count)| {
+                    let request_chars: u64 = request
+                        .contents
+                        .iter()
+                        .flat_map(|content| &content.parts)
+                        .filter_map(|part| part.text.as_deref())
+                        .map(|text| text.chars().count() as u64)
+                        .sum();
+                    (chars + request_chars, tokens + count.total_tokens)
+                });
+
+        if chars == 0 || tokens == 0 {
+            HeuristicEstimator::default()
+        } else {
+            HeuristicEstimator::new(chars as f64 / tokens as f64)
+        }
+    }
+}
+impl TokenEstimator for HeuristicEstimator {
+    fn estimate_text(&self, text: &str) -> u64 {
+        (text.chars().count() as f64 / self.chars_per_token).ceil() as u64
+    }
+}
+
+/// The SentencePiece meta symbol used in place of spaces.
+const SPACE_SYMBOL: char = '\u{2581}';
+
+/// Tokenizes text with a SentencePiece unigram model, e.g. the tokenizer model published with the Gemma models, which
+/// share their vocabulary with Gemini.
+///
+/// Text is segmented with the highest scoring sequence of pieces. Characters not in the vocabulary are counted as one
+/// token per UTF-8 byte if the model has byte fallback pieces, else as a single unknown token.
+#[derive(Debug, Clone)]
+pub struct SentencePieceEstimator {
+    pieces: HashMap<String, f32>,
+    max_piece_chars: usize,
+    unknown_score: f32,
+    byte_fallback: bool,
+    add_dummy_prefix: bool,
+}
+impl SentencePieceEstimator {
+    /// Loads a SentencePiece model from a local `.model` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, GoogleAPIError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| GoogleAPIError {
+            message: format!(
+                "Failed to read SentencePiece model {}: {}",
+                path.display(),
+                e
+            ),
+            code: None,
+            rate_limit: None,
+        })?;
+        SentencePieceEstimator::from_bytes(&bytes)
+    }
+
+    /// Loads a SentencePiece model from the bytes of a serialized `ModelProto`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, GoogleAPIError> {
+        let invalid = |what: &str| GoogleAPIError {
+            message: format!("Invalid SentencePiece model: {}", what),
+            code: None,
+            rate_limit: None,
+        };
+
+        let mut pieces = HashMap::new();
+        let mut byte_fallback = false;
+        let mut add_dummy_prefix = true;
+        let mut min_score = 0.0f32;
+
+        for (field, value) in ProtoFields::new(bytes) {
+            match (field.map_err(|_| invalid("malformed model"))?, value) {
+                // ModelProto.pieces
+                (1, ProtoValue::Bytes(piece_bytes)) => {
+                    let (mut piece, mut score, mut piece_type) = (None, 0.0, 1);
+                    for (field, value) in ProtoFields::new(piece_bytes) {
+                        match (field.map_err(|_| invalid("malformed piece"))?, value) {
+                            (1, ProtoValue::Bytes(text)) => {
+                                piece = Some(
+                                    String::from_utf8(text.to_vec())
+                                        .map_err(|_| invalid("piece is not UTF-8"))?,
+                                )
+                            }
+                            (2, ProtoValue::Fixed32(bits)) => score = f32::from_bits(bits),
+                            (3, ProtoValue::Varint(value)) => piece_type = value,
+                            _ => {}
+                        }
+                    }
+                    let piece = piece.ok_or_else(|| invalid("piece without text"))?;
+                    match piece_type {
+                        // NORMAL and USER_DEFINED pieces are used for segmentation
+                        1 | 4 => {
+                            min_score = min_score.min(score);
+                            pieces.insert(piece, score);
+                        }
+                        // BYTE
+                        6 => byte_fallback = true,
+                        _ => {}
+                    }
+                }
+                // ModelProto.normalizer_spec
+                (3, ProtoValue::Bytes(normalizer_bytes)) => {
+                    for (field, value) in ProtoFields::new(normalizer_bytes) {
+                        if let (Ok(3), ProtoValue::Varint(value)) = (field, value) {
+                            add_dummy_prefix = value != 0;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if pieces.is_empty() {
+            return Err(invalid("no pieces"));
+        }
+        let max_piece_chars = pieces.keys().map(|p| p.chars().count()).max().unwrap_or(1);
+
+        Ok(SentencePieceEstimator {
+            pieces,
+            max_piece_chars,
+            unknown_score: min_score - 10.0,
+            byte_fallback,
+            add_dummy_prefix,
+        })
+    }
+
+    /// Returns the number of pieces in the vocabulary used for segmentation.
+    pub fn vocabulary_size(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Segments the text with the highest scoring sequence of pieces and returns the number of tokens.
+    fn count_tokens(&self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        let mut normalized = String::with_capacity(text.len() + 3);
+        if self.add_dummy_prefix {
+            normalized.push(SPACE_SYMBOL);
+        }
+        normalized.extend(
+            text.chars()
+                .map(|c| if c == ' ' { SPACE_SYMBOL } else { c }),
+        );
+
+        let offsets: Vec<usize> = normalized
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(normalized.len()))
+            .collect();
+        let len = offsets.len() - 1;
+
+        // best[i] is the (score, token count) of the best segmentation of the first i characters
+        let mut best: Vec<(f32, u64)> = vec![(f32::NEG_INFINITY, 0); len + 1];
+        best[0] = (0.0, 0);
+        for end in 1..=len {
+            for start in end.saturating_sub(self.max_piece_chars)..end {
+                let (start_score, start_tokens) = best[start];
+                if start_score == f32::NEG_INFINITY {
+                    continue;
+                }
+                let candidate = &normalized[offsets[start]..offsets[end]];
+                let (score, tokens) = match self.pieces.get(candidate) {
+                    Some(score) => (*score, 1),
+                    None if end - start == 1 => {
+                        let tokens = if self.byte_fallback {
+                            candidate.len() as u64
+                        } else {
+                            1
+                        };
+                        (self.unknown_score, tokens)
+                    }
+                    None => continue,
+                };
+                let total = start_score + score;
+                if total > best[end].0 {
+                    best[end] = (total, start_tokens + tokens);
+                }
+            }
+        }
+        best[len].1
+    }
+}
+impl TokenEstimator for SentencePieceEstimator {
+    fn estimate_text(&self, text: &str) -> u64 {
+        self.count_tokens(text)
+    }
+}
+
+enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Iterates over the fields of a protobuf message as `(field number, value)`.
+/// A malformed message yields an `Err` field number and ends the iteration.
+struct ProtoFields<'a> {
+    bytes: &'a [u8],
+    failed: bool,
+}
+impl<'a> ProtoFields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ProtoFields {
+            bytes,
+            failed: false,
+        }
+    }
+
+    fn read_varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for (i, byte) in self.bytes.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.bytes = &self.bytes[i + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn read_field(&mut self) -> Option<(u64, ProtoValue<'a>)> {
+        let key = self.read_varint()?;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(self.read_varint()?),
+            1 => {
+                self.take(8)?;
+                ProtoValue::Fixed64
+            }
+            2 => {
+                let len = usize::try_from(self.read_varint()?).ok()?;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => {
+                let bytes = self.take(4)?;
+                ProtoValue::Fixed32(u32::from_le_bytes(bytes.try_into().ok()?))
+            }
+            _ => return None,
+        };
+        Some((key >> 3, value))
+    }
+}
+impl<'a> Iterator for ProtoFields<'a> {
+    type Item = (Result<u64, ()>, ProtoValue<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() || self.failed {
+            return None;
+        }
+        match self.read_field() {
+            Some((field, value)) => Some((Ok(field), value)),
+            None => {
+                self.failed = true;
+                Some((Err(()), ProtoValue::Fixed64))
+            }
+        }
+    }
+}
+
+/// How far the estimates of an estimator are from the token counts returned by the API.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EstimateErrorBounds {
+    pub samples: usize,
+    /// The mean of `|estimated - actual| / actual`.
+    pub mean_relative_error: f64,
+    /// The largest `|estimated - actual| / actual`.
+    pub max_relative_error: f64,
+    /// The smallest and largest `estimated / actual` seen.
+    pub min_ratio: f64,
+    pub max_ratio: f64,
+}
+impl EstimateErrorBounds {
+    /// Measures the estimator against requests and the token counts the API returned for them.
+    pub fn measure(estimator: &dyn TokenEstimator, samples: &[(Request, TokenCount)]) -> Self {
+        let mut bounds = EstimateErrorBounds::default();
+        for (request, count) in samples {
+            bounds.record(estimator.estimate_request(request), count);
+        }
+        bounds
+    }
+
+    /// Adds a sample, i.e. an estimate and the token count returned by the API for the same prompt.
+    pub fn record(&mut self, estimated: u64, actual: &TokenCount) {
+        if actual.total_tokens == 0 {
+            return;
+        }
+        let ratio = estimated as f64 / actual.total_tokens as f64;
+        let relative_error = (ratio - 1.0).abs();
+
+        if self.samples == 0 {
+            self.min_ratio = ratio;
+            self.max_ratio = ratio;
+        } else {
+            self.min_ratio = self.min_ratio.min(ratio);
+            self.max_ratio = self.max_ratio.max(ratio);
+        }
+        self.mean_relative_error = (self.mean_relative_error * self.samples as f64
+            + relative_error)
+            / (self.samples + 1) as f64;
+        self.max_relative_error = self.max_relative_error.max(relative_error);
+        self.samples += 1;
+    }
+
+    /// Returns the range the actual token count is expected to be in for an estimate, based on the samples seen.
+    /// Returns `None` if there are no samples.
+    pub fn actual_range(&self, estimated: u64) -> Option<(u64, u64)> {
+        if self.samples == 0 || self.min_ratio <= 0.0 {
+            return None;
+        }
+        Some((
+            (estimated as f64 / self.max_ratio).floor() as u64,
+            (estimated as f64 / self.min_ratio).ceil() as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::gemini::request::text_request;
+
+    fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn encode_bytes(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        encode_varint((field << 3) | 2, out);
+        encode_varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    /// Encodes a `ModelProto` with the given `(piece, score, type)` pieces.
+    fn encode_model(pieces: &[(&str, f32, u64)], add_dummy_prefix: bool) -> Vec<u8> {
+        let mut model = Vec::new();
+        for (piece, score, piece_type) in pieces {
+            let mut encoded = Vec::new();
+            encode_bytes(1, piece.as_bytes(), &mut encoded);
+            encode_varint((2 << 3) | 5, &mut encoded);
+            encoded.extend_from_slice(&score.to_bits().to_le_bytes());
+            encode_varint(3 << 3, &mut encoded);
+            encode_varint(*piece_type, &mut encoded);
+            encode_bytes(1, &encoded, &mut model);
+        }
+        let mut normalizer = Vec::new();
+        encode_varint(3 << 3, &mut normalizer);
+        encode_varint(add_dummy_prefix as u64, &mut normalizer);
+        encode_bytes(3, &normalizer, &mut model);
+        model
+    }
+
+    #[test]
+    fn test_heuristic_estimator() {
+        let estimator = HeuristicEstimator::default();
+        let request = text_request("Give me a recipe for banana bread.");
+
+        assert_eq!(estimator.estimate_text("abcdefgh"), 2);
+        assert_eq!(request.estimate_tokens(&estimator), 9);
+        assert_eq!(request.contents[0].estimate_tokens(&estimator), 9);
+    }
+
+    #[test]
+    fn test_heuristic_estimator_calibration_and_error_bounds() {
+        let samples = vec![
+            (
+                text_request(&"a".repeat(300)),
+                TokenCount {
+                    total_tokens: 100,
+                    ..Default::default()
+                },
+            ),
+            (
+                text_request(&"b".repeat(300)),
+                TokenCount {
+                    total_tokens: 100,
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let estimator = HeuristicEstimator::calibrated(&samples);
+        assert_eq!(estimator.chars_per_token, 3.0);
+
+        let bounds = EstimateErrorBounds::measure(&HeuristicEstimator::default(), &samples);
+        assert_eq!(bounds.samples, 2);
+        assert_eq!(bounds.max_relative_error, 0.25);
+        assert_eq!(bounds.actual_range(75), Some((100, 100)));
+        assert_eq!(EstimateErrorBounds::default().actual_range(75), None);
+    }
+
+    #[test]
+    fn test_sentencepiece_estimator_segments_with_the_best_pieces() {
+        let model = encode_model(
+            &[
+                ("<unk>", 0.0, 2),
+                ("<0x41>", 0.0, 6),
+                ("\u{2581}", -2.0, 1),
+                ("\u{2581}hello", -1.0, 1),
+                ("\u{2581}world", -1.0, 1),
+                ("h", -3.0, 1),
+                ("e", -3.0, 1),
+                ("l", -3.0, 1),
+                ("o", -3.0, 1),
+            ],
+            true,
+        );
+        let estimator = SentencePieceEstimator::from_bytes(&model).unwrap();
+
+        assert_eq!(estimator.vocabulary_size(), 7);
+        assert_eq!(estimator.estimate_text("hello world"), 2);
+        assert_eq!(estimator.estimate_text("hello hello"), 2);
+        assert_eq!(estimator.estimate_text("hello"), 1);
+        // "▁" + "h" + "e" + "l" + "l"
+        assert_eq!(estimator.estimate_text("hell"), 5);
+        // "é" is not in the vocabulary, so it falls back to its two UTF-8 bytes
+        assert_eq!(estimator.estimate_text("hello é"), 4);
+        assert_eq!(estimator.estimate_text(""), 0);
+    }
+
+    #[test]
+    fn test_sentencepiece_estimator_rejects_invalid_models() {
+        assert!(SentencePieceEstimator::from_bytes(&[]).is_err());
+        assert!(SentencePieceEstimator::from_bytes(&[0x0a, 0xff]).is_err());
+        assert!(SentencePieceEstimator::from_file("/does/not/exist.model").is_err());
+    }
+}
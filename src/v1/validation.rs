@@ -0,0 +1,678 @@
+//! Pre-flight validation of a [`Request`] against the limits of a model, so that invalid requests fail locally rather
+//! than with an HTTP 400 after a round-trip.
+use std::error::Error;
+use std::fmt;
+
+use crate::v1::errors::GoogleAPIError;
+use crate::v1::gemini::request::Request;
+use crate::v1::gemini::{ModelInformation, ResponseType, Role};
+use crate::v1::tokens::{HeuristicEstimator, TokenEstimator};
+
+/// The maximum total size of the inline data in a request, as base64 encoded bytes.
+/// Larger files need to be uploaded and referred to with [`crate::v1::gemini::request::FileData`].
+pub const MAX_INLINE_DATA_BYTES: usize = 20 * 1024 * 1024;
+
+/// The MIME types accepted for inline and file data.
+pub const SUPPORTED_MIME_TYPES: &[&str] = &[
+    // Images
+    "image/png",
+    "image/jpeg",
+    "image/webp",
+    "image/heic",
+    "image/heif",
+    // Audio
+    "audio/wav",
+    "audio/mp3",
+    "audio/mpeg",
+    "audio/aiff",
+    "audio/aac",
+    "audio/ogg",
+    "audio/flac",
+    // Video
+    "video/mp4",
+    "video/mpeg",
+    "video/mov",
+    "video/quicktime",
+    "video/avi",
+    "video/x-flv",
+    "video/mpg",
+    "video/webm",
+    "video/wmv",
+    "video/3gpp",
+    // Documents
+    "application/pdf",
+    "application/json",
+    "application/rtf",
+    "application/x-javascript",
+    "application/x-python-code",
+    "application/x-typescript",
+    "text/plain",
+    "text/html",
+    "text/css",
+    "text/csv",
+    "text/javascript",
+    "text/markdown",
+    "text/rtf",
+    "text/x-python",
+    "text/x-typescript",
+    "text/xml",
+];
+
+/// A single problem found in a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    EmptyContents,
+    EmptyParts {
+        content_index: usize,
+    },
+    /// The first content needs to be from the user.
+    FirstContentNotFromUser,
+    /// Contents need to alternate between the user and the model.
+    RoleNotAlternating {
+        content_index: usize,
+    },
+    /// A function response needs to follow a model content calling the function.
+    FunctionResponseWithoutCall {
+        content_index: usize,
+        name: String,
+    },
+    /// A function call needs to be answered by a function response in the next content.
+    FunctionCallWithoutResponse {
+        content_index: usize,
+        name: String,
+    },
+    UnsupportedMimeType {
+        content_index: usize,
+        part_index: usize,
+        mime_type: String,
+    },
+    InlineDataTooLarge {
+        bytes: usize,
+        limit: usize,
+    },
+    /// The prompt has more tokens than the model accepts. `estimated` is true if the count is a local estimate.
+    InputTokenLimitExceeded {
+        tokens: u64,
+        limit: u64,
+        estimated: bool,
+    },
+    OutputTokenLimitExceeded {
+        max_output_tokens: i32,
+        limit: i32,
+    },
+    /// A generation config value is out of range, or there are too many stop sequences.
+    InvalidGenerationConfig(String),
+    /// The model does not support the API method, e.g. `generateContent` on an embedding model.
+    UnsupportedGenerationMethod {
+        model: String,
+        method: String,
+    },
+    /// The request uses a feature not available on the API version or endpoint.
+    UnsupportedFeature {
+        feature: String,
+        api: String,
+    },
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::EmptyContents => write!(f, "the request has no contents"),
+            Violation::EmptyParts { content_index } => {
+                write!(f, "content {} has no parts", content_index)
+            }
+            Violation::FirstContentNotFromUser => {
+                write!(f, "the first content needs to have the user role")
+            }
+            Violation::RoleNotAlternating { content_index } => write!(
+                f,
+                "content {} has the same role as the previous content, roles need to alternate between user and model",
+                content_index
+            ),
+            Violation::FunctionResponseWithoutCall {
+                content_index,
+                name,
+            } => write!(
+                f,
+                "content {} has a response to function '{}', which was not called in the previous content",
+                content_index, name
+            ),
+            Violation::FunctionCallWithoutResponse {
+                content_index,
+                name,
+            } => write!(
+                f,
+                "content {} calls function '{}', which is not answered in the next content",
+                content_index, name
+            ),
+            Violation::UnsupportedMimeType {
+                content_index,
+                part_index,
+                mime_type,
+            } => write!(
+                f,
+                "content {} part {} has an unsupported MIME type '{}'",
+                content_index, part_index, mime_type
+            ),
+            Violation::InlineDataTooLarge { bytes, limit } => write!(
+                f,
+                "the inline data is {} bytes, more than the limit of {} bytes",
+                bytes, limit
+            ),
+            Violation::InputTokenLimitExceeded {
+                tokens,
+                limit,
+                estimated,
+            } => write!(
+                f,
+                "the prompt has {}{} tokens, more than the model input limit of {} tokens",
+                if *estimated { "an estimated " } else { "" },
+                tokens,
+                limit
+            ),
+            Violation::OutputTokenLimitExceeded {
+                max_output_tokens,
+                limit,
+            } => write!(
+                f,
+                "max_output_tokens is {}, more than the model output limit of {} tokens",
+                max_output_tokens, limit
+            ),
+            Violation::InvalidGenerationConfig(problem) => {
+                write!(f, "invalid generation config: {}", problem)
+            }
+            Violation::UnsupportedGenerationMethod { model, method } => {
+                write!(f, "model '{}' does not support '{}'", model, method)
+            }
+            Violation::UnsupportedFeature { feature, api } => {
+                write!(f, "'{}' is not supported on {}", feature, api)
+            }
+        }
+    }
+}
+
+/// All the problems found in a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestValidationError {
+    pub violations: Vec<Violation>,
+}
+impl fmt::Display for RequestValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid request: ")?;
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", violation)?;
+        }
+        Ok(())
+    }
+}
+impl Error for RequestValidationError {}
+impl From<RequestValidationError> for GoogleAPIError {
+    fn from(e: RequestValidationError) -> Self {
+        GoogleAPIError {
+            message: e.to_string(),
+            code: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl Request {
+    /// Validates the request against the model, returning every violation found.
+    ///
+    /// The token count of the prompt is estimated locally with the [`HeuristicEstimator`]. Use
+    /// [`Request::validate_with_estimator`] for a more accurate estimator, or [`Request::validate_with_token_count`]
+    /// to check against a count returned by the API instead.
+    pub fn validate(&self, model: &ModelInformation) -> Result<(), RequestValidationError> {
+        self.validate_with_estimator(model, &HeuristicEstimator::default())
+    }
+
+    /// Validates the request against the model, estimating the token count of the prompt with the estimator.
+    pub fn validate_with_estimator(
+        &self,
+        model: &ModelInformation,
+        estimator: &dyn TokenEstimator,
+    ) -> Result<(), RequestValidationError> {
+        self.validate_tokens(model, estimator.estimate_request(self), true)
+    }
+
+    /// Validates the request against the model, returning every violation found.
+    ///
+    /// Parameters:
+    /// * model - the model information, e.g. from [`crate::v1::api::Client::get_model`]
+    /// * total_tokens - the prompt token count, e.g. from [`crate::v1::api::Client::count_tokens`]
+    pub fn validate_with_token_count(
+        &self,
+        model: &ModelInformation,
+        total_tokens: u64,
+    ) -> Result<(), RequestValidationError> {
+        self.validate_tokens(model, total_tokens, false)
+    }
+
+    fn validate_tokens(
+        &self,
+        model: &ModelInformation,
+        tokens: u64,
+        estimated: bool,
+    ) -> Result<(), RequestValidationError> {
+        let mut violations = Vec::new();
+
+        self.check_contents(&mut violations);
+        self.check_data(&mut violations);
+        self.check_generation_config(model, &mut violations);
+        self.check_features(model, &mut violations);
+
+        if !model.supported_generation_methods.is_empty()
+            && !model.supports(&ResponseType::GenerateContent)
+        {
+            violations.push(Violation::UnsupportedGenerationMethod {
+                model: model.name.clone(),
+                method: ResponseType::GenerateContent.to_string(),
+            });
+        }
+        if model.input_token_limit > 0 && tokens > model.input_token_limit as u64 {
+            violations.push(Violation::InputTokenLimitExceeded {
+                tokens,
+                limit: model.input_token_limit as u64,
+                estimated,
+            });
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(RequestValidationError { violations })
+        }
+    }
+
+    fn check_contents(&self, violations: &mut Vec<Violation>) {
+        if self.contents.is_empty() {
+            violations.push(Violation::EmptyContents);
+            return;
+        }
+        if !matches!(self.contents[0].role, Role::User) {
+            violations.push(Violation::FirstContentNotFromUser);
+        }
+
+        for (content_index, content) in self.contents.iter().enumerate() {
+            if content.parts.is_empty() {
+                violations.push(Violation::EmptyParts { content_index });
+            }
+
+            let previous = content_index
+                .checked_sub(1)
+                .and_then(|i| self.contents.get(i));
+            if let Some(previous) = previous {
+                if std::mem::discriminant(&previous.role) == std::mem::discriminant(&content.role) {
+                    violations.push(Violation::RoleNotAlternating { content_index });
+                }
+            }
+
+            for response in content
+                .parts
+                .iter()
+                .filter_map(|p| p.function_response.as_ref())
+            {
+                let called = previous
+                    .filter(|previous| matches!(previous.role, Role::Model))
+                    .map(|previous| {
+                        previous.parts.iter().any(|part| {
+                            part.function_call
+                                .as_ref()
+                                .is_some_and(|call| call.name == response.name)
+                        })
+                    })
+                    .unwrap_or(false);
+                if !called {
+                    violations.push(Violation::FunctionResponseWithoutCall {
+                        content_index,
+                        name: response.name.clone(),
+                    });
+                }
+            }
+
+            // The last content may be a function call, that is what the model returned
+            if let Some(next) = self.contents.get(content_index + 1) {
+                for call in content
+                    .parts
+                    .iter()
+                    .filter_map(|p| p.function_call.as_ref())
+                {
+                    let answered = next.parts.iter().any(|part| {
+                        part.function_response
+                            .as_ref()
+                            .is_some_and(|response| response.name == call.name)
+                    });
+                    if !answered {
+                        violations.push(Violation::FunctionCallWithoutResponse {
+                            content_index,
+                            name: call.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_data(&self, violations: &mut Vec<Violation>) {
+        let mut inline_bytes = 0;
+
+        for (content_index, content) in self.contents.iter().enumerate() {
+            for (part_index, part) in content.parts.iter().enumerate() {
+                let mime_type = match (&part.inline_data, &part.file_data) {
+                    (Some(inline_data), _) => {
+                        inline_bytes += inline_data.data.len();
+                        &inline_data.mime_type
+                    }
+                    (None, Some(file_data)) => &file_data.mime_type,
+                    (None, None) => continue,
+                };
+                if !SUPPORTED_MIME_TYPES.contains(&mime_type.to_lowercase().as_str()) {
+                    violations.push(Violation::UnsupportedMimeType {
+                        content_index,
+                        part_index,
+                        mime_type: mime_type.clone(),
+                    });
+                }
+            }
+        }
+
+        if inline_bytes > MAX_INLINE_DATA_BYTES {
+            violations.push(Violation::InlineDataTooLarge {
+                bytes: inline_bytes,
+                limit: MAX_INLINE_DATA_BYTES,
+            });
+        }
+    }
+
+    fn check_generation_config(&self, model: &ModelInformation, violations: &mut Vec<Violation>) {
+        let generation_config = match &self.generation_config {
+            Some(generation_config) => generation_config,
+            None => return,
+        };
+
+        violations.extend(
+            generation_config
+                .get_range_violations()
+                .into_iter()
+                .map(Violation::InvalidGenerationConfig),
+        );
+        if let Some(max_output_tokens) = generation_config.max_output_tokens {
+            if model.output_token_limit > 0 && max_output_tokens > model.output_token_limit {
+                violations.push(Violation::OutputTokenLimitExceeded {
+                    max_output_tokens,
+                    limit: model.output_token_limit,
+                });
+            }
+        }
+    }
+
+    /// Checks for features that are not available on the public `v1` API, or only available on Vertex AI.
+    /// Vertex AI models are recognised by their `publishers/` resource name.
+    fn check_features(&self, model: &ModelInformation, violations: &mut Vec<Violation>) {
+        let is_vertex = model.name.starts_with("publishers/");
+        let api = if cfg!(feature = "beta") {
+            "the public v1beta API"
+        } else {
+            "the public v1 API"
+        };
+        let mut unsupported = |feature: &str| {
+            violations.push(Violation::UnsupportedFeature {
+                feature: feature.to_string(),
+                api: api.to_string(),
+            })
+        };
+
+        if is_vertex {
+            return;
+        }
+        if self.tools.iter().any(|tool| tool.retrieval.is_some()) {
+            unsupported("tools.retrieval");
+        }
+        if cfg!(feature = "beta") {
+            return;
+        }
+
+        // Only available on v1beta
+        for (feature, used) in [
+            (
+                "tools.googleSearch",
+                self.tools.iter().any(|tool| tool.google_search.is_some()),
+            ),
+            (
+                "tools.googleSearchRetrieval",
+                self.tools
+                    .iter()
+                    .any(|tool| tool.google_search_retrieval.is_some()),
+            ),
+            (
+                "tools.codeExecution",
+                self.tools.iter().any(|tool| tool.code_execution.is_some()),
+            ),
+        ] {
+            if used {
+                unsupported(feature);
+            }
+        }
+        if let Some(generation_config) = &self.generation_config {
+            for (feature, used) in [
+                (
+                    "generationConfig.responseModalities",
+                    generation_config.response_modalities.is_some(),
+                ),
+                (
+                    "generationConfig.speechConfig",
+                    generation_config.speech_config.is_some(),
+                ),
+                (
+                    "generationConfig.thinkingConfig",
+                    generation_config.thinking_config.is_some(),
+                ),
+                (
+                    "generationConfig.mediaResolution",
+                    generation_config.media_resolution.is_some(),
+                ),
+            ] {
+                if used {
+                    unsupported(feature);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::gemini::request::{
+        FunctionCall, FunctionResponse, GenerationConfig, InlineData, Tools,
+    };
+    use crate::v1::gemini::{Content, Part};
+
+    fn model() -> ModelInformation {
+        ModelInformation {
+            name: "models/gemini-1.5-flash".to_string(),
+            input_token_limit: 100,
+            output_token_limit: 50,
+            supported_generation_methods: vec!["generateContent".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn content(role: Role, part: Part) -> Content {
+        Content {
+            role,
+            parts: vec![part],
+        }
+    }
+
+    fn text(text: &str) -> Part {
+        Part {
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn request(contents: Vec<Content>) -> Request {
+        Request::new(contents, vec![], vec![], None)
+    }
+
+    #[test]
+    fn test_valid_request() {
+        let request = request(vec![
+            content(Role::User, text("Hello")),
+            content(Role::Model, text("Hi")),
+            content(Role::User, text("How are you?")),
+        ]);
+
+        assert_eq!(request.validate(&model()), Ok(()));
+    }
+
+    #[test]
+    fn test_all_violations_are_returned() {
+        let mut request = request(vec![
+            content(Role::Model, text("Hi")),
+            content(Role::Model, text(&"a".repeat(1000))),
+            Content {
+                role: Role::User,
+                parts: vec![],
+            },
+        ]);
+        request.contents[1].parts.push(Part {
+            inline_data: Some(InlineData {
+                mime_type: "image/bmp".to_string(),
+                data: "Qk0=".to_string(),
+            }),
+            ..Default::default()
+        });
+        request.generation_config = Some(GenerationConfig {
+            temperature: Some(3.0),
+            max_output_tokens: Some(100),
+            stop_sequences: Some(vec!["x".to_string(); 6]),
+            ..Default::default()
+        });
+
+        let violations = request.validate(&model()).unwrap_err().violations;
+
+        assert_eq!(
+            violations,
+            vec![
+                Violation::FirstContentNotFromUser,
+                Violation::RoleNotAlternating { content_index: 1 },
+                Violation::EmptyParts { content_index: 2 },
+                Violation::UnsupportedMimeType {
+                    content_index: 1,
+                    part_index: 1,
+                    mime_type: "image/bmp".to_string()
+                },
+                Violation::InvalidGenerationConfig(
+                    "temperature must be in [0.0, 2.0], got 3".to_string()
+                ),
+                Violation::InvalidGenerationConfig(
+                    "stop_sequences must have at most 5 entries, got 6".to_string()
+                ),
+                Violation::OutputTokenLimitExceeded {
+                    max_output_tokens: 100,
+                    limit: 50
+                },
+                Violation::InputTokenLimitExceeded {
+                    tokens: 509,
+                    limit: 100,
+                    estimated: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_response_ordering() {
+        let call = |name: &str| Part {
+            function_call: Some(FunctionCall {
+                id: None,
+                name: name.to_string(),
+                args: serde_json::json!({}),
+            }),
+            ..Default::default()
+        };
+        let response = |name: &str| Part {
+            function_response: Some(FunctionResponse {
+                id: None,
+                name: name.to_string(),
+                response: serde_json::json!({}),
+            }),
+            ..Default::default()
+        };
+        let request = request(vec![
+            content(Role::User, text("What's the weather?")),
+            content(Role::Model, call("get_weather")),
+            content(Role::User, response("get_time")),
+        ]);
+
+        let violations = request.validate(&model()).unwrap_err().violations;
+
+        assert_eq!(
+            violations,
+            vec![
+                Violation::FunctionCallWithoutResponse {
+                    content_index: 1,
+                    name: "get_weather".to_string()
+                },
+                Violation::FunctionResponseWithoutCall {
+                    content_index: 2,
+                    name: "get_time".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_count_and_model_method() {
+        let embedding_model = ModelInformation {
+            name: "models/text-embedding-004".to_string(),
+            input_token_limit: 2048,
+            supported_generation_methods: vec!["embedContent".to_string()],
+            ..Default::default()
+        };
+        let request = request(vec![content(Role::User, text("Hello"))]);
+
+        let error = request
+            .validate_with_token_count(&embedding_model, 4096)
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Invalid request: model 'models/text-embedding-004' does not support 'generateContent'; \
+             the prompt has 4096 tokens, more than the model input limit of 2048 tokens"
+        );
+        assert!(GoogleAPIError::from(error)
+            .message
+            .starts_with("Invalid request"));
+    }
+
+    #[test]
+    fn test_feature_compatibility() {
+        let mut request = request(vec![content(Role::User, text("Hello"))]);
+        request.tools = vec![Tools::code_execution(), Tools::vertex_rag_store(vec![])];
+        let vertex_model = ModelInformation {
+            name: "publishers/google/models/gemini-1.5-flash".to_string(),
+            ..Default::default()
+        };
+
+        let violations = request.validate(&model()).unwrap_err().violations;
+
+        assert_eq!(
+            violations[0],
+            Violation::UnsupportedFeature {
+                feature: "tools.retrieval".to_string(),
+                api: if cfg!(feature = "beta") {
+                    "the public v1beta API".to_string()
+                } else {
+                    "the public v1 API".to_string()
+                }
+            }
+        );
+        assert_eq!(violations.len(), if cfg!(feature = "beta") { 1 } else { 2 });
+        assert_eq!(request.validate(&vertex_model), Ok(()));
+    }
+}
@@ -1,21 +1,103 @@
 //! Contains logic and types specific to the Vertex AI endpoint (opposed to the public Gemini API endpoint)
+use serde::{Deserialize, Serialize};
 use std::{fmt, sync::Arc};
 
 use super::{
     api::{Client, Url},
-    gemini::{Model, ResponseType},
+    gemini::{
+        request::{EmbedContentRequest, Request, TaskType},
+        response::{ComputedTokens, ContentEmbedding},
+        Content, Model, ModelInformation, ModelInformationList, ResponseType,
+    },
 };
+use crate::v1::auth::GcpAuthProvider;
 use crate::v1::errors::GoogleAPIError;
+use crate::v1::operation::{deserialize_int64, OperationPoller, Status};
+use crate::v1::telemetry::{self, ContentCapture};
 
-const VERTEX_AI_API_URL_BASE: &str = "https://{region}-aiplatform.googleapis.com/v1";
+const VERTEX_AI_API_HOST: &str = "https://{region}-aiplatform.googleapis.com";
 
-const GCP_API_AUTH_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+/// The `global` location is served from a host without a region prefix.
+const VERTEX_AI_GLOBAL_API_HOST: &str = "https://aiplatform.googleapis.com";
+const GLOBAL_LOCATION: &str = "global";
+
+const DEFAULT_PUBLISHER: &str = "google";
+
+/// The version of the Vertex AI API to call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VertexApiVersion {
+    #[default]
+    V1,
+    V1Beta1,
+}
+impl fmt::Display for VertexApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VertexApiVersion::V1 => f.write_str("v1"),
+            VertexApiVersion::V1Beta1 => f.write_str("v1beta1"),
+        }
+    }
+}
+
+/// How Vertex AI resources are addressed, beyond the location and project.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VertexOptions {
+    pub(crate) api_version: VertexApiVersion,
+    /// The publisher of models given by ID, e.g. `google`.
+    pub(crate) publisher: String,
+}
+impl Default for VertexOptions {
+    fn default() -> Self {
+        VertexOptions {
+            api_version: VertexApiVersion::default(),
+            publisher: DEFAULT_PUBLISHER.to_string(),
+        }
+    }
+}
+impl VertexOptions {
+    /// Returns the base URL for the location, e.g. `https://us-central1-aiplatform.googleapis.com/v1`, or
+    /// `https://aiplatform.googleapis.com/v1` for the `global` location.
+    pub(crate) fn base_url(location: &str, api_version: VertexApiVersion) -> String {
+        let host = if location == GLOBAL_LOCATION {
+            VERTEX_AI_GLOBAL_API_HOST.to_string()
+        } else {
+            VERTEX_AI_API_HOST.replace("{region}", location)
+        };
+        format!("{}/{}", host, api_version)
+    }
+
+    /// Returns the resource name of the model within the project and location.
+    ///
+    /// The model can be:
+    /// * a full resource name, e.g. `projects/{project}/locations/{location}/endpoints/{endpoint}`, used as is
+    /// * a deployed endpoint, e.g. `endpoints/{endpoint}` for a tuned model
+    /// * a publisher model, e.g. `publishers/{publisher}/models/{model}`
+    /// * a model ID, e.g. `gemini-1.5-flash`, of the configured publisher
+    pub(crate) fn resource_name(&self, model: &Model, location: &str, project_id: &str) -> String {
+        let name = model.to_string();
+        let parent = format!("projects/{}/locations/{}", project_id, location);
+        if name.starts_with("projects/") {
+            name
+        } else if name.starts_with("endpoints/") || name.starts_with("publishers/") {
+            format!("{}/{}", parent, name)
+        } else {
+            format!(
+                "{}/publishers/{}/models/{}",
+                parent,
+                self.publisher,
+                model.id()
+            )
+        }
+    }
+}
 
 impl Client {
     /// Create a new private API client (Vertex AI) using the default model, `Gemini-pro`.
+    /// Both streamed and non-streamed responses are supported, see [`Client::generate_content`] and
+    /// [`Client::stream_generate_content`]. The deprecated [`Client::post`] streams the response.
     ///
     /// Parameters:
-    /// * region - the GCP region to use
+    /// * region - the GCP region to use, or `global`
     /// * project_id - the GCP account project_id to use
     pub fn new_from_region_project_id(region: String, project_id: String) -> Self {
         Client::new_from_region_project_id_response_type(
@@ -29,118 +111,704 @@ impl Client {
         project_id: String,
         response_type: ResponseType,
     ) -> Self {
-        let url = Url::new_from_region_project_id(
-            &Model::default(),
-            region.clone(),
-            project_id.clone(),
-            &response_type,
-        );
         Self {
-            url: url.url,
             model: Model::default(),
             region: Some(region),
             project_id: Some(project_id),
             response_type,
+            credentials: Arc::new(GcpAuthProvider::new()),
+            base_url: None,
+            vertex_options: VertexOptions::default(),
+            cassette: None,
+            middleware: Vec::new(),
+            content_capture: ContentCapture::default(),
+            rate_limiter: None,
         }
     }
-    /// Create a new private API client.
+    /// Create a new private API client. The deprecated [`Client::post`] streams the response.
     /// Parameters:
     /// * model - the Gemini model to use
-    /// * region - the GCP region to use
+    /// * region - the GCP region to use, or `global`
     /// * project_id - the GCP account project_id to use
     pub fn new_from_model_region_project_id(
         model: Model,
         region: String,
         project_id: String,
     ) -> Self {
-        let url = Url::new_from_region_project_id(
-            &model,
-            region.clone(),
-            project_id.clone(),
-            &ResponseType::StreamGenerateContent,
-        );
         Self {
-            url: url.url,
             model,
             region: Some(region),
             project_id: Some(project_id),
             response_type: ResponseType::StreamGenerateContent,
+            credentials: Arc::new(GcpAuthProvider::new()),
+            base_url: None,
+            vertex_options: VertexOptions::default(),
+            cassette: None,
+            middleware: Vec::new(),
+            content_capture: ContentCapture::default(),
+            rate_limiter: None,
         }
     }
 
-    /// If this is a Vertex AI request, get the token from the GCP authn library, if it is correctly configured, else None.
-    pub(crate) async fn get_auth_token_option(&self) -> Result<Option<String>, GoogleAPIError> {
-        let token_option = if self.project_id.is_some() && self.region.is_some() {
-            let token = self.get_gcp_authn_token().await?.as_str().to_string();
-            Some(token)
+    /// Sets the version of the Vertex AI API to call, [`VertexApiVersion::V1`] by default.
+    /// A base URL override includes its own version, which is used instead, see [`Client::with_base_url`].
+    pub fn with_vertex_api_version(mut self, api_version: VertexApiVersion) -> Self {
+        self.vertex_options.api_version = api_version;
+        self
+    }
+
+    /// Sets the publisher of the model on Vertex AI, `google` by default, e.g. to call a partner model by its ID.
+    /// Models given as `publishers/{publisher}/models/{model}` use their own publisher.
+    pub fn with_publisher(mut self, publisher: impl Into<String>) -> Self {
+        self.vertex_options.publisher = publisher.into();
+        self
+    }
+
+    /// Returns true if the client is for the Vertex AI endpoint, i.e. it has a region and project_id.
+    pub fn is_vertex(&self) -> bool {
+        self.project_id.is_some() && self.region.is_some()
+    }
+
+    /// Gets a single page of the models of the publisher, `google` by default, available on Vertex AI.
+    pub(crate) async fn get_publisher_model_list_page(
+        &self,
+        timeout: u64,
+        page_size: Option<u32>,
+        page_token: Option<String>,
+    ) -> Result<ModelInformationList, GoogleAPIError> {
+        // Listing publisher models is only available on `v1beta1`
+        let url = format!(
+            "{}/publishers/{}/models",
+            self.publisher_models_base_url(),
+            self.vertex_options.publisher
+        );
+        let mut query = Vec::new();
+        if let Some(page_size) = page_size {
+            query.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
+
+        let result = self.get(timeout, &url, &query).await?;
+        let list: PublisherModelList = self.deserialize_response(result).await?;
+
+        Ok(ModelInformationList {
+            models: list
+                .publisher_models
+                .into_iter()
+                .map(ModelInformation::from)
+                .collect(),
+            next_page_token: list.next_page_token,
+        })
+    }
+
+    /// Gets the publisher model, e.g. `publishers/google/models/gemini-1.5-flash`.
+    pub(crate) async fn get_publisher_model(
+        &self,
+        timeout: u64,
+    ) -> Result<ModelInformation, GoogleAPIError> {
+        let name = self.model.to_string();
+        let name = if name.starts_with("publishers/") {
+            name
+        } else if name.contains('/') {
+            return Err(GoogleAPIError {
+                message: format!("{} is not a publisher model", name),
+                code: None,
+                rate_limit: None,
+            });
         } else {
-            None
+            format!(
+                "publishers/{}/models/{}",
+                self.vertex_options.publisher, name
+            )
         };
-        Ok(token_option)
-    }
-    /// Gets a GCP authn token.
-    async fn get_gcp_authn_token(&self) -> Result<Arc<gcp_auth::Token>, GoogleAPIError> {
-        let provider = gcp_auth::provider().await.map_err(|e| GoogleAPIError {
-            message: format!("Failed to create AuthenticationManager: {}", e),
-            code: None,
-        })?;
-        let scopes = &[GCP_API_AUTH_SCOPE];
-        let token = provider.token(scopes).await.map_err(|e| GoogleAPIError {
-            message: format!("Failed to generate authentication token: {}", e),
-            code: None,
-        })?;
-        Ok(token)
+
+        // Getting publisher models is only available on `v1beta1`
+        let url = format!("{}/{}", self.publisher_models_base_url(), name);
+        let result = self.get(timeout, &url, &[]).await?;
+        let publisher_model: PublisherModel = self.deserialize_response(result).await?;
+        Ok(ModelInformation::from(publisher_model))
+    }
+
+    /// Creates a batch prediction job, which runs the requests of a JSON Lines file or BigQuery table on the
+    /// client's model, see: "https://cloud.google.com/vertex-ai/generative-ai/docs/multimodal/batch-prediction-gemini".
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * display_name - a name for the job
+    /// * input_config - where the requests are, see [`BatchPredictionRequest::to_jsonl`]
+    /// * output_config - where to write the responses
+    pub async fn create_batch_prediction_job(
+        &self,
+        timeout: u64,
+        display_name: &str,
+        input_config: BatchPredictionInputConfig,
+        output_config: BatchPredictionOutputConfig,
+    ) -> Result<BatchPredictionJob, GoogleAPIError> {
+        let (region, project_id) = self.vertex_location("Batch prediction jobs")?;
+        let body = CreateBatchPredictionJob {
+            display_name,
+            model: self
+                .vertex_options
+                .resource_name(&self.model, region, project_id),
+            input_config,
+            output_config,
+        };
+        let url = self.batch_prediction_jobs_url(region, project_id);
+        let result = self.post_json(timeout, &url, &body).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Gets the latest state of a batch prediction job.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the job, e.g. `projects/{project}/locations/{location}/batchPredictionJobs/{id}`
+    pub async fn get_batch_prediction_job(
+        &self,
+        timeout: u64,
+        name: &str,
+    ) -> Result<BatchPredictionJob, GoogleAPIError> {
+        self.vertex_location("Batch prediction jobs")?;
+        let url = format!("{}/{}", self.api_base_url(), name);
+        let result = self.get(timeout, &url, &[]).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Polls a batch prediction job until its state is final, see [`JobState::is_terminal`].
+    /// Parameters:
+    /// * timeout - the timeout in seconds of each poll
+    /// * name - the resource name of the job
+    /// * poller - when to poll, and for how long
+    pub async fn wait_for_batch_prediction_job(
+        &self,
+        timeout: u64,
+        name: &str,
+        poller: &OperationPoller,
+    ) -> Result<BatchPredictionJob, GoogleAPIError> {
+        poller
+            .poll_until(
+                || self.get_batch_prediction_job(timeout, name),
+                |job| job.state.is_terminal(),
+            )
+            .await
+    }
+
+    /// Lists a page of the batch prediction jobs of the project and location.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * page_size - the maximum number of jobs to return
+    /// * page_token - the `next_page_token` of the previous page, if any
+    pub async fn list_batch_prediction_jobs(
+        &self,
+        timeout: u64,
+        page_size: Option<u32>,
+        page_token: Option<String>,
+    ) -> Result<BatchPredictionJobList, GoogleAPIError> {
+        let (region, project_id) = self.vertex_location("Batch prediction jobs")?;
+        let mut query = Vec::new();
+        if let Some(page_size) = page_size {
+            query.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
+        let url = self.batch_prediction_jobs_url(region, project_id);
+        let result = self.get(timeout, &url, &query).await?;
+        self.deserialize_response(result).await
+    }
+
+    /// Cancels a batch prediction job. The job is `JOB_STATE_CANCELLING` until it is cancelled.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * name - the resource name of the job
+    pub async fn cancel_batch_prediction_job(
+        &self,
+        timeout: u64,
+        name: &str,
+    ) -> Result<(), GoogleAPIError> {
+        self.vertex_location("Batch prediction jobs")?;
+        let url = format!("{}/{}:cancel", self.api_base_url(), name);
+        let result = self
+            .post_json(timeout, &url, &serde_json::json!({}))
+            .await?;
+        let _: serde_json::Value = self.deserialize_response(result).await?;
+        Ok(())
+    }
+
+    fn batch_prediction_jobs_url(&self, region: &str, project_id: &str) -> String {
+        format!(
+            "{}/projects/{}/locations/{}/batchPredictionJobs",
+            self.api_base_url(),
+            project_id,
+            region
+        )
+    }
+
+    /// Returns the region and project ID of a Vertex AI client, or an error naming the unsupported feature.
+    fn vertex_location(&self, feature: &str) -> Result<(&str, &str), GoogleAPIError> {
+        match (&self.region, &self.project_id) {
+            (Some(region), Some(project_id)) => Ok((region, project_id)),
+            _ => Err(GoogleAPIError {
+                message: format!("{} are only available on Vertex AI", feature),
+                code: None,
+                rate_limit: None,
+            }),
+        }
+    }
+
+    /// Returns the `v1beta1` base URL. A base URL override ending with an API version, e.g. `http://localhost:8080/v1`,
+    /// has its version replaced, and any other override is used as is.
+    fn publisher_models_base_url(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => match base_url.rsplit_once('/') {
+                Some((root, version))
+                    if version == VertexApiVersion::V1.to_string()
+                        || version == VertexApiVersion::V1Beta1.to_string() =>
+                {
+                    format!("{}/{}", root, VertexApiVersion::V1Beta1)
+                }
+                _ => base_url.clone(),
+            },
+            None => VertexOptions::base_url(
+                self.region.as_deref().unwrap_or_default(),
+                VertexApiVersion::V1Beta1,
+            ),
+        }
+    }
+
+    /// Embeds the contents with the `predict` method of a Vertex AI embedding model, e.g. `text-embedding-004`.
+    ///
+    /// The text parts of each content are embedded. The output dimensionality of the first request that sets one is
+    /// used for all of them.
+    pub(crate) async fn predict_embeddings(
+        &self,
+        timeout: u64,
+        embed_requests: &[EmbedContentRequest],
+    ) -> Result<Vec<ContentEmbedding>, GoogleAPIError> {
+        let url = self.operation_url(&ResponseType::Predict);
+        let result = self
+            .post_json(
+                timeout,
+                &url,
+                &PredictEmbeddingsRequest::from(embed_requests),
+            )
+            .await?;
+        let response: PredictEmbeddingsResponse = self.deserialize_response(result).await?;
+        Ok(response
+            .predictions
+            .into_iter()
+            .map(|prediction| prediction.embeddings)
+            .collect())
+    }
+
+    /// Computes the tokens of the request contents, returning the token IDs and tokens of each content.
+    /// Only available on Vertex AI; use [`Client::count_tokens`] for the public API.
+    /// Parameters:
+    /// * timeout - the timeout in seconds
+    /// * api_request - the request to compute the tokens of
+    pub async fn compute_tokens(
+        &self,
+        timeout: u64,
+        api_request: &Request,
+    ) -> Result<ComputedTokens, GoogleAPIError> {
+        let span = telemetry::operation_span(self, "compute_tokens");
+        telemetry::traced(&span, async {
+            if !self.is_vertex() {
+                return Err(GoogleAPIError {
+                    message: "computeTokens is only available on Vertex AI".to_string(),
+                    code: None,
+                    rate_limit: None,
+                });
+            }
+
+            let url = self.operation_url(&ResponseType::ComputeTokens);
+            let body = ComputeTokensRequest {
+                contents: &api_request.contents,
+            };
+            let result = self.post_json(timeout, &url, &body).await?;
+            self.deserialize_response(result).await
+        })
+        .await
     }
 }
+
 /// Ensuring there is no leakage of secrets
 impl fmt::Display for Client {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.region.is_some() && self.project_id.is_some() {
-            write!(
-                f,
-                "GenerativeAiClient {{ url: {:?}, model: {:?}, region: {:?}, project_id: {:?} }}",
-                self.url, self.model, self.region, self.project_id
-            )
+        write!(
+            f,
+            "GenerativeAiClient {{ url: {:?}, model: {:?}, region: {:?}, project_id: {:?} }}",
+            self.operation_url(&self.response_type),
+            self.model,
+            self.region,
+            self.project_id
+        )
+    }
+}
+
+/// The Vertex AI `computeTokens` body.
+#[derive(Serialize)]
+struct ComputeTokensRequest<'a> {
+    contents: &'a [Content],
+}
+
+/// The Vertex AI `predict` body for embedding models.
+#[derive(Debug, Serialize)]
+struct PredictEmbeddingsRequest<'a> {
+    instances: Vec<EmbeddingInstance<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<EmbeddingParameters>,
+}
+#[derive(Debug, Serialize)]
+struct EmbeddingInstance<'a> {
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_type: Option<&'a TaskType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EmbeddingParameters {
+    output_dimensionality: u32,
+}
+impl<'a> From<&'a [EmbedContentRequest]> for PredictEmbeddingsRequest<'a> {
+    fn from(embed_requests: &'a [EmbedContentRequest]) -> Self {
+        PredictEmbeddingsRequest {
+            instances: embed_requests
+                .iter()
+                .map(|request| EmbeddingInstance {
+                    content: request
+                        .content
+                        .parts
+                        .iter()
+                        .filter_map(|part| part.text.as_deref())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    task_type: request.task_type.as_ref(),
+                    title: request.title.as_deref(),
+                })
+                .collect(),
+            parameters: embed_requests
+                .iter()
+                .find_map(|request| request.output_dimensionality)
+                .map(|output_dimensionality| EmbeddingParameters {
+                    output_dimensionality,
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PredictEmbeddingsResponse {
+    #[serde(default)]
+    predictions: Vec<EmbeddingPrediction>,
+}
+#[derive(Debug, Deserialize)]
+struct EmbeddingPrediction {
+    embeddings: ContentEmbedding,
+}
+
+/// A page of the Google publisher models available on Vertex AI.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherModelList {
+    #[serde(default)]
+    pub publisher_models: Vec<PublisherModel>,
+    #[serde(default)]
+    pub next_page_token: Option<String>,
+}
+/// A model published on Vertex AI, e.g. `publishers/google/models/gemini-1.5-flash`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherModel {
+    pub name: String,
+    #[serde(default)]
+    pub version_id: String,
+    pub launch_stage: Option<String>,
+    pub open_source_category: Option<String>,
+}
+/// Vertex AI does not report the supported methods of publisher models, so they are inferred from the model name.
+impl From<PublisherModel> for ModelInformation {
+    fn from(publisher_model: PublisherModel) -> Self {
+        let id = Model::from(publisher_model.name.as_str()).id();
+        let supported_generation_methods = if id.starts_with("gemini") {
+            vec![
+                "generateContent".to_string(),
+                "countTokens".to_string(),
+                "computeTokens".to_string(),
+            ]
+        } else if id.contains("embedding") {
+            vec!["embedContent".to_string()]
         } else {
-            write!(
-                f,
-                "GenerativeAiClient {{ url: {:?}, model: {:?}, region: {:?}, project_id: {:?} }}",
-                Url::new(
-                    &self.model,
-                    "*************".to_string(),
-                    &self.response_type
+            vec![]
+        };
+
+        ModelInformation {
+            display_name: id,
+            name: publisher_model.name,
+            version: publisher_model.version_id,
+            supported_generation_methods,
+            ..Default::default()
+        }
+    }
+}
+
+/// A request of a batch prediction job, i.e. a line of its JSON Lines input file, or a row of its BigQuery table.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchPredictionRequest {
+    pub request: Request,
+}
+impl BatchPredictionRequest {
+    /// Returns the requests as the JSON Lines of a batch prediction input file, one `{"request": {...}}` per line,
+    /// to upload to Cloud Storage.
+    pub fn to_jsonl(requests: &[Request]) -> Result<String, GoogleAPIError> {
+        let mut jsonl = String::new();
+        for (index, request) in requests.iter().enumerate() {
+            let line = serde_json::to_string(&BatchPredictionRequest {
+                request: request.clone(),
+            })
+            .map_err(|e| GoogleAPIError {
+                message: format!(
+                    "Failed to serialize batch prediction request {}: {}",
+                    index, e
                 ),
-                self.model,
-                self.region,
-                self.project_id
-            )
+                code: None,
+                rate_limit: None,
+            })?;
+            jsonl.push_str(&line);
+            jsonl.push('\n');
         }
+        Ok(jsonl)
     }
 }
 
+/// Where the requests of a batch prediction job are read from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPredictionInputConfig {
+    /// `jsonl` or `bigquery`.
+    pub instances_format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gcs_source: Option<GcsSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bigquery_source: Option<BigQuerySource>,
+}
+impl BatchPredictionInputConfig {
+    /// Reads the requests from JSON Lines files, e.g. `gs://bucket/requests.jsonl`.
+    pub fn gcs(uris: Vec<String>) -> Self {
+        BatchPredictionInputConfig {
+            instances_format: "jsonl".to_string(),
+            gcs_source: Some(GcsSource { uris }),
+            bigquery_source: None,
+        }
+    }
+
+    /// Reads the requests from the `request` column of a table, e.g. `bq://project.dataset.table`.
+    pub fn bigquery(input_uri: impl Into<String>) -> Self {
+        BatchPredictionInputConfig {
+            instances_format: "bigquery".to_string(),
+            gcs_source: None,
+            bigquery_source: Some(BigQuerySource {
+                input_uri: input_uri.into(),
+            }),
+        }
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GcsSource {
+    pub uris: Vec<String>,
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BigQuerySource {
+    pub input_uri: String,
+}
+
+/// Where the responses of a batch prediction job are written to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPredictionOutputConfig {
+    /// `jsonl` or `bigquery`.
+    pub predictions_format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gcs_destination: Option<GcsDestination>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bigquery_destination: Option<BigQueryDestination>,
+}
+impl BatchPredictionOutputConfig {
+    /// Writes the responses as JSON Lines files under a prefix, e.g. `gs://bucket/responses/`.
+    pub fn gcs(output_uri_prefix: impl Into<String>) -> Self {
+        BatchPredictionOutputConfig {
+            predictions_format: "jsonl".to_string(),
+            gcs_destination: Some(GcsDestination {
+                output_uri_prefix: output_uri_prefix.into(),
+            }),
+            bigquery_destination: None,
+        }
+    }
+
+    /// Writes the responses to a new table in a dataset, e.g. `bq://project.dataset`, or to a given table.
+    pub fn bigquery(output_uri: impl Into<String>) -> Self {
+        BatchPredictionOutputConfig {
+            predictions_format: "bigquery".to_string(),
+            gcs_destination: None,
+            bigquery_destination: Some(BigQueryDestination {
+                output_uri: output_uri.into(),
+            }),
+        }
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcsDestination {
+    pub output_uri_prefix: String,
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BigQueryDestination {
+    pub output_uri: String,
+}
+
+/// The state of a Vertex AI job.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JobState {
+    #[default]
+    #[serde(rename = "JOB_STATE_UNSPECIFIED")]
+    Unspecified,
+    #[serde(rename = "JOB_STATE_QUEUED")]
+    Queued,
+    #[serde(rename = "JOB_STATE_PENDING")]
+    Pending,
+    #[serde(rename = "JOB_STATE_RUNNING")]
+    Running,
+    #[serde(rename = "JOB_STATE_SUCCEEDED")]
+    Succeeded,
+    #[serde(rename = "JOB_STATE_FAILED")]
+    Failed,
+    #[serde(rename = "JOB_STATE_CANCELLING")]
+    Cancelling,
+    #[serde(rename = "JOB_STATE_CANCELLED")]
+    Cancelled,
+    #[serde(rename = "JOB_STATE_PAUSED")]
+    Paused,
+    #[serde(rename = "JOB_STATE_EXPIRED")]
+    Expired,
+    #[serde(rename = "JOB_STATE_UPDATING")]
+    Updating,
+    #[serde(rename = "JOB_STATE_PARTIALLY_SUCCEEDED")]
+    PartiallySucceeded,
+}
+impl JobState {
+    /// Returns true if the job will not change state anymore.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded
+                | JobState::Failed
+                | JobState::Cancelled
+                | JobState::Expired
+                | JobState::PartiallySucceeded
+        )
+    }
+}
+
+/// A Vertex AI batch prediction job.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPredictionJob {
+    /// The resource name, e.g. `projects/{project}/locations/{location}/batchPredictionJobs/{id}`.
+    pub name: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub model: String,
+    pub input_config: Option<BatchPredictionInputConfig>,
+    pub output_config: Option<BatchPredictionOutputConfig>,
+    #[serde(default)]
+    pub state: JobState,
+    /// Set if the job failed.
+    pub error: Option<Status>,
+    pub output_info: Option<BatchPredictionOutputInfo>,
+    pub completion_stats: Option<CompletionStats>,
+    pub create_time: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub update_time: Option<String>,
+}
+
+/// Where the responses of a batch prediction job were written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPredictionOutputInfo {
+    pub gcs_output_directory: Option<String>,
+    pub bigquery_output_dataset: Option<String>,
+    pub bigquery_output_table: Option<String>,
+}
+
+/// The number of requests of a batch prediction job, by outcome.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionStats {
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub successful_count: i64,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub failed_count: i64,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub incomplete_count: i64,
+}
+
+/// A page of batch prediction jobs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPredictionJobList {
+    #[serde(default)]
+    pub batch_prediction_jobs: Vec<BatchPredictionJob>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateBatchPredictionJob<'a> {
+    display_name: &'a str,
+    model: String,
+    input_config: BatchPredictionInputConfig,
+    output_config: BatchPredictionOutputConfig,
+}
+
 impl Url {
-    pub(crate) fn new_from_region_project_id(
+    /// Parameters:
+    /// * base_url - overrides the base URL of the location, see [`Client::with_base_url`]
+    pub(crate) fn new_from_vertex_options(
+        base_url: Option<&str>,
+        options: &VertexOptions,
         model: &Model,
-        region: String,
-        project_id: String,
+        location: &str,
+        project_id: &str,
         response_type: &ResponseType,
     ) -> Self {
-        let base_url = VERTEX_AI_API_URL_BASE
-            .to_owned()
-            .replace("{region}", &region);
-
+        let base_url = match base_url {
+            Some(base_url) => base_url.to_string(),
+            None => VertexOptions::base_url(location, options.api_version),
+        };
         let url = format!(
-            "{}/projects/{}/locations/{}/publishers/google/models/{}:{}",
-            base_url, project_id, region, model, response_type,
+            "{}/{}:{}",
+            base_url,
+            options.resource_name(model, location, project_id),
+            response_type,
         );
         Self { url }
     }
 }
+
 #[cfg(test)]
 mod tests {
     use crate::v1::{
         api::{Client, Url},
-        gemini::{Model, ResponseType},
+        gemini::{
+            request::{text_request, GenerationConfig},
+            Model, ResponseType,
+        },
     };
 
     use super::*;
@@ -171,27 +839,329 @@ mod tests {
         assert_eq!(client.project_id, Some(project_id));
     }
 
+    #[test]
+    fn test_publisher_model_into_model_information() {
+        let list: PublisherModelList = serde_json::from_value(serde_json::json!({
+            "publisherModels": [
+                { "name": "publishers/google/models/gemini-1.5-flash", "versionId": "002", "launchStage": "GA" },
+                { "name": "publishers/google/models/text-embedding-005", "versionId": "001" },
+                { "name": "publishers/google/models/imagen-3.0-generate-001" }
+            ],
+            "nextPageToken": "next"
+        }))
+        .unwrap();
+
+        let models: Vec<ModelInformation> = list
+            .publisher_models
+            .into_iter()
+            .map(ModelInformation::from)
+            .collect();
+
+        assert_eq!(models[0].version, "002");
+        assert!(models[0].supports(&ResponseType::StreamGenerateContent));
+        assert!(models[1].supports(&ResponseType::EmbedContent));
+        assert!(!models[2].supports(&ResponseType::GenerateContent));
+        assert_eq!(list.next_page_token.as_deref(), Some("next"));
+    }
+
+    #[tokio::test]
+    async fn test_publisher_models_use_v1beta1_with_a_base_url_override() {
+        use crate::v1::auth::StaticTokenProvider;
+        use crate::v1::mock_server::{MockResponse, MockServer};
+
+        let server = MockServer::start().await;
+        server.enqueue_route(
+            "GET",
+            "/publishers/google/models/gemini-1.5-flash",
+            MockResponse::json(
+                200,
+                serde_json::json!({"name": "publishers/google/models/gemini-1.5-flash", "versionId": "002"}),
+            ),
+        );
+        let client = Client::new_from_model_region_project_id(
+            Model::Gemini1_5Flash,
+            "us-central1".to_string(),
+            "my-project".to_string(),
+        )
+        .with_base_url(server.base_url())
+        .with_credential_provider(StaticTokenProvider::new("test-token"));
+
+        let model = client.get_publisher_model(5).await.unwrap();
+
+        assert_eq!(model.version, "002");
+        assert_eq!(
+            server.requests()[0].path,
+            "/v1beta1/publishers/google/models/gemini-1.5-flash"
+        );
+        let proxied = client.with_base_url("https://proxy.example.com/vertex/");
+        assert_eq!(
+            proxied.publisher_models_base_url(),
+            "https://proxy.example.com/vertex"
+        );
+    }
+
     #[test]
     fn test_url_new_from_region_project_id() {
         let model = Model::default();
         let region = String::from("us-central1");
         let project_id = String::from("my-project");
-        let url = Url::new_from_region_project_id(
+        let url = Url::new_from_vertex_options(
+            None,
+            &VertexOptions::default(),
             &model,
-            region.clone(),
-            project_id.clone(),
+            &region,
+            &project_id,
             &ResponseType::StreamGenerateContent,
         );
 
         assert_eq!(
             url.url,
             format!(
-                "{}/projects/{}/locations/{}/publishers/google/models/{}:streamGenerateContent",
-                VERTEX_AI_API_URL_BASE.replace("{region}", &region),
+                "https://{}-aiplatform.googleapis.com/v1/projects/{}/locations/{}/publishers/google/models/{}:streamGenerateContent",
+                region,
                 project_id,
                 region,
                 model
             )
         );
     }
+
+    #[test]
+    fn test_vertex_resource_path_shapes() {
+        let url = |client: &Client| client.operation_url(&ResponseType::GenerateContent);
+        let client = |model: &str, location: &str| {
+            Client::new_from_model_region_project_id(
+                Model::from(model),
+                location.to_string(),
+                "my-project".to_string(),
+            )
+        };
+
+        assert_eq!(
+            url(&client("gemini-2.0-flash", "global")),
+            "https://aiplatform.googleapis.com/v1/projects/my-project/locations/global/publishers/google/models/gemini-2.0-flash:generateContent"
+        );
+        assert_eq!(
+            url(&client("gemini-2.0-flash", "europe-west4")
+                .with_vertex_api_version(VertexApiVersion::V1Beta1)),
+            "https://europe-west4-aiplatform.googleapis.com/v1beta1/projects/my-project/locations/europe-west4/publishers/google/models/gemini-2.0-flash:generateContent"
+        );
+        assert_eq!(
+            url(&client("publishers/mistralai/models/mistral-large", "us-central1")),
+            "https://us-central1-aiplatform.googleapis.com/v1/projects/my-project/locations/us-central1/publishers/mistralai/models/mistral-large:generateContent"
+        );
+        assert_eq!(
+            url(&client("mistral-large", "us-central1").with_publisher("mistralai")),
+            "https://us-central1-aiplatform.googleapis.com/v1/projects/my-project/locations/us-central1/publishers/mistralai/models/mistral-large:generateContent"
+        );
+        assert_eq!(
+            url(&client("endpoints/1234567890", "us-central1")),
+            "https://us-central1-aiplatform.googleapis.com/v1/projects/my-project/locations/us-central1/endpoints/1234567890:generateContent"
+        );
+        assert_eq!(
+            url(&client(
+                "projects/other-project/locations/us-east4/endpoints/1234567890",
+                "us-east4"
+            )),
+            "https://us-east4-aiplatform.googleapis.com/v1/projects/other-project/locations/us-east4/endpoints/1234567890:generateContent"
+        );
+        assert_eq!(
+            url(&client("endpoints/1234567890", "us-central1")
+                .with_base_url("https://my-endpoint.example.com/v1")),
+            "https://my-endpoint.example.com/v1/projects/my-project/locations/us-central1/endpoints/1234567890:generateContent"
+        );
+        // The version of the override wins over the API version
+        assert_eq!(
+            url(&client("gemini-2.0-flash", "us-central1")
+                .with_vertex_api_version(VertexApiVersion::V1Beta1)
+                .with_base_url("https://proxy.example.com/v1")),
+            "https://proxy.example.com/v1/projects/my-project/locations/us-central1/publishers/google/models/gemini-2.0-flash:generateContent"
+        );
+    }
+
+    #[test]
+    fn test_predict_embeddings_request_and_response() {
+        let mut query = EmbedContentRequest::new("What is the capital of France?");
+        query.task_type = Some(TaskType::RetrievalQuery);
+        query.output_dimensionality = Some(256);
+        let mut document = EmbedContentRequest::new("Paris is the capital of France.");
+        document.title = Some("France".to_string());
+        let requests = [query, document];
+
+        let body = serde_json::to_value(PredictEmbeddingsRequest::from(&requests[..])).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "instances": [
+                    {"content": "What is the capital of France?", "task_type": "RETRIEVAL_QUERY"},
+                    {"content": "Paris is the capital of France.", "title": "France"}
+                ],
+                "parameters": {"outputDimensionality": 256}
+            })
+        );
+
+        let response: PredictEmbeddingsResponse = serde_json::from_value(serde_json::json!({
+            "predictions": [
+                {"embeddings": {"values": [0.1, 0.2], "statistics": {"token_count": 8, "truncated": false}}},
+                {"embeddings": {"values": [0.3, 0.4], "statistics": {"token_count": 7, "truncated": false}}}
+            ],
+            "metadata": {"billableCharacterCount": 50}
+        }))
+        .unwrap();
+        assert_eq!(response.predictions[1].embeddings.values, vec![0.3, 0.4]);
+
+        let client = Client::new_from_model_region_project_id(
+            Model::TextEmbedding004,
+            "us-central1".to_string(),
+            "my-project".to_string(),
+        );
+        assert_eq!(
+            client.operation_url(&ResponseType::Predict),
+            "https://us-central1-aiplatform.googleapis.com/v1/projects/my-project/locations/us-central1/publishers/google/models/text-embedding-004:predict"
+        );
+        // The deprecated `post` keeps streaming on Vertex AI
+        assert_eq!(client.response_type, ResponseType::StreamGenerateContent);
+        assert_eq!(
+            Client::new_from_region_project_id("global".to_string(), "my-project".to_string())
+                .response_type,
+            ResponseType::StreamGenerateContent
+        );
+    }
+
+    #[test]
+    fn test_batch_prediction_requests_to_jsonl() {
+        let requests: Vec<Request> = ["Hello", "Bonjour"]
+            .iter()
+            .map(|text| Request {
+                generation_config: Some(GenerationConfig {
+                    temperature: Some(0.2),
+                    ..Default::default()
+                }),
+                ..text_request(text)
+            })
+            .collect();
+
+        let jsonl = BatchPredictionRequest::to_jsonl(&requests).unwrap();
+
+        let lines: Vec<serde_json::Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1]["request"]["contents"][0]["parts"][0]["text"],
+            "Bonjour"
+        );
+        assert_eq!(lines[0]["request"]["generationConfig"]["temperature"], 0.2);
+        assert_eq!(lines[0].as_object().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_prediction_job_lifecycle_against_a_mock_endpoint() {
+        use crate::v1::auth::StaticTokenProvider;
+        use crate::v1::mock_server::{MockResponse, MockServer};
+        use std::time::Duration;
+
+        let name = "projects/my-project/locations/us-central1/batchPredictionJobs/1";
+        let job = |state: &str| {
+            serde_json::json!({
+                "name": name,
+                "displayName": "evaluation",
+                "model": "projects/my-project/locations/us-central1/publishers/google/models/gemini-1.5-flash",
+                "inputConfig": {"instancesFormat": "bigquery", "bigquerySource": {"inputUri": "bq://my-project.evals.requests"}},
+                "outputConfig": {"predictionsFormat": "bigquery", "bigqueryDestination": {"outputUri": "bq://my-project.evals"}},
+                "state": state
+            })
+        };
+        let mut succeeded = job("JOB_STATE_SUCCEEDED");
+        succeeded["outputInfo"] = serde_json::json!({"bigqueryOutputTable": "predictions_1", "bigqueryOutputDataset": "bq://my-project.evals"});
+        succeeded["completionStats"] =
+            serde_json::json!({"successfulCount": "9", "failedCount": "1"});
+        let server = MockServer::start().await;
+        server
+            .enqueue_route("POST", "/batchPredictionJobs", MockResponse::json(200, job("JOB_STATE_PENDING")))
+            .enqueue_route("GET", "/batchPredictionJobs/1", MockResponse::json(200, job("JOB_STATE_RUNNING")))
+            .enqueue_route("GET", "/batchPredictionJobs/1", MockResponse::json(200, succeeded))
+            .enqueue_route(
+                "GET",
+                "/batchPredictionJobs",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"batchPredictionJobs": [job("JOB_STATE_CANCELLING")], "nextPageToken": "page-2"}),
+                ),
+            )
+            .enqueue_route("POST", "/batchPredictionJobs/1:cancel", MockResponse::json(200, serde_json::json!({})));
+        let client = Client::new_from_model_region_project_id(
+            Model::Gemini1_5Flash,
+            "us-central1".to_string(),
+            "my-project".to_string(),
+        )
+        .with_base_url(server.base_url())
+        .with_credential_provider(StaticTokenProvider::new("test-token"));
+        let poller = OperationPoller {
+            initial_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let created = client
+            .create_batch_prediction_job(
+                5,
+                "evaluation",
+                BatchPredictionInputConfig::bigquery("bq://my-project.evals.requests"),
+                BatchPredictionOutputConfig::bigquery("bq://my-project.evals"),
+            )
+            .await
+            .unwrap();
+        let done = client
+            .wait_for_batch_prediction_job(5, &created.name, &poller)
+            .await
+            .unwrap();
+        let list = client
+            .list_batch_prediction_jobs(5, Some(1), None)
+            .await
+            .unwrap();
+        client.cancel_batch_prediction_job(5, name).await.unwrap();
+
+        assert_eq!(created.state, JobState::Pending);
+        assert_eq!(done.state, JobState::Succeeded);
+        assert_eq!(done.completion_stats.unwrap().failed_count, 1);
+        assert_eq!(
+            done.output_info.unwrap().bigquery_output_table.as_deref(),
+            Some("predictions_1")
+        );
+        assert_eq!(list.batch_prediction_jobs[0].state, JobState::Cancelling);
+        assert_eq!(list.next_page_token.as_deref(), Some("page-2"));
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].path,
+            "/v1/projects/my-project/locations/us-central1/batchPredictionJobs"
+        );
+        assert_eq!(requests[0].headers["authorization"], "Bearer test-token");
+        let body = requests[0].json();
+        assert_eq!(
+            body["model"],
+            "projects/my-project/locations/us-central1/publishers/google/models/gemini-1.5-flash"
+        );
+        assert_eq!(
+            body["inputConfig"]["bigquerySource"]["inputUri"],
+            "bq://my-project.evals.requests"
+        );
+        assert!(body["inputConfig"].get("gcsSource").is_none());
+        assert_eq!(requests[1].path, format!("/v1/{}", name));
+        assert_eq!(requests[3].query["pageSize"], "1");
+        assert_eq!(requests[4].path, format!("/v1/{}:cancel", name));
+
+        let gcs =
+            serde_json::to_value(BatchPredictionOutputConfig::gcs("gs://bucket/out/")).unwrap();
+        assert_eq!(
+            gcs,
+            serde_json::json!({"predictionsFormat": "jsonl", "gcsDestination": {"outputUriPrefix": "gs://bucket/out/"}})
+        );
+        let public = Client::new("test-api-key".to_string());
+        assert!(public
+            .list_batch_prediction_jobs(5, None, None)
+            .await
+            .is_err());
+    }
 }